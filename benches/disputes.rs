@@ -0,0 +1,122 @@
+//! Dispute throughput of a client with many deposits.
+//!
+//! `lookup/hash_map` is the deposit lookup as it was before `DepositMap`, `lookup/deposit_map` the
+//! current one. `processor` measures disputes and resolves end to end.
+//!
+//! Run with `cargo bench --bench disputes`.
+
+use std::{collections::HashMap, hint::black_box};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rust_decimal_macros::dec;
+use toy_stream_processor::{
+    deposit_map::DepositMap,
+    ids::{ClientID, TransactionID},
+    processor::{Processor, ProcessorConfig},
+    transaction::{Deposit, Dispute, Resolve, Transaction},
+};
+
+const DEPOSITS: [u32; 3] = [100, 10_000, 1_000_000];
+
+/// Stand-in for a deposit, the size of the value matters for how cache-friendly the map is.
+#[derive(Clone, Copy, Default)]
+struct Value {
+    disputed: bool,
+    _payload: [u64; 6],
+}
+
+/// Pseudo-random order of the disputed tx ids, disputes don't follow the deposit order.
+fn dispute_order(deposits: u32) -> Vec<TransactionID> {
+    (0..deposits)
+        .map(|i| TransactionID::new((i.wrapping_mul(2_654_435_761) % deposits) + 1))
+        .collect()
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    for deposits in DEPOSITS {
+        let order = dispute_order(deposits);
+        group.throughput(Throughput::Elements(deposits.into()));
+
+        let mut hash_map: HashMap<_, _> = (1..=deposits)
+            .map(|tx| (TransactionID::new(tx), Value::default()))
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("hash_map", deposits),
+            &order,
+            |b, order| {
+                b.iter(|| {
+                    for tx in order {
+                        let value = hash_map.get_mut(tx).unwrap();
+                        value.disputed = !value.disputed;
+                    }
+                })
+            },
+        );
+
+        let mut deposit_map: DepositMap<_> = (1..=deposits)
+            .map(|tx| (TransactionID::new(tx), Value::default()))
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("deposit_map", deposits),
+            &order,
+            |b, order| {
+                b.iter(|| {
+                    for tx in order {
+                        let value = deposit_map.get_mut(tx).unwrap();
+                        value.disputed = !value.disputed;
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn processor(c: &mut Criterion) {
+    let client = ClientID::new(1);
+    let mut group = c.benchmark_group("processor");
+    for deposits in DEPOSITS {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for tx in 1..=deposits {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    client,
+                    tx: TransactionID::new(tx),
+                    amount: dec!(1.0).try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+
+        let transactions: Vec<_> = dispute_order(deposits)
+            .into_iter()
+            .flat_map(|tx| {
+                [
+                    Transaction::Dispute(Dispute {
+                        client,
+                        tx,
+                        amount: None,
+                        reason: None,
+                    }),
+                    Transaction::Resolve(Resolve { client, tx }),
+                ]
+            })
+            .collect();
+        group.throughput(Throughput::Elements(transactions.len() as u64));
+        group.bench_function(BenchmarkId::new("dispute_resolve", deposits), |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| {
+                    for transaction in transactions {
+                        black_box(processor.handle_transaction(transaction)).unwrap();
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup, processor);
+criterion_main!(benches);
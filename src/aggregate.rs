@@ -0,0 +1,121 @@
+use std::{collections::BTreeMap, num::NonZeroU32};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::processor::ProcessorStatusEntry;
+
+/// Summed balances over a group of clients.
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct Aggregate {
+    pub clients: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: usize,
+}
+
+impl Aggregate {
+    /// Adds the balances of a single client to the aggregate.
+    pub fn add(&mut self, entry: &ProcessorStatusEntry) {
+        self.clients += 1;
+        self.available += entry.available;
+        self.held += entry.held;
+        self.total += entry.total;
+        self.locked += usize::from(entry.locked);
+    }
+}
+
+/// Summed balances of the clients with an id in `range_start..=range_end`.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct RangeBucketEntry {
+    pub range_start: u32,
+    pub range_end: u32,
+    pub clients: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: usize,
+}
+
+/// Groups clients into id ranges of `size` ids (e.g. 0-999, 1000-1999) and sums up their balances.
+///
+/// Returns one entry per non-empty range, sorted by range.
+pub fn group_by_range<I>(entries: I, size: NonZeroU32) -> Vec<RangeBucketEntry>
+where
+    I: IntoIterator<Item = ProcessorStatusEntry>,
+{
+    let mut buckets: BTreeMap<u32, Aggregate> = BTreeMap::new();
+    for entry in entries {
+        let range_start = u32::from(u16::from(entry.client)) / size * size.get();
+        buckets.entry(range_start).or_default().add(&entry);
+    }
+
+    buckets
+        .into_iter()
+        .map(|(range_start, aggregate)| RangeBucketEntry {
+            range_start,
+            range_end: range_start + (size.get() - 1),
+            clients: aggregate.clients,
+            available: aggregate.available,
+            held: aggregate.held,
+            total: aggregate.total,
+            locked: aggregate.locked,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::ids::ClientID;
+
+    use super::*;
+
+    fn entry(client: u16, available: Decimal, held: Decimal, locked: bool) -> ProcessorStatusEntry {
+        ProcessorStatusEntry::new(
+            ClientID::new(client),
+            available,
+            held,
+            available + held,
+            locked,
+        )
+    }
+
+    #[test]
+    fn test_group_by_range() {
+        let entries = vec![
+            entry(1500, dec!(5), dec!(0), false),
+            entry(3, dec!(1.5), dec!(2), false),
+            entry(999, dec!(10), dec!(0), true),
+            entry(1000, dec!(0.25), dec!(1), true),
+        ];
+
+        let buckets = group_by_range(entries, NonZeroU32::new(1000).unwrap());
+
+        assert_eq!(
+            buckets,
+            vec![
+                RangeBucketEntry {
+                    range_start: 0,
+                    range_end: 999,
+                    clients: 2,
+                    available: dec!(11.5),
+                    held: dec!(2),
+                    total: dec!(13.5),
+                    locked: 1,
+                },
+                RangeBucketEntry {
+                    range_start: 1000,
+                    range_end: 1999,
+                    clients: 2,
+                    available: dec!(5.25),
+                    held: dec!(1),
+                    total: dec!(6.25),
+                    locked: 1,
+                },
+            ]
+        );
+    }
+}
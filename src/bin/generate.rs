@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use toy_stream_processor::generator::{self, GeneratorConfig};
+
+#[derive(Debug, Parser)]
+/// Generates a synthetic transaction stream as CSV on stdout, e.g. for benchmarks and fuzzing
+struct Args {
+    /// Number of transactions to generate
+    #[arg(long, default_value_t = 1_000_000)]
+    count: u32,
+
+    /// Number of distinct clients
+    #[arg(long, default_value_t = 1_000)]
+    clients: u16,
+
+    /// Seed for the random number generator, the same seed always produces the same output
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // Without an explicit seed every run is different, print the seed to be able to reproduce it
+    let seed = args.seed.unwrap_or_else(rand::random);
+    eprintln!("seed: {seed}");
+
+    let config = GeneratorConfig {
+        transactions: args.count,
+        clients: args.clients,
+        seed,
+    };
+    generator::generate(std::io::stdout().lock(), &config).context("generate transactions")?;
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::{aggregate::Aggregate, processor::Processor};
+
+/// Summary of a successful run for compliance, written by `--certificate`.
+///
+/// Ties the row counts of the run to its final state: the summed balances of all clients and a
+/// hash over all client states, see `Processor::state_hash`. It isn't signed, the hash only tells
+/// whether two runs ended in the same state.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct Certificate {
+    /// Number of transactions applied to the client accounts.
+    pub processed: usize,
+    /// Number of invalid rows skipped by the reader.
+    pub skipped: usize,
+    /// Number of valid transactions rejected by the processor.
+    pub rejected: usize,
+    /// Summed balances of all clients, at full precision.
+    pub balances: Aggregate,
+    pub state_hash: String,
+}
+
+impl Certificate {
+    pub fn new(processed: usize, skipped: usize, rejected: usize, processor: &Processor) -> Self {
+        let mut balances = Aggregate::default();
+        for entry in processor.status_entries() {
+            balances.add(&entry);
+        }
+
+        Self {
+            processed,
+            skipped,
+            rejected,
+            balances,
+            state_hash: processor.state_hash(),
+        }
+    }
+
+    /// Writes the certificate as pretty-printed JSON.
+    pub fn write_json<W: io::Write>(&self, writer: W) -> Result<(), serde_json::Error> {
+        serde_json::to_writer_pretty(writer, self)
+    }
+}
@@ -1,39 +1,184 @@
-use std::collections::HashMap;
-
 use rust_decimal::Decimal;
 use thiserror::Error;
+use tracing::warn;
 
-use crate::{ids::TransactionID, positive_decimal::PositiveDecimal};
+use crate::{
+    deposit_map::DepositMap,
+    ids::{ClientID, TransactionID},
+    positive_decimal::PositiveDecimal,
+    snapshot::{ClientSnapshot, DepositSnapshot},
+    transaction::Transaction,
+};
 
 /// A client account that tracks balances and processes transactions.
+///
+/// Balances keep the full precision of the amounts, they are only rounded once when written, see
+/// `OutputConfig::scale`. They are sums and differences of amounts, which never have more decimal
+/// places than the most precise amount, so their precision can't grow on its own. Rounding every
+/// amount to the 4 places of the output would drift instead, e.g. a stream of `0.00004` deposits
+/// would never add up to anything.
 #[derive(Debug)]
 pub struct Client {
     total: Decimal,
     held: Decimal,
+    /// Held funds released by the `release` admin operation while their deposits are still disputed.
+    released: Decimal,
+    /// Sum of all withdrawals, only used to audit `total`.
+    withdrawn: Decimal,
     locked: bool,
-    deposits: HashMap<TransactionID, Deposit>,
+    /// The chargeback that locked the account.
+    locked_by: Option<TransactionID>,
+    /// Largest applied deposit, for risk reporting.
+    max_deposit: Option<PositiveDecimal>,
+    /// Largest applied withdrawal, for risk reporting.
+    max_withdrawal: Option<PositiveDecimal>,
+    /// Deposits by tx id, looked up by every dispute, resolve and chargeback.
+    deposits: DepositMap<Deposit>,
+    /// While `apply_atomically` runs, the deposits changed so far as they were before, `None` for
+    /// the ones that didn't exist.
+    undo: Option<Vec<(TransactionID, Option<Deposit>)>>,
+    config: ClientConfig,
 }
 
-#[derive(Debug)]
+/// Options controlling how a `Client` processes transactions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientConfig {
+    /// Allow withdrawals to use held funds, meant for privileged corrections only.
+    pub allow_withdraw_held: bool,
+    /// Allow administrative operations like `release`.
+    pub allow_admin_ops: bool,
+    /// Disputes with an amount only hold that part of the deposit, see `partial_dispute`.
+    pub partial_disputes: bool,
+}
+
+/// An operation on the account of a single client, i.e. a transaction without the client id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientOp {
+    Deposit {
+        tx: TransactionID,
+        amount: PositiveDecimal,
+    },
+    Withdrawal {
+        tx: TransactionID,
+        amount: PositiveDecimal,
+    },
+    /// A partial dispute if it has an amount and partial disputes are enabled.
+    Dispute {
+        tx: TransactionID,
+        amount: Option<PositiveDecimal>,
+        reason: Option<String>,
+    },
+    Resolve {
+        tx: TransactionID,
+    },
+    Chargeback {
+        tx: TransactionID,
+    },
+    Release {
+        amount: PositiveDecimal,
+    },
+}
+
+impl ClientOp {
+    /// Converts a transaction to the operation on its client's account. `None` for an `open`,
+    /// which is handled by the `Processor` since it's about whether the account exists.
+    pub fn from_transaction(transaction: Transaction) -> Option<Self> {
+        Some(match transaction {
+            Transaction::Deposit(deposit) => ClientOp::Deposit {
+                tx: deposit.tx,
+                amount: deposit.amount,
+            },
+            Transaction::Withdrawal(withdrawal) => ClientOp::Withdrawal {
+                tx: withdrawal.tx,
+                amount: withdrawal.amount,
+            },
+            Transaction::Dispute(dispute) => ClientOp::Dispute {
+                tx: dispute.tx,
+                amount: dispute.amount,
+                reason: dispute.reason,
+            },
+            Transaction::Resolve(resolve) => ClientOp::Resolve { tx: resolve.tx },
+            Transaction::Chargeback(chargeback) => ClientOp::Chargeback { tx: chargeback.tx },
+            Transaction::Release(release) => ClientOp::Release {
+                amount: release.amount,
+            },
+            Transaction::Open(_) => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
 struct Deposit {
     amount: PositiveDecimal,
     disputed: bool,
+    charged_back: bool,
+    /// Whether the deposit was ever disputed, also after the dispute is resolved.
+    was_disputed: bool,
+    /// Part of the amount held by the dispute, less than the amount for partial disputes.
+    /// Zero when not disputed, and the amount charged back after a chargeback.
+    held: Decimal,
+    /// Reason code of the most recent dispute, kept after it's resolved for auditing.
+    reason: Option<String>,
 }
 
-#[derive(Debug, Error)]
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
 pub enum ProcessingError {
     #[error("account is locked, no transactions allowed")]
     AccountLocked,
+    #[error("account is locked, deposit rejected")]
+    DepositToLocked,
+    #[error("account is locked, withdrawal rejected")]
+    WithdrawalFromLocked,
     #[error("duplicate transaction id")]
     DuplicateTransactionID,
     #[error("insufficient funds")]
     InsufficientFunds,
-    #[error("deposit not found")]
+    #[error("deposit not found for this client")]
     DepositNotFound,
     #[error("deposit already disputed")]
     AlreadyDisputed,
-    #[error("deposit not disputed")]
+    #[error("partial disputes exceed the deposit amount")]
+    OverDispute,
+    #[error("deposit exists but isn't disputed")]
     NotDisputed,
+    #[error("administrative operations are not allowed")]
+    AdminOpsNotAllowed,
+    #[error("release exceeds the held funds")]
+    InsufficientHeldFunds,
+    #[error("duplicate idempotency key")]
+    DuplicateIdempotencyKey,
+    #[error("client isn't on the roster")]
+    ClientNotOnRoster,
+    #[error("client has no open account")]
+    AccountNotOpen,
+    #[error("client account is already open")]
+    AccountAlreadyOpen,
+    #[error("transaction exceeded the processing timeout")]
+    TimedOut,
+}
+
+impl ProcessingError {
+    /// Returns a short snake case name of the error, used to count errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProcessingError::AccountLocked => "account_locked",
+            ProcessingError::DepositToLocked => "deposit_to_locked",
+            ProcessingError::WithdrawalFromLocked => "withdrawal_from_locked",
+            ProcessingError::DuplicateTransactionID => "duplicate_transaction_id",
+            ProcessingError::InsufficientFunds => "insufficient_funds",
+            ProcessingError::DepositNotFound => "deposit_not_found",
+            ProcessingError::AlreadyDisputed => "already_disputed",
+            ProcessingError::OverDispute => "over_dispute",
+            ProcessingError::NotDisputed => "not_disputed",
+            ProcessingError::AdminOpsNotAllowed => "admin_ops_not_allowed",
+            ProcessingError::InsufficientHeldFunds => "insufficient_held_funds",
+            ProcessingError::DuplicateIdempotencyKey => "duplicate_idempotency_key",
+            ProcessingError::ClientNotOnRoster => "client_not_on_roster",
+            ProcessingError::AccountNotOpen => "account_not_open",
+            ProcessingError::AccountAlreadyOpen => "account_already_open",
+            ProcessingError::TimedOut => "timed_out",
+        }
+    }
 }
 
 impl Client {
@@ -41,11 +186,23 @@ impl Client {
     ///
     /// This method initializes a new client account with a total balance, held balance, and locked status all set to zero.
     pub fn new() -> Self {
+        Self::with_config(ClientConfig::default())
+    }
+
+    /// Creates a new client account with zero balance, using the given config.
+    pub fn with_config(config: ClientConfig) -> Self {
         Self {
             total: Decimal::ZERO,
             held: Decimal::ZERO,
+            released: Decimal::ZERO,
+            withdrawn: Decimal::ZERO,
             locked: false,
-            deposits: HashMap::new(),
+            locked_by: None,
+            max_deposit: None,
+            max_withdrawal: None,
+            deposits: DepositMap::new(),
+            undo: None,
+            config,
         }
     }
 
@@ -53,6 +210,109 @@ impl Client {
         self.total - self.held
     }
 
+    /// Returns whether a deposit with the given transaction id has been processed.
+    pub fn has_deposit(&self, tx: TransactionID) -> bool {
+        self.deposits.contains_key(&tx)
+    }
+
+    /// Returns the highest transaction id of the kept deposits.
+    pub fn last_deposit_tx(&self) -> Option<TransactionID> {
+        self.deposits.iter().map(|(tx, _)| *tx).max()
+    }
+
+    /// Held funds released by the `release` admin operation while their deposits are still disputed.
+    pub fn released(&self) -> Decimal {
+        self.released
+    }
+
+    /// Returns the sum of the disputed amounts of all currently disputed deposits.
+    ///
+    /// For a consistent account this is always equal to `held + released`.
+    pub fn disputed_total(&self) -> Decimal {
+        self.deposits
+            .values()
+            .filter(|deposit| deposit.disputed)
+            .map(|deposit| deposit.held)
+            .sum()
+    }
+
+    /// Returns the reason code of the most recent dispute of a deposit, if one was given.
+    pub fn dispute_reason(&self, tx: TransactionID) -> Option<&str> {
+        self.deposits.get(&tx)?.reason.as_deref()
+    }
+
+    /// Returns whether a deposit was disputed at least once, `false` if there's no such deposit.
+    pub fn was_disputed(&self, tx: TransactionID) -> bool {
+        self.deposits
+            .get(&tx)
+            .is_some_and(|deposit| deposit.was_disputed)
+    }
+
+    /// Returns the number of deposits that were disputed at least once, resolved or not.
+    pub fn disputed_deposit_count(&self) -> usize {
+        self.deposits
+            .values()
+            .filter(|deposit| deposit.was_disputed)
+            .count()
+    }
+
+    /// Returns the number of deposits kept to be able to dispute them.
+    pub fn deposit_count(&self) -> usize {
+        self.deposits.len()
+    }
+
+    /// Returns the ids of all currently disputed deposits, in no particular order.
+    pub fn disputed_tx_ids(&self) -> impl Iterator<Item = TransactionID> + '_ {
+        self.deposits
+            .iter()
+            .filter(|(_, deposit)| deposit.disputed)
+            .map(|(tx, _)| *tx)
+    }
+
+    /// Returns the ids of the deposits a dispute would currently succeed for, sorted by id.
+    ///
+    /// These are the deposits that are neither disputed nor charged back, none if the account is
+    /// locked.
+    pub fn eligible_dispute_tx_ids(&self) -> Vec<TransactionID> {
+        if self.locked {
+            return Vec::new();
+        }
+
+        let mut txs: Vec<_> = self
+            .deposits
+            .iter()
+            .filter(|(_, deposit)| !deposit.disputed && !deposit.charged_back)
+            .map(|(tx, _)| *tx)
+            .collect();
+        txs.sort();
+
+        txs
+    }
+
+    /// Recomputes the total balance from the stored operations: the deposits minus the amounts
+    /// charged back, minus all withdrawals.
+    ///
+    /// For a consistent account this is always equal to `total`.
+    pub fn audited_total(&self) -> Decimal {
+        let deposited: Decimal = self
+            .deposits
+            .values()
+            .map(|deposit| match deposit.charged_back {
+                true => Decimal::from(deposit.amount) - deposit.held,
+                false => Decimal::from(deposit.amount),
+            })
+            .sum();
+
+        deposited - self.withdrawn
+    }
+
+    /// Same as `available`, but returns `None` instead of panicking if the subtraction overflows.
+    ///
+    /// This can't happen as long as the balances are kept consistent, it's meant for consistency checks.
+    pub fn checked_available(&self) -> Option<Decimal> {
+        self.total.checked_sub(self.held)
+    }
+
     pub fn held(&self) -> Decimal {
         self.held
     }
@@ -65,10 +325,111 @@ impl Client {
         self.locked
     }
 
-    /// Checks if the account is locked and returns an error if it is.
-    fn ensure_not_locked(&self) -> Result<(), ProcessingError> {
+    /// Returns the tx id of the chargeback that locked the account, `None` if it isn't locked.
+    pub fn locked_by(&self) -> Option<TransactionID> {
+        self.locked_by
+    }
+
+    /// Returns the largest deposit applied to the account, even if it was charged back since.
+    pub fn max_deposit(&self) -> Option<Decimal> {
+        self.max_deposit.map(Decimal::from)
+    }
+
+    /// Returns the largest withdrawal applied to the account.
+    pub fn max_withdrawal(&self) -> Option<Decimal> {
+        self.max_withdrawal.map(Decimal::from)
+    }
+
+    /// Captures the complete state of the account, deposits are sorted by transaction id.
+    pub fn snapshot(&self, client: ClientID) -> ClientSnapshot {
+        let mut deposits = self
+            .deposits
+            .iter()
+            .map(|(tx, deposit)| DepositSnapshot {
+                tx: *tx,
+                amount: deposit.amount,
+                disputed: deposit.disputed,
+                charged_back: deposit.charged_back,
+                was_disputed: deposit.was_disputed,
+                held: (deposit.held != Decimal::ZERO && deposit.held != deposit.amount.into())
+                    .then_some(deposit.held),
+                reason: deposit.reason.clone(),
+            })
+            .collect::<Vec<_>>();
+        deposits.sort_by_key(|deposit| deposit.tx);
+
+        ClientSnapshot {
+            client,
+            total: self.total,
+            held: self.held,
+            released: self.released,
+            withdrawn: self.withdrawn,
+            locked: self.locked,
+            locked_by: self.locked_by,
+            max_deposit: self.max_deposit,
+            max_withdrawal: self.max_withdrawal,
+            deposits,
+        }
+    }
+
+    /// Restores an account from a snapshot, the snapshot is assumed to be consistent.
+    pub fn restore(snapshot: &ClientSnapshot, config: ClientConfig) -> Self {
+        let deposits = snapshot
+            .deposits
+            .iter()
+            .map(|deposit| {
+                (
+                    deposit.tx,
+                    Deposit {
+                        amount: deposit.amount,
+                        disputed: deposit.disputed,
+                        charged_back: deposit.charged_back,
+                        // older snapshots only know about the current state
+                        was_disputed: deposit.was_disputed
+                            || deposit.disputed
+                            || deposit.charged_back,
+                        held: match (deposit.held, deposit.disputed || deposit.charged_back) {
+                            (Some(held), _) => held,
+                            (None, true) => deposit.amount.into(),
+                            (None, false) => Decimal::ZERO,
+                        },
+                        reason: deposit.reason.clone(),
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            total: snapshot.total,
+            held: snapshot.held,
+            released: snapshot.released,
+            withdrawn: snapshot.withdrawn,
+            locked: snapshot.locked,
+            locked_by: snapshot.locked_by,
+            // older snapshots don't have it, but the deposits are all kept
+            max_deposit: snapshot
+                .max_deposit
+                .or_else(|| snapshot.deposits.iter().map(|deposit| deposit.amount).max()),
+            max_withdrawal: snapshot.max_withdrawal,
+            deposits,
+            undo: None,
+            config,
+        }
+    }
+
+    /// Checks the balance invariants that must hold after every operation, in debug builds only.
+    pub fn debug_assert_invariants(&self) {
+        debug_assert!(!self.held.is_sign_negative(), "held balance is negative");
+        debug_assert!(
+            self.checked_available().is_some(),
+            "available balance overflowed"
+        );
+    }
+
+    /// Checks if the account is locked and returns the given error if it is.
+    fn ensure_not_locked(&self, error: ProcessingError) -> Result<(), ProcessingError> {
         match self.locked {
-            true => Err(ProcessingError::AccountLocked),
+            true => Err(error),
             false => Ok(()),
         }
     }
@@ -76,7 +437,7 @@ impl Client {
     /// Processes a deposit transaction, adding funds to the account.
     ///
     /// # Errors
-    /// - `AccountLocked`: Account is locked and cannot process transactions
+    /// - `DepositToLocked`: Account is locked and cannot process transactions
     /// - `DuplicateTransactionID`: Transaction ID already exists
     pub fn deposit(
         &mut self,
@@ -84,7 +445,7 @@ impl Client {
         amount: PositiveDecimal,
     ) -> Result<(), ProcessingError> {
         // ensure not locked
-        self.ensure_not_locked()?;
+        self.ensure_not_locked(ProcessingError::DepositToLocked)?;
 
         // verify that the transaction id is unique
         if self.deposits.contains_key(&tx) {
@@ -92,58 +453,80 @@ impl Client {
         }
 
         // insert amount into deposits
+        self.save_deposit(tx);
         self.deposits.insert(
             tx,
             Deposit {
                 amount,
                 disputed: false,
+                charged_back: false,
+                was_disputed: false,
+                held: Decimal::ZERO,
+                reason: None,
             },
         );
 
         self.total += Decimal::from(amount);
+        self.max_deposit = self.max_deposit.max(Some(amount));
 
         Ok(())
     }
 
     /// Processes a withdrawal transaction, removing funds if sufficient balance exists.
     ///
+    /// Held funds can't be withdrawn, unless `allow_withdraw_held` is enabled in which case
+    /// the withdrawal is checked against the total balance instead.
+    ///
     /// # Errors
-    /// - `AccountLocked`: Account is locked and cannot process transactions
-    /// - `InsufficientFunds`: Available balance is less than withdrawal amount
+    /// - `WithdrawalFromLocked`: Account is locked and cannot process transactions
+    /// - `InsufficientFunds`: Available (or total) balance is less than withdrawal amount
     pub fn withdrawal(
         &mut self,
         _tx: TransactionID,
         amount: PositiveDecimal,
     ) -> Result<(), ProcessingError> {
         // ensure not locked
-        self.ensure_not_locked()?;
+        self.ensure_not_locked(ProcessingError::WithdrawalFromLocked)?;
 
         // insert amount into deposits
         // no need to check for negative amount since TransactionAmount is guaranteed to be positive
         let decimal_amount: Decimal = amount.into();
 
-        // ensure sufficient funds
-        if decimal_amount > self.total {
+        // ensure sufficient funds, held funds only count in the administrative mode
+        let withdrawable = match self.config.allow_withdraw_held {
+            true => self.total,
+            false => self.available(),
+        };
+        if decimal_amount > withdrawable {
             return Err(ProcessingError::InsufficientFunds);
         }
 
         // make withdrawal
         self.total -= decimal_amount;
+        self.withdrawn += decimal_amount;
+        self.max_withdrawal = self.max_withdrawal.max(Some(amount));
 
         Ok(())
     }
 
     /// Marks a deposit transaction as disputed, holding its funds.
     ///
+    /// The optional `reason` code is stored on the deposit, see `dispute_reason`.
+    ///
     /// # Errors
     /// - `AccountLocked`: Account is locked and cannot process transactions
     /// - `DepositNotFound`: Referenced deposit transaction does not exist
     /// - `AlreadyDisputed`: Deposit is already under dispute
-    pub fn dispute(&mut self, tx: TransactionID) -> Result<(), ProcessingError> {
+    pub fn dispute(
+        &mut self,
+        tx: TransactionID,
+        reason: Option<String>,
+    ) -> Result<(), ProcessingError> {
         // ensure not locked
-        self.ensure_not_locked()?;
+        self.ensure_not_locked(ProcessingError::AccountLocked)?;
 
         // get the deposit
+        self.save_deposit(tx);
         let deposit = self
             .deposits
             .get_mut(&tx)
@@ -155,8 +538,53 @@ impl Client {
         }
 
         // hold the disputed amount
-        self.held += Decimal::from(deposit.amount);
+        deposit.held = deposit.amount.into();
+        self.held += deposit.held;
+        deposit.disputed = true;
+        deposit.was_disputed = true;
+        deposit.reason = reason;
+
+        Ok(())
+    }
+
+    /// Disputes part of a deposit, holding only the given amount.
+    ///
+    /// A deposit can be partially disputed several times, as long as the held parts add up to at
+    /// most the deposit amount. A resolve or chargeback then applies to all of them.
+    ///
+    /// # Errors
+    /// - `AccountLocked`: Account is locked and cannot process transactions
+    /// - `DepositNotFound`: Referenced deposit transaction does not exist
+    /// - `OverDispute`: The held parts would exceed the deposit amount
+    pub fn partial_dispute(
+        &mut self,
+        tx: TransactionID,
+        amount: PositiveDecimal,
+        reason: Option<String>,
+    ) -> Result<(), ProcessingError> {
+        // ensure not locked
+        self.ensure_not_locked(ProcessingError::AccountLocked)?;
+
+        // get the deposit
+        self.save_deposit(tx);
+        let deposit = self
+            .deposits
+            .get_mut(&tx)
+            .ok_or(ProcessingError::DepositNotFound)?;
+
+        // never hold more than was deposited
+        let amount = Decimal::from(amount);
+        let held = deposit.held + amount;
+        if held > Decimal::from(deposit.amount) {
+            return Err(ProcessingError::OverDispute);
+        }
+
+        // hold the disputed part
+        self.held += amount;
+        deposit.held = held;
         deposit.disputed = true;
+        deposit.was_disputed = true;
+        deposit.reason = reason;
 
         Ok(())
     }
@@ -169,9 +597,10 @@ impl Client {
     /// - `NotDisputed`: Deposit is not under dispute
     pub fn resolve(&mut self, tx: TransactionID) -> Result<(), ProcessingError> {
         // ensure not locked
-        self.ensure_not_locked()?;
+        self.ensure_not_locked(ProcessingError::AccountLocked)?;
 
         // get the deposit
+        self.save_deposit(tx);
         let deposit = self
             .deposits
             .get_mut(&tx)
@@ -183,23 +612,29 @@ impl Client {
         }
 
         // release the disputed amount
-        self.held -= Decimal::from(deposit.amount);
+        let amount = std::mem::take(&mut deposit.held);
         deposit.disputed = false;
+        self.release_disputed(amount);
 
         Ok(())
     }
 
     /// Processes a chargeback on a disputed transaction, removing funds and locking the account.
     ///
+    /// If part of the deposit was already withdrawn the total balance goes negative, meaning the
+    /// client owes the difference. This is allowed (with a warning) to keep the held balance in
+    /// line with the disputed deposits, and the account is locked anyway.
+    ///
     /// # Errors
     /// - `AccountLocked`: Account is locked and cannot process transactions
     /// - `DepositNotFound`: Referenced deposit transaction does not exist
     /// - `NotDisputed`: Deposit is not under dispute
     pub fn chargeback(&mut self, tx: TransactionID) -> Result<(), ProcessingError> {
         // ensure not locked
-        self.ensure_not_locked()?;
+        self.ensure_not_locked(ProcessingError::AccountLocked)?;
 
         // get the deposit
+        self.save_deposit(tx);
         let deposit = self
             .deposits
             .get_mut(&tx)
@@ -210,16 +645,163 @@ impl Client {
             return Err(ProcessingError::NotDisputed);
         }
 
-        // release the disputed amount
-        self.held -= Decimal::from(deposit.amount);
-        self.total -= Decimal::from(deposit.amount);
+        // release the disputed amount, which is kept on the deposit as the amount charged back
+        let amount = deposit.held;
         deposit.disputed = false;
+        deposit.charged_back = true;
+        self.release_disputed(amount);
+        self.total -= amount;
+
+        if self.total.is_sign_negative() {
+            warn!(%tx, total = %self.total, "chargeback left the account with a negative balance");
+        }
 
         // lock the account
         self.locked = true;
+        self.locked_by = Some(tx);
 
         Ok(())
     }
+
+    /// Releases held funds to available without a matching resolve, e.g. because a dispute was
+    /// withdrawn externally. This is an administrative operation for manual corrections.
+    ///
+    /// The disputed deposits stay disputed, once they are resolved or charged back the
+    /// released funds are accounted for instead of held funds.
+    ///
+    /// # Errors
+    /// - `AdminOpsNotAllowed`: Administrative operations are not enabled
+    /// - `AccountLocked`: Account is locked and cannot process transactions
+    /// - `InsufficientHeldFunds`: Held balance is less than the amount to release
+    pub fn release(&mut self, amount: PositiveDecimal) -> Result<(), ProcessingError> {
+        // ensure admin operations are enabled
+        if !self.config.allow_admin_ops {
+            return Err(ProcessingError::AdminOpsNotAllowed);
+        }
+
+        // ensure not locked
+        self.ensure_not_locked(ProcessingError::AccountLocked)?;
+
+        // ensure we don't release more than held
+        let amount = Decimal::from(amount);
+        if amount > self.held {
+            return Err(ProcessingError::InsufficientHeldFunds);
+        }
+
+        self.held -= amount;
+        self.released += amount;
+
+        Ok(())
+    }
+
+    /// Applies an operation to the account, see the method of the operation for its errors.
+    pub fn apply(&mut self, op: ClientOp) -> Result<(), ProcessingError> {
+        let result = match op {
+            ClientOp::Deposit { tx, amount } => self.deposit(tx, amount),
+            ClientOp::Withdrawal { tx, amount } => self.withdrawal(tx, amount),
+            ClientOp::Dispute { tx, amount, reason } => {
+                match amount.filter(|_| self.config.partial_disputes) {
+                    Some(amount) => self.partial_dispute(tx, amount, reason),
+                    None => self.dispute(tx, reason),
+                }
+            }
+            ClientOp::Resolve { tx } => self.resolve(tx),
+            ClientOp::Chargeback { tx } => self.chargeback(tx),
+            ClientOp::Release { amount } => self.release(amount),
+        };
+
+        self.debug_assert_invariants();
+
+        result
+    }
+
+    /// Applies the operations in order, e.g. all transactions of a client of a shard, without
+    /// looking the account up for each of them. Returns the outcome of every operation, a failed
+    /// one doesn't stop the ones after it.
+    pub fn apply_batch(
+        &mut self,
+        ops: impl IntoIterator<Item = ClientOp>,
+    ) -> Vec<Result<(), ProcessingError>> {
+        ops.into_iter().map(|op| self.apply(op)).collect()
+    }
+
+    /// Applies several operations as one, either all of them succeed or the account is unchanged.
+    ///
+    /// Meant for composite operations like a dispute followed by a chargeback. The balances and
+    /// the lock are saved before the steps run, and every deposit is saved before a step first
+    /// changes it. If a step fails, all of them are restored, deposits that didn't exist before
+    /// are removed, and the error is returned.
+    pub fn apply_atomically<F>(&mut self, steps: F) -> Result<(), ProcessingError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), ProcessingError>,
+    {
+        let (total, held, released, withdrawn, locked, locked_by) = (
+            self.total,
+            self.held,
+            self.released,
+            self.withdrawn,
+            self.locked,
+            self.locked_by,
+        );
+        let (max_deposit, max_withdrawal) = (self.max_deposit, self.max_withdrawal);
+        let outer = self.undo.replace(Vec::new());
+
+        let result = steps(self);
+        let saved = std::mem::replace(&mut self.undo, outer).unwrap_or_default();
+        match result {
+            Ok(()) => {
+                // a surrounding `apply_atomically` may still have to undo the changes
+                for (tx, deposit) in saved {
+                    self.save_deposit_as(tx, deposit);
+                }
+            }
+            Err(_) => {
+                self.total = total;
+                self.held = held;
+                self.released = released;
+                self.withdrawn = withdrawn;
+                self.locked = locked;
+                self.locked_by = locked_by;
+                self.max_deposit = max_deposit;
+                self.max_withdrawal = max_withdrawal;
+                for (tx, deposit) in saved {
+                    match deposit {
+                        Some(deposit) => self.deposits.insert(tx, deposit),
+                        None => self.deposits.remove(&tx),
+                    };
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Saves the current state of a deposit before it's changed within `apply_atomically`.
+    fn save_deposit(&mut self, tx: TransactionID) {
+        if self.undo.is_some() {
+            let deposit = self.deposits.get(&tx).cloned();
+            self.save_deposit_as(tx, deposit);
+        }
+    }
+
+    /// Saves the state of a deposit for `apply_atomically`, unless an earlier state is saved.
+    fn save_deposit_as(&mut self, tx: TransactionID, deposit: Option<Deposit>) {
+        if let Some(undo) = &mut self.undo {
+            if !undo.iter().any(|(saved, _)| *saved == tx) {
+                undo.push((tx, deposit));
+            }
+        }
+    }
+
+    /// Removes the amount of a deposit that is no longer disputed from the held funds.
+    ///
+    /// Part of the amount may already have been released by the `release` admin operation,
+    /// that part is taken from the released funds instead.
+    fn release_disputed(&mut self, amount: Decimal) {
+        let from_held = amount.min(self.held);
+        self.held -= from_held;
+        self.released -= amount - from_held;
+    }
 }
 
 impl Default for Client {
@@ -229,9 +811,15 @@ impl Default for Client {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use rust_decimal_macros::dec;
 
+    use crate::{
+        processor::{Processor, ProcessorConfig},
+        transaction,
+    };
+
     use super::*;
 
     #[test]
@@ -247,12 +835,13 @@ mod tests {
         assert_eq!(client.locked(), false);
 
         client
-            .dispute(TransactionID::new(1))
+            .dispute(TransactionID::new(1), None)
             .expect("dispute should succeed");
         assert_eq!(client.total(), dec!(10.0));
         assert_eq!(client.held(), dec!(10.0));
         assert_eq!(client.available(), dec!(0.0));
         assert_eq!(client.locked(), false);
+        assert_eq!(client.locked_by(), None);
 
         client
             .chargeback(TransactionID::new(1))
@@ -261,6 +850,7 @@ mod tests {
         assert_eq!(client.held(), dec!(0.0));
         assert_eq!(client.available(), dec!(0.0));
         assert_eq!(client.locked(), true);
+        assert_eq!(client.locked_by(), Some(TransactionID::new(1)));
 
         client
             .deposit(TransactionID::new(2), dec!(1000.0).try_into().unwrap())
@@ -292,7 +882,7 @@ mod tests {
         assert_eq!(client.locked(), false);
 
         client
-            .dispute(TransactionID::new(1))
+            .dispute(TransactionID::new(1), None)
             .expect("dispute should succeed");
         assert_eq!(client.total(), dec!(1010.0));
         assert_eq!(client.held(), dec!(10.0));
@@ -316,6 +906,164 @@ mod tests {
         assert_eq!(client.locked(), true);
     }
 
+    #[test]
+    fn test_checked_available() {
+        let mut client = Client::new();
+        assert_eq!(client.checked_available(), Some(dec!(0.0)));
+
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .deposit(TransactionID::new(2), dec!(5.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+
+        assert_eq!(client.checked_available(), Some(dec!(5.0)));
+        assert_eq!(client.checked_available(), Some(client.available()));
+    }
+
+    #[test]
+    fn test_dispute_reason() {
+        let mut client = Client::new();
+        for tx in 1..=2 {
+            client
+                .deposit(TransactionID::new(tx), dec!(1.0).try_into().unwrap())
+                .expect("deposit should succeed");
+        }
+        client
+            .dispute(TransactionID::new(1), Some("fraud".to_string()))
+            .expect("dispute should succeed");
+        client
+            .dispute(TransactionID::new(2), None)
+            .expect("dispute should succeed");
+
+        assert_eq!(client.dispute_reason(TransactionID::new(1)), Some("fraud"));
+        assert_eq!(client.dispute_reason(TransactionID::new(2)), None);
+
+        // the reason stays available for auditing once the dispute is resolved
+        client
+            .resolve(TransactionID::new(1))
+            .expect("resolve should succeed");
+        assert_eq!(client.dispute_reason(TransactionID::new(1)), Some("fraud"));
+    }
+
+    #[test]
+    fn test_deposit_count() {
+        let mut client = Client::new();
+        assert_eq!(client.deposit_count(), 0);
+
+        for tx in 1..=3 {
+            client
+                .deposit(TransactionID::new(tx), dec!(1.0).try_into().unwrap())
+                .expect("deposit should succeed");
+        }
+        // withdrawals and rejected deposits aren't retained
+        client
+            .withdrawal(TransactionID::new(4), dec!(1.0).try_into().unwrap())
+            .expect("withdrawal should succeed");
+        assert!(client
+            .deposit(TransactionID::new(1), dec!(1.0).try_into().unwrap())
+            .is_err());
+
+        assert_eq!(client.deposit_count(), 3);
+    }
+
+    #[test]
+    fn test_max_deposit_and_withdrawal() {
+        let mut client = Client::new();
+        assert_eq!(
+            (client.max_deposit(), client.max_withdrawal()),
+            (None, None)
+        );
+
+        for (tx, amount) in [
+            (1, dec!(5.5)),
+            (2, dec!(120.25)),
+            (3, dec!(0.01)),
+            (4, dec!(80)),
+        ] {
+            client
+                .deposit(TransactionID::new(tx), amount.try_into().unwrap())
+                .expect("deposit should succeed");
+        }
+        for (tx, amount) in [(5, dec!(20)), (6, dec!(60.5)), (7, dec!(3))] {
+            client
+                .withdrawal(TransactionID::new(tx), amount.try_into().unwrap())
+                .expect("withdrawal should succeed");
+        }
+        // rejected operations don't count
+        assert!(client
+            .withdrawal(TransactionID::new(8), dec!(1000).try_into().unwrap())
+            .is_err());
+        assert!(client
+            .deposit(TransactionID::new(1), dec!(500).try_into().unwrap())
+            .is_err());
+
+        assert_eq!(client.max_deposit(), Some(dec!(120.25)));
+        assert_eq!(client.max_withdrawal(), Some(dec!(60.5)));
+
+        // both survive a snapshot, older snapshots fall back to the largest retained deposit
+        let mut snapshot = client.snapshot(ClientID::new(1));
+        let restored = Client::restore(&snapshot, ClientConfig::default());
+        assert_eq!(restored.max_deposit(), Some(dec!(120.25)));
+        assert_eq!(restored.max_withdrawal(), Some(dec!(60.5)));
+        (snapshot.max_deposit, snapshot.max_withdrawal) = (None, None);
+        let restored = Client::restore(&snapshot, ClientConfig::default());
+        assert_eq!(restored.max_deposit(), Some(dec!(120.25)));
+        assert_eq!(restored.max_withdrawal(), None);
+    }
+
+    #[test]
+    fn test_disputed_tx_ids() {
+        let mut client = Client::new();
+        assert_eq!(client.disputed_tx_ids().count(), 0);
+
+        for tx in 1..=3 {
+            client
+                .deposit(TransactionID::new(tx), dec!(1.0).try_into().unwrap())
+                .expect("deposit should succeed");
+        }
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+        client
+            .dispute(TransactionID::new(3), None)
+            .expect("dispute should succeed");
+
+        let mut disputed = client.disputed_tx_ids().collect::<Vec<_>>();
+        disputed.sort();
+        assert_eq!(disputed, vec![TransactionID::new(1), TransactionID::new(3)]);
+    }
+
+    #[test]
+    fn test_chargeback_after_partial_withdrawal() {
+        let mut client = Client::new();
+
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .withdrawal(TransactionID::new(2), dec!(8.0).try_into().unwrap())
+            .expect("withdrawal should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+        assert_eq!(client.total(), dec!(2.0));
+        assert_eq!(client.held(), dec!(10.0));
+        assert_eq!(client.available(), dec!(-8.0));
+
+        client
+            .chargeback(TransactionID::new(1))
+            .expect("chargeback should succeed even though the funds were partly withdrawn");
+        assert_eq!(client.total(), dec!(-8.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.available(), dec!(-8.0));
+        assert!(client.locked());
+    }
+
     #[test]
     fn test_withdraw_insufficient_funds() {
         let mut client = Client::new();
@@ -333,12 +1081,70 @@ mod tests {
         assert_eq!(client.available(), dec!(10.0));
     }
 
+    #[test]
+    fn test_withdraw_held_funds_denied() {
+        let mut client = Client::new();
+
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .deposit(TransactionID::new(2), dec!(5.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+
+        client
+            .withdrawal(TransactionID::new(3), dec!(10.0).try_into().unwrap())
+            .expect_err("withdrawal should fail since the funds are held");
+        assert_eq!(client.total(), dec!(15.0));
+        assert_eq!(client.held(), dec!(10.0));
+        assert_eq!(client.available(), dec!(5.0));
+
+        client
+            .withdrawal(TransactionID::new(4), dec!(5.0).try_into().unwrap())
+            .expect("withdrawal of available funds should succeed");
+        assert_eq!(client.total(), dec!(10.0));
+        assert_eq!(client.available(), dec!(0.0));
+    }
+
+    #[test]
+    fn test_withdraw_held_funds_allowed() {
+        let mut client = Client::with_config(ClientConfig {
+            allow_withdraw_held: true,
+            ..Default::default()
+        });
+
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .deposit(TransactionID::new(2), dec!(5.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+
+        client
+            .withdrawal(TransactionID::new(3), dec!(10.0).try_into().unwrap())
+            .expect("withdrawal should succeed using held funds");
+        assert_eq!(client.total(), dec!(5.0));
+        assert_eq!(client.held(), dec!(10.0));
+        assert_eq!(client.available(), dec!(-5.0));
+
+        client
+            .withdrawal(TransactionID::new(4), dec!(10.0).try_into().unwrap())
+            .expect_err("withdrawal should still fail beyond the total balance");
+        assert_eq!(client.total(), dec!(5.0));
+    }
+
     #[test]
     fn test_dispute_nonexistent_transaction() {
         let mut client = Client::new();
 
         client
-            .dispute(TransactionID::new(1))
+            .dispute(TransactionID::new(1), None)
             .expect_err("dispute should fail for non-existent transaction");
         assert_eq!(client.total(), dec!(0.0));
         assert_eq!(client.held(), dec!(0.0));
@@ -354,11 +1160,11 @@ mod tests {
             .expect("deposit should succeed");
 
         client
-            .dispute(TransactionID::new(1))
+            .dispute(TransactionID::new(1), None)
             .expect("first dispute should succeed");
 
         client
-            .dispute(TransactionID::new(1))
+            .dispute(TransactionID::new(1), None)
             .expect_err("second dispute should fail");
 
         assert_eq!(client.total(), dec!(10.0));
@@ -395,6 +1201,28 @@ mod tests {
         assert_eq!(client.available(), dec!(10.0));
     }
 
+    #[test]
+    fn test_resolve_error_messages() {
+        let mut client = Client::new();
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+
+        let not_disputed = client.resolve(TransactionID::new(1)).unwrap_err();
+        let not_found = client.resolve(TransactionID::new(2)).unwrap_err();
+        assert_eq!(
+            not_disputed.to_string(),
+            "deposit exists but isn't disputed"
+        );
+        assert_eq!(not_found.to_string(), "deposit not found for this client");
+
+        let not_disputed = client.chargeback(TransactionID::new(1)).unwrap_err();
+        assert_eq!(
+            not_disputed.to_string(),
+            "deposit exists but isn't disputed"
+        );
+    }
+
     #[test]
     fn test_chargeback_nonexistent_transaction() {
         let mut client = Client::new();
@@ -435,7 +1263,7 @@ mod tests {
             .deposit(TransactionID::new(1), dec!(100.0).try_into().unwrap())
             .expect("initial deposit should succeed");
         client
-            .dispute(TransactionID::new(1))
+            .dispute(TransactionID::new(1), None)
             .expect("dispute should succeed");
         client
             .chargeback(TransactionID::new(1))
@@ -457,7 +1285,7 @@ mod tests {
             .deposit(TransactionID::new(4), dec!(20.0).try_into().unwrap())
             .expect_err("deposit for dispute setup should fail");
         client
-            .dispute(TransactionID::new(4))
+            .dispute(TransactionID::new(4), None)
             .expect_err("dispute should fail on locked account");
 
         // Try resolve
@@ -477,6 +1305,106 @@ mod tests {
         assert_eq!(client.locked(), true);
     }
 
+    #[test]
+    fn test_locked_account_errors() {
+        let mut client = Client::new();
+
+        client
+            .deposit(TransactionID::new(1), dec!(100.0).try_into().unwrap())
+            .expect("initial deposit should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+        client
+            .chargeback(TransactionID::new(1))
+            .expect("chargeback should succeed");
+
+        assert!(matches!(
+            client.deposit(TransactionID::new(2), dec!(50.0).try_into().unwrap()),
+            Err(ProcessingError::DepositToLocked)
+        ));
+        assert!(matches!(
+            client.withdrawal(TransactionID::new(3), dec!(10.0).try_into().unwrap()),
+            Err(ProcessingError::WithdrawalFromLocked)
+        ));
+        assert!(matches!(
+            client.dispute(TransactionID::new(1), None),
+            Err(ProcessingError::AccountLocked)
+        ));
+    }
+
+    #[test]
+    fn test_release() {
+        let mut client = Client::with_config(ClientConfig {
+            allow_admin_ops: true,
+            ..Default::default()
+        });
+
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+
+        client
+            .release(dec!(4.0).try_into().unwrap())
+            .expect("release should succeed");
+        assert_eq!(client.total(), dec!(10.0));
+        assert_eq!(client.held(), dec!(6.0));
+        assert_eq!(client.available(), dec!(4.0));
+        assert_eq!(client.disputed_total(), client.held() + client.released());
+
+        // resolving the deposit accounts for the released part
+        client
+            .resolve(TransactionID::new(1))
+            .expect("resolve should succeed");
+        assert_eq!(client.total(), dec!(10.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.released(), dec!(0.0));
+        assert_eq!(client.available(), dec!(10.0));
+    }
+
+    #[test]
+    fn test_release_more_than_held() {
+        let mut client = Client::with_config(ClientConfig {
+            allow_admin_ops: true,
+            ..Default::default()
+        });
+
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+
+        assert!(matches!(
+            client.release(dec!(10.5).try_into().unwrap()),
+            Err(ProcessingError::InsufficientHeldFunds)
+        ));
+        assert_eq!(client.held(), dec!(10.0));
+        assert_eq!(client.available(), dec!(0.0));
+    }
+
+    #[test]
+    fn test_release_not_allowed() {
+        let mut client = Client::new();
+
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .dispute(TransactionID::new(1), None)
+            .expect("dispute should succeed");
+
+        assert!(matches!(
+            client.release(dec!(1.0).try_into().unwrap()),
+            Err(ProcessingError::AdminOpsNotAllowed)
+        ));
+        assert_eq!(client.held(), dec!(10.0));
+    }
+
     #[test]
     fn test_duplicate_transaction_id() {
         let mut client = Client::new();
@@ -503,4 +1431,180 @@ mod tests {
             "available funds should remain unchanged"
         );
     }
+
+    #[test]
+    fn test_partial_dispute() {
+        let mut client = Client::new();
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+
+        client
+            .partial_dispute(TransactionID::new(1), dec!(6.0).try_into().unwrap(), None)
+            .expect("first partial dispute should succeed");
+        assert!(matches!(
+            client.partial_dispute(TransactionID::new(1), dec!(4.5).try_into().unwrap(), None),
+            Err(ProcessingError::OverDispute)
+        ));
+        assert_eq!(client.held(), dec!(6.0));
+
+        // exactly the remaining amount is fine
+        client
+            .partial_dispute(TransactionID::new(1), dec!(4.0).try_into().unwrap(), None)
+            .expect("second partial dispute should succeed");
+        assert_eq!(client.held(), dec!(10.0));
+        assert_eq!(client.disputed_total(), dec!(10.0));
+    }
+
+    #[test]
+    fn test_partial_chargeback() {
+        let mut client = Client::new();
+        client
+            .deposit(TransactionID::new(1), dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        client
+            .partial_dispute(TransactionID::new(1), dec!(3.0).try_into().unwrap(), None)
+            .expect("partial dispute should succeed");
+        client
+            .chargeback(TransactionID::new(1))
+            .expect("chargeback should succeed");
+
+        assert_eq!(client.total(), dec!(7.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.audited_total(), client.total());
+
+        // the charged back part survives a snapshot
+        let restored = Client::restore(&client.snapshot(ClientID::new(1)), ClientConfig::default());
+        assert_eq!(restored.audited_total(), dec!(7.0));
+    }
+
+    #[test]
+    fn test_balances_keep_full_precision() {
+        let mut client = Client::new();
+        for tx in 1..=10_000 {
+            client
+                .deposit(TransactionID::new(tx), dec!(0.00004).try_into().unwrap())
+                .expect("deposit should succeed");
+        }
+        client
+            .deposit(
+                TransactionID::new(10_001),
+                dec!(1.00005).try_into().unwrap(),
+            )
+            .expect("deposit should succeed");
+        client
+            .dispute(TransactionID::new(10_001), None)
+            .expect("dispute should succeed");
+
+        assert_eq!(client.total(), dec!(1.40005));
+        assert_eq!(client.held(), dec!(1.00005));
+        assert_eq!(client.available(), dec!(0.4));
+        assert_eq!(client.audited_total(), client.total());
+
+        // rounding every deposit to the 4 places of the output would lose all of them
+        let rounded: Decimal = (0..10_000)
+            .map(|_| {
+                dec!(0.00004)
+                    .round_dp_with_strategy(4, rust_decimal::RoundingStrategy::MidpointAwayFromZero)
+            })
+            .sum();
+        assert_eq!(rounded, dec!(0));
+    }
+
+    #[test]
+    fn test_apply_atomically_rolls_back() {
+        let (client_id, tx) = (ClientID::new(1), TransactionID::new(1));
+        let mut client = Client::new();
+        client
+            .deposit(tx, dec!(10.0).try_into().unwrap())
+            .expect("deposit should succeed");
+        let before = client.snapshot(client_id);
+
+        // the dispute succeeds, then the chargeback of a missing deposit fails
+        let result = client.apply_atomically(|client| {
+            client.deposit(TransactionID::new(2), dec!(5.0).try_into().unwrap())?;
+            client.dispute(tx, Some("fraud".to_string()))?;
+            client.chargeback(TransactionID::new(3))
+        });
+        assert!(matches!(result, Err(ProcessingError::DepositNotFound)));
+        assert_eq!(client.snapshot(client_id), before);
+        assert!(!client.has_deposit(TransactionID::new(2)));
+
+        client
+            .apply_atomically(|client| {
+                client.dispute(tx, None)?;
+                client.chargeback(tx)
+            })
+            .expect("dispute and chargeback should succeed");
+        assert_eq!(client.total(), dec!(0.0));
+        assert_eq!(client.locked_by(), Some(tx));
+
+        // a nested call that succeeded is undone along with the failing outer one
+        let mut client = Client::new();
+        let result = client.apply_atomically(|client| {
+            client.apply_atomically(|client| client.deposit(tx, dec!(1.0).try_into().unwrap()))?;
+            client.chargeback(TransactionID::new(3))
+        });
+        assert!(matches!(result, Err(ProcessingError::DepositNotFound)));
+        assert!(!client.has_deposit(tx));
+        assert_eq!(client.total(), dec!(0));
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let client = ClientID::new(1);
+        let tx = TransactionID::new;
+        let amount = |value: Decimal| PositiveDecimal::try_from(value).unwrap();
+        let transactions = vec![
+            Transaction::Deposit(transaction::Deposit {
+                client,
+                tx: tx(1),
+                amount: amount(dec!(10.0)),
+            }),
+            Transaction::Withdrawal(transaction::Withdrawal {
+                client,
+                tx: tx(2),
+                amount: amount(dec!(20.0)),
+            }),
+            Transaction::Deposit(transaction::Deposit {
+                client,
+                tx: tx(3),
+                amount: amount(dec!(5.0)),
+            }),
+            Transaction::Dispute(transaction::Dispute {
+                client,
+                tx: tx(1),
+                amount: None,
+                reason: None,
+            }),
+            Transaction::Resolve(transaction::Resolve { client, tx: tx(9) }),
+            Transaction::Chargeback(transaction::Chargeback { client, tx: tx(1) }),
+            Transaction::Deposit(transaction::Deposit {
+                client,
+                tx: tx(4),
+                amount: amount(dec!(1.0)),
+            }),
+        ];
+
+        // the same transactions applied one by one through the processor
+        let mut processor = Processor::new(ProcessorConfig::default());
+        let expected: Vec<_> = transactions
+            .iter()
+            .map(|transaction| processor.handle_transaction(transaction.clone()))
+            .collect();
+
+        let mut account = Client::new();
+        let results = account.apply_batch(
+            transactions
+                .into_iter()
+                .filter_map(ClientOp::from_transaction),
+        );
+
+        assert_eq!(results, expected);
+        assert_eq!(
+            account.snapshot(client),
+            processor.snapshot().clients.pop().unwrap()
+        );
+        assert!(account.locked());
+    }
 }
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use crate::ids::TransactionID;
+
+/// Map from tx id to deposit, optimized for the lookups of disputes, resolves and chargebacks.
+///
+/// Feeds usually assign tx ids in increasing order, so deposits are appended to a `Vec` sorted by
+/// id. Since the ids of a client are spread fairly evenly, the position of an id can be guessed
+/// from its value, and a short search around the guess finds it with one or two cache misses and
+/// without hashing. A plain binary search over the whole `Vec` touches too many cache lines and
+/// loses to a `HashMap` for clients with many deposits, see `benches/disputes.rs`.
+///
+/// Deposits arriving out of order go to a `HashMap` instead, so the map still works for any order
+/// of ids, just without the benefit.
+#[derive(Clone, Debug)]
+pub struct DepositMap<V> {
+    /// Deposits whose id is higher than all ids before them, sorted by id.
+    sorted: Vec<(TransactionID, V)>,
+    /// Deposits that arrived after a deposit with a higher id.
+    unordered: HashMap<TransactionID, V>,
+}
+
+impl<V> Default for DepositMap<V> {
+    fn default() -> Self {
+        Self {
+            sorted: Vec::new(),
+            unordered: HashMap::new(),
+        }
+    }
+}
+
+impl<V> DepositMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sorted.len() + self.unordered.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains_key(&self, tx: &TransactionID) -> bool {
+        self.get(tx).is_some()
+    }
+
+    pub fn get(&self, tx: &TransactionID) -> Option<&V> {
+        match self.position(*tx) {
+            Some(index) => Some(&self.sorted[index].1),
+            None => self.unordered.get(tx),
+        }
+    }
+
+    pub fn get_mut(&mut self, tx: &TransactionID) -> Option<&mut V> {
+        match self.position(*tx) {
+            Some(index) => Some(&mut self.sorted[index].1),
+            None => self.unordered.get_mut(tx),
+        }
+    }
+
+    /// Returns the index of a tx id in `sorted`.
+    ///
+    /// Interpolates the index from the first and last id, then gallops from that guess until the
+    /// id is bracketed and binary searches the bracket.
+    fn position(&self, tx: TransactionID) -> Option<usize> {
+        let ((first, _), (last, _)) = (self.sorted.first()?, self.sorted.last()?);
+        if tx < *first || tx > *last {
+            return None;
+        }
+
+        let len = self.sorted.len();
+        let (offset, span) = (
+            u32::from(tx) - u32::from(*first),
+            u32::from(*last) - u32::from(*first),
+        );
+        let guess = match span {
+            0 => 0,
+            span => (u64::from(offset) * (len as u64 - 1) / u64::from(span)) as usize,
+        };
+
+        let id = |index: usize| self.sorted[index].0;
+        let (mut start, mut end) = (guess, guess + 1);
+        let mut step = 1;
+        while start > 0 && id(start) > tx {
+            start = start.saturating_sub(step);
+            step *= 2;
+        }
+        step = 1;
+        while end < len && id(end - 1) < tx {
+            end = (end + step).min(len);
+            step *= 2;
+        }
+
+        self.sorted[start..end]
+            .binary_search_by_key(&tx, |(id, _)| *id)
+            .ok()
+            .map(|index| start + index)
+    }
+
+    /// Inserts a deposit, returning the previous one with the same id like `HashMap::insert`.
+    pub fn insert(&mut self, tx: TransactionID, value: V) -> Option<V> {
+        if let Some(previous) = self.get_mut(&tx) {
+            return Some(std::mem::replace(previous, value));
+        }
+
+        match self.sorted.last() {
+            Some((last, _)) if *last > tx => self.unordered.insert(tx, value),
+            _ => {
+                self.sorted.push((tx, value));
+                None
+            }
+        }
+    }
+
+    /// Removes a deposit, returning it if it was in the map.
+    pub fn remove(&mut self, tx: &TransactionID) -> Option<V> {
+        match self.position(*tx) {
+            Some(index) => Some(self.sorted.remove(index).1),
+            None => self.unordered.remove(tx),
+        }
+    }
+
+    /// Returns the deposits in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&TransactionID, &V)> {
+        self.sorted
+            .iter()
+            .map(|(tx, value)| (tx, value))
+            .chain(self.unordered.iter())
+    }
+
+    /// Returns the deposits in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<V> FromIterator<(TransactionID, V)> for DepositMap<V> {
+    fn from_iter<I: IntoIterator<Item = (TransactionID, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (tx, value) in iter {
+            map.insert(tx, value);
+        }
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deposit_map_in_order() {
+        let mut map: DepositMap<u32> = (1..=100).map(|tx| (TransactionID::new(tx), tx)).collect();
+        assert_eq!(map.len(), 100);
+        assert!(map.unordered.is_empty());
+
+        assert_eq!(map.get(&TransactionID::new(42)), Some(&42));
+        *map.get_mut(&TransactionID::new(42)).unwrap() = 0;
+        assert_eq!(map.get(&TransactionID::new(42)), Some(&0));
+        assert_eq!(map.get(&TransactionID::new(101)), None);
+    }
+
+    #[test]
+    fn test_deposit_map_uneven_ids() {
+        // clustered ids make the interpolated guess miss by a lot
+        let ids: Vec<u32> = (1..=50).chain((1..=50).map(|i| 1_000_000 + i)).collect();
+        let map: DepositMap<u32> = ids
+            .iter()
+            .map(|tx| (TransactionID::new(*tx), *tx))
+            .collect();
+        assert!(map.unordered.is_empty());
+
+        for tx in &ids {
+            assert_eq!(map.get(&TransactionID::new(*tx)), Some(tx));
+        }
+        for tx in [0, 51, 500_000, 1_000_000, 1_000_051] {
+            assert_eq!(map.get(&TransactionID::new(tx)), None);
+        }
+    }
+
+    #[test]
+    fn test_deposit_map_out_of_order() {
+        let mut map = DepositMap::new();
+        for tx in [5, 10, 7, 11, 1] {
+            assert_eq!(map.insert(TransactionID::new(tx), tx), None);
+        }
+        assert_eq!(map.insert(TransactionID::new(7), 70), Some(7));
+        assert_eq!(map.insert(TransactionID::new(10), 100), Some(10));
+
+        assert_eq!(map.len(), 5);
+        for (tx, value) in [(1, 1), (5, 5), (7, 70), (10, 100), (11, 11)] {
+            assert_eq!(map.get(&TransactionID::new(tx)), Some(&value));
+        }
+        assert!(!map.contains_key(&TransactionID::new(2)));
+
+        let mut values: Vec<_> = map.values().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![1, 5, 11, 70, 100]);
+    }
+
+    #[test]
+    fn test_deposit_map_remove() {
+        let mut map = DepositMap::new();
+        for tx in [5, 10, 7, 11, 1] {
+            map.insert(TransactionID::new(tx), tx);
+        }
+
+        // 7 is in the unordered part, 10 in the sorted one
+        assert_eq!(map.remove(&TransactionID::new(7)), Some(7));
+        assert_eq!(map.remove(&TransactionID::new(10)), Some(10));
+        assert_eq!(map.remove(&TransactionID::new(10)), None);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.insert(TransactionID::new(10), 100), None);
+
+        let mut values: Vec<_> = map.values().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![1, 5, 11, 100]);
+    }
+}
@@ -0,0 +1,53 @@
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use thiserror::Error;
+
+use crate::{ids::ClientID, minor_units::to_minor_units};
+
+/// Scale of the integer minor units used by `FlatClientState`, i.e. balances are in 1/10000ths.
+pub const FLAT_SCALE: u32 = 4;
+
+/// The state of a client account with a C-compatible layout, meant to be passed over FFI.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlatClientState {
+    pub client: u16,
+    /// `1` if the account is locked, `0` otherwise.
+    pub locked: u8,
+    /// Available balance in minor units, see `FLAT_SCALE`.
+    pub available: i64,
+    /// Held balance in minor units, see `FLAT_SCALE`.
+    pub held: i64,
+    /// Total balance in minor units, see `FLAT_SCALE`.
+    pub total: i64,
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("balance {value} of client {client} doesn't fit in 64-bit minor units")]
+    Overflow { client: ClientID, value: Decimal },
+}
+
+impl FlatClientState {
+    /// Converts the balances to minor units, rounding fractions of a minor unit.
+    pub fn new(
+        client: ClientID,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+        locked: bool,
+    ) -> Result<Self, ExportError> {
+        let convert = |value| {
+            to_minor_units(value, FLAT_SCALE)
+                .and_then(|minor| minor.to_i64())
+                .ok_or(ExportError::Overflow { client, value })
+        };
+
+        Ok(Self {
+            client: client.into(),
+            locked: u8::from(locked),
+            available: convert(available)?,
+            held: convert(held)?,
+            total: convert(total)?,
+        })
+    }
+}
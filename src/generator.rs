@@ -0,0 +1,115 @@
+use std::io;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_decimal::Decimal;
+
+use crate::ids::{ClientID, TransactionID};
+
+/// Options for generating a synthetic transaction stream.
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    /// Number of transactions to generate.
+    pub transactions: u32,
+    /// Number of distinct clients, client ids are in the range `1..=clients`.
+    pub clients: u16,
+    /// Seed for the random number generator, the same seed always produces the same output.
+    pub seed: u64,
+}
+
+/// Writes a random, but reproducible, stream of transactions as CSV.
+///
+/// Most transactions are deposits and withdrawals, the rest are disputes, resolves and
+/// chargebacks referencing earlier deposits of the same client. Not every generated
+/// transaction is valid, e.g. withdrawals may exceed the available funds.
+pub fn generate<W>(writer: W, config: &GeneratorConfig) -> Result<(), csv::Error>
+where
+    W: io::Write,
+{
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut deposits: Vec<(ClientID, TransactionID)> = Vec::new();
+
+    writer.write_record(["type", "client", "tx", "amount"])?;
+
+    for tx in 1..=config.transactions {
+        let client = ClientID::new(rng.gen_range(1..=config.clients.max(1)));
+        let tx = TransactionID::new(tx);
+        let amount = Decimal::new(rng.gen_range(1..=10_000_000), 4);
+
+        match rng.gen_range(0..100) {
+            // disputes, resolves and chargebacks need an earlier deposit to refer to
+            roll @ 90.. if !deposits.is_empty() => {
+                let (client, tx) = deposits[rng.gen_range(0..deposits.len())];
+                let transaction_type = match roll {
+                    90..=95 => "dispute",
+                    96..=98 => "resolve",
+                    _ => "chargeback",
+                };
+                writer.write_record([
+                    transaction_type,
+                    &client.to_string(),
+                    &tx.to_string(),
+                    "",
+                ])?;
+            }
+            60..=89 => {
+                writer.write_record([
+                    "withdrawal",
+                    &client.to_string(),
+                    &tx.to_string(),
+                    &amount.to_string(),
+                ])?;
+            }
+            _ => {
+                deposits.push((client, tx));
+                writer.write_record([
+                    "deposit",
+                    &client.to_string(),
+                    &tx.to_string(),
+                    &amount.to_string(),
+                ])?;
+            }
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reader::{ReaderConfig, TransactionReader};
+
+    use super::*;
+
+    fn generate_to_string(seed: u64) -> String {
+        let config = GeneratorConfig {
+            transactions: 1000,
+            clients: 10,
+            seed,
+        };
+
+        let mut output = Vec::new();
+        generate(&mut output, &config).expect("generate should succeed");
+        String::from_utf8(output).expect("output should be utf-8")
+    }
+
+    #[test]
+    fn test_same_seed_same_output() {
+        assert_eq!(generate_to_string(42), generate_to_string(42));
+        assert_ne!(generate_to_string(42), generate_to_string(43));
+    }
+
+    #[test]
+    fn test_output_is_valid() {
+        let output = generate_to_string(7);
+
+        // every generated row is read, none is skipped
+        let transactions = TransactionReader::new(output.as_bytes(), ReaderConfig::default())
+            .chunks(100)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("generated transactions should be valid");
+        assert_eq!(transactions.iter().map(Vec::len).sum::<usize>(), 1000);
+    }
+}
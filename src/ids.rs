@@ -1,5 +1,7 @@
 use derive_more::{Constructor, Display, From, Into};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 
 /// A strongly-typed identifier for a client in the transaction processing system.
 ///
@@ -8,6 +10,7 @@ use serde::{Deserialize, Serialize};
 ///
 /// # Examples
 /// ```
+/// # use toy_stream_processor::ids::ClientID;
 /// let client_id = ClientID::new(1234);
 /// ```
 #[derive(
@@ -21,11 +24,53 @@ use serde::{Deserialize, Serialize};
     From,
     Hash,
     Into,
+    Ord,
     PartialEq,
+    PartialOrd,
     Serialize,
 )]
 pub struct ClientID(u16);
 
+impl ClientID {
+    /// Formats the id zero-padded to at least `width` digits, for fixed-width consumers.
+    ///
+    /// # Examples
+    /// ```
+    /// # use toy_stream_processor::ids::ClientID;
+    /// assert_eq!(ClientID::new(42).to_padded_string(5), "00042");
+    /// assert_eq!(ClientID::new(42).to_padded_string(1), "42");
+    /// ```
+    pub fn to_padded_string(&self, width: usize) -> String {
+        format!("{:0width$}", self.0)
+    }
+
+    /// Returns a deterministic pseudonym of the id for sharing outputs, the first 64 bits of the
+    /// HMAC-SHA256 of the id under `key` as 16 hex digits.
+    ///
+    /// Without the key the id can't be recovered. Distinct pseudonyms aren't guaranteed, but with
+    /// 64 bits the chance that any two of the 65536 possible ids share one is about 2^-33 for a
+    /// random key.
+    ///
+    /// # Examples
+    /// ```
+    /// # use toy_stream_processor::ids::ClientID;
+    /// let pseudonym = ClientID::new(42).pseudonym(b"secret");
+    /// assert_eq!(pseudonym.len(), 16);
+    /// assert_eq!(pseudonym, ClientID::new(42).pseudonym(b"secret"));
+    /// ```
+    pub fn pseudonym(&self, key: &[u8]) -> String {
+        // HMAC accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length is valid");
+        mac.update(&self.0.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        digest[..8]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
 /// A strongly-typed identifier for a transaction in the processing system.
 ///
 /// This type wraps a `u32` to provide type safety and prevent mixing up transaction IDs with other numeric values.
@@ -33,6 +78,7 @@ pub struct ClientID(u16);
 ///
 /// # Examples
 /// ```
+/// # use toy_stream_processor::ids::TransactionID;
 /// let tx_id = TransactionID::new(5678);
 /// ```
 #[derive(
@@ -46,7 +92,9 @@ pub struct ClientID(u16);
     From,
     Hash,
     Into,
+    Ord,
     PartialEq,
+    PartialOrd,
     Serialize,
 )]
 pub struct TransactionID(u32);
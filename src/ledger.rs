@@ -0,0 +1,111 @@
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    ids::{ClientID, TransactionID},
+    transaction::Transaction,
+};
+
+/// A single balance change in the ledger, deposits are positive and withdrawals negative.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct LedgerEntry {
+    pub client: ClientID,
+    pub tx: TransactionID,
+    pub amount: Decimal,
+}
+
+impl LedgerEntry {
+    /// Returns the ledger entry for a deposit or withdrawal, `None` for dispute related operations.
+    pub fn from_transaction(transaction: &Transaction) -> Option<Self> {
+        match transaction {
+            Transaction::Deposit(deposit) => Some(LedgerEntry {
+                client: deposit.client,
+                tx: deposit.tx,
+                amount: deposit.amount.into(),
+            }),
+            Transaction::Withdrawal(withdrawal) => Some(LedgerEntry {
+                client: withdrawal.client,
+                tx: withdrawal.tx,
+                amount: -Decimal::from(withdrawal.amount),
+            }),
+            Transaction::Dispute(_)
+            | Transaction::Resolve(_)
+            | Transaction::Chargeback(_)
+            | Transaction::Release(_)
+            | Transaction::Open(_) => None,
+        }
+    }
+}
+
+/// Writes deposits and withdrawals as a unified ledger CSV.
+pub struct LedgerWriter<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W> LedgerWriter<W>
+where
+    W: io::Write,
+{
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+
+    pub fn write(&mut self, entry: &LedgerEntry) -> Result<(), csv::Error> {
+        self.writer.serialize(entry)
+    }
+
+    pub fn flush(&mut self) -> Result<(), csv::Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::transaction::{Deposit, Dispute, Withdrawal};
+
+    use super::*;
+
+    #[test]
+    fn test_ledger_signs() {
+        let transactions = [
+            Transaction::Deposit(Deposit {
+                client: ClientID::new(1),
+                tx: TransactionID::new(1),
+                amount: dec!(10.5).try_into().unwrap(),
+            }),
+            Transaction::Withdrawal(Withdrawal {
+                client: ClientID::new(1),
+                tx: TransactionID::new(2),
+                amount: dec!(2.25).try_into().unwrap(),
+            }),
+            Transaction::Dispute(Dispute {
+                client: ClientID::new(1),
+                tx: TransactionID::new(1),
+                amount: None,
+                reason: None,
+            }),
+        ];
+
+        let mut output = Vec::new();
+        let mut writer = LedgerWriter::new(&mut output);
+        for entry in transactions
+            .iter()
+            .filter_map(LedgerEntry::from_transaction)
+        {
+            writer.write(&entry).expect("write should succeed");
+        }
+        writer.flush().expect("flush should succeed");
+        drop(writer);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount\n1,1,10.5\n1,2,-2.25\n"
+        );
+    }
+}
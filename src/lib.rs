@@ -0,0 +1,22 @@
+//! A toy transaction stream processor.
+//!
+//! Reads client transactions (deposits, withdrawals and disputes) from CSV and keeps track of
+//! the resulting client account balances.
+
+pub mod aggregate;
+pub mod certificate;
+pub mod client;
+pub mod deposit_map;
+pub mod export;
+pub mod generator;
+pub mod ids;
+pub mod ledger;
+pub mod metadata;
+pub mod minor_units;
+pub mod output;
+pub mod positive_decimal;
+pub mod processor;
+pub mod reader;
+pub mod run;
+pub mod snapshot;
+pub mod transaction;
@@ -1,25 +1,23 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    process::ExitCode,
+};
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use tracing::{trace, warn};
+use toy_stream_processor::{
+    output,
+    run::{
+        eligible_disputes, inspect, run, run_interactive, transform, validate, Args, RunError,
+        EMPTY_OUTPUT_EXIT_CODE, HALTED_ON_LOCK_EXIT_CODE, INVALID_SNAPSHOT_EXIT_CODE,
+    },
+};
+use tracing::trace;
 
-mod client;
-mod ids;
 mod logging;
-mod positive_decimal;
-mod processor;
-mod reader;
-mod transaction;
-
-#[derive(Debug, Parser)]
-/// Transaction processor
-struct Args {
-    /// Path to the file containing the transactions
-    file: PathBuf,
-}
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     // Set up logging
     logging::init_logging();
 
@@ -28,37 +26,109 @@ fn main() -> Result<()> {
 
     trace!(?args, "application started");
 
-    // Open the CSV file
-    let file = File::open(&args.file).context("open csv file")?;
-    trace!(?args.file, "opened csv file");
+    // In interactive mode the affected client is printed after every transaction instead
+    if args.interactive {
+        match run_interactive(std::io::stdin().lock(), std::io::stdout().lock(), &args) {
+            // the reader of the output went away, there's nobody left to answer
+            Err(RunError::WriteOutput(err)) if err.is_broken_pipe() => {}
+            Ok(summary) if summary.halted_on_lock.is_some() => {
+                return Ok(ExitCode::from(HALTED_ON_LOCK_EXIT_CODE));
+            }
+            result => {
+                result?;
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    // Read the CSV file using the TransactionReader
-    // This reader only returns valid transactions
-    let mut reader = reader::TransactionReader::new(&file);
+    // Print the client states of a bincode output file instead of processing transactions
+    if args.inspect.is_some() {
+        output::write_until_closed(std::io::stdout(), inspect(&args)?, &args.output_config())
+            .context("write status entries")?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    // Create a processor to process the transactions
-    let mut processor = processor::Processor::new();
+    // Check a snapshot before resuming from it, instead of processing transactions
+    if args.validate.is_some() {
+        let issues = validate(&args)?;
+        print_lines(&issues)?;
+        return Ok(match issues.is_empty() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::from(INVALID_SNAPSHOT_EXIT_CODE),
+        });
+    }
 
-    // Loop through all the the transactions and process them one by one
-    while let Some(transaction) = reader.next() {
-        trace!(?transaction, "processing transaction");
+    // List the deposits that could be disputed in a snapshot, instead of processing transactions
+    if args.eligible_disputes.is_some() {
+        print_lines(eligible_disputes(&args)?)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-        // In case the transaction processing fails, print a warning, but don't stop processing
-        if let Err(err) = processor.handle_transaction(transaction) {
-            warn!(?err, "transaction processing failed");
-        }
+    // Write the valid transactions as canonical CSV, instead of processing them
+    if args.transform_only {
+        transform(&args, std::io::stdout().lock())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let summary = run(&args)?;
+    trace!(
+        processed = summary.processed,
+        skipped = summary.skipped,
+        rejected = summary.rejected,
+        "processed transactions"
+    );
+
+    if !args.writes_output_files() {
+        // Print the status of all the clients to stdout, e.g. `| head` may stop reading early
+        output::write_until_closed(
+            std::io::stdout(),
+            summary.status_entries(&args),
+            &args.output_config(),
+        )
+        .context("write status entries")?;
+    }
+
+    // Narrate the transactions of the client being investigated
+    for line in &summary.explanation {
+        eprintln!("{line}");
     }
 
-    // Print the status of all the clients to stdout
-    let mut csv_writer = csv::Writer::from_writer(std::io::stdout());
-    for status_entry in processor.status_entries() {
-        csv_writer
-            .serialize(status_entry)
-            .context("write status entry")?;
+    // Summarize the warnings of the run, to tell a dirty input apart from a few bad rows
+    if args.warning_histogram {
+        eprintln!("{}", summary.warning_histogram());
+    }
+    if let Some(profile) = summary.processor.profile() {
+        eprintln!("{profile}");
     }
-    csv_writer.flush().context("flush csv writer")?;
 
     trace!(?args, "application finished");
 
+    // Signal that the batch stopped at a chargeback and the rest still needs to be processed
+    if summary.halted_on_lock.is_some() {
+        return Ok(ExitCode::from(HALTED_ON_LOCK_EXIT_CODE));
+    }
+
+    // Let wrapper scripts tell a run without any client activity apart from a regular one
+    if args.fail_on_empty_output && summary.is_empty() {
+        return Ok(ExitCode::from(EMPTY_OUTPUT_EXIT_CODE));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints one line per item to stdout, stopping quietly once the reader closes the pipe.
+fn print_lines<I, T>(lines: I) -> Result<()>
+where
+    I: IntoIterator<Item = T>,
+    T: Display,
+{
+    let mut stdout = io::stdout().lock();
+    for line in lines {
+        match writeln!(stdout, "{line}") {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => break,
+            result => result.context("write to stdout")?,
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,86 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::run::{input_dir_files, Args, RunSummary};
+
+/// Provenance of a run, written by `--metadata-out` next to the output.
+#[derive(Debug, Serialize)]
+pub struct RunMetadata<'a> {
+    pub tool_version: &'static str,
+    pub inputs: Vec<InputFile>,
+    /// Number of transactions applied to the client accounts.
+    pub processed: usize,
+    /// Number of invalid rows skipped by the reader.
+    pub skipped: usize,
+    /// Number of valid transactions rejected by the processor.
+    pub rejected: usize,
+    /// Skipped and rejected rows per reason.
+    pub reasons: &'a BTreeMap<&'static str, usize>,
+    pub clients: usize,
+    /// Start and end of the run in milliseconds since the Unix epoch.
+    pub started_at_unix_ms: u128,
+    pub finished_at_unix_ms: u128,
+    /// All options of the run, including the defaults that weren't given.
+    pub config: &'a Args,
+}
+
+/// An input file with its size at the end of the run, `None` if it can't be determined.
+#[derive(Debug, Serialize)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub size: Option<u64>,
+}
+
+impl<'a> RunMetadata<'a> {
+    pub fn new(args: &'a Args, summary: &'a RunSummary, started_at: SystemTime) -> Self {
+        let unix_ms = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_millis())
+        };
+        let inputs = args
+            .file
+            .iter()
+            .chain(&args.parallel_files)
+            .cloned()
+            .chain(
+                args.input_dir
+                    .as_deref()
+                    .and_then(|dir| input_dir_files(dir).ok())
+                    .into_iter()
+                    .flatten(),
+            )
+            .map(|path| InputFile {
+                size: file_size(&path),
+                path,
+            })
+            .collect();
+
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION"),
+            inputs,
+            processed: summary.processed,
+            skipped: summary.skipped,
+            rejected: summary.rejected,
+            reasons: &summary.reasons,
+            clients: summary.processor.status_entries().count(),
+            started_at_unix_ms: unix_ms(started_at),
+            finished_at_unix_ms: unix_ms(SystemTime::now()),
+            config: args,
+        }
+    }
+
+    /// Writes the metadata as pretty-printed JSON.
+    pub fn write_json<W: io::Write>(&self, writer: W) -> Result<(), serde_json::Error> {
+        serde_json::to_writer_pretty(writer, self)
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+}
@@ -0,0 +1,31 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// Converts an amount to integer minor units, e.g. `1.50` at scale 2 becomes `150`.
+///
+/// Fractions of a minor unit are rounded to the nearest integer, with halves rounded away from zero.
+/// Returns `None` if the result doesn't fit in a `Decimal`.
+pub fn to_minor_units(value: Decimal, scale: u32) -> Option<Decimal> {
+    10u64
+        .checked_pow(scale)
+        .and_then(|factor| value.checked_mul(Decimal::from(factor)))
+        .map(|minor| minor.round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_to_minor_units() {
+        assert_eq!(to_minor_units(dec!(1.50), 2).unwrap().to_string(), "150");
+        assert_eq!(to_minor_units(dec!(1.5), 0).unwrap().to_string(), "2");
+        assert_eq!(to_minor_units(dec!(-0.125), 2).unwrap().to_string(), "-13");
+        assert_eq!(
+            to_minor_units(dec!(0.12344), 4).unwrap().to_string(),
+            "1234"
+        );
+        assert_eq!(to_minor_units(Decimal::MAX, 2), None);
+    }
+}
@@ -0,0 +1,1295 @@
+use std::{
+    io,
+    num::{NonZeroU32, NonZeroUsize},
+};
+
+use clap::ValueEnum;
+use csv::WriterBuilder;
+use rust_decimal::{Decimal, RoundingStrategy::MidpointAwayFromZero};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    aggregate::{self, Aggregate},
+    ids::{ClientID, TransactionID},
+    minor_units::to_minor_units,
+    processor::ProcessorStatusEntry,
+};
+
+/// Options controlling how the client states are written.
+#[derive(Clone, Debug, Default)]
+pub struct OutputConfig {
+    /// Format of the output.
+    pub format: OutputFormat,
+    /// Don't write the header row, some downstream tools can't handle it.
+    pub omit_header: bool,
+    /// Start CSV output with a UTF-8 BOM, which Excel needs to detect the encoding.
+    pub bom: bool,
+    /// Add a `state_hash` column, to detect which clients changed between runs.
+    pub state_hash: bool,
+    /// Round balances to this many decimal places, halves away from zero, `None` writes them at full
+    /// precision. This is the only place balances are rounded. Ignored with `minor_units_scale`.
+    pub scale: Option<u32>,
+    /// Write balances as integer minor units, i.e. multiplied by `10^scale` and rounded.
+    pub minor_units_scale: Option<u32>,
+    /// Trim or pad the trailing zeros of the amounts, `None` keeps the scale of the balances, e.g.
+    /// `5.1000` after adding `2.1000` and `3`. Ignored with `minor_units_scale`.
+    pub trailing_zeros: Option<TrailingZeros>,
+    /// Write one row per client id range of this size with summed balances, instead of one per client.
+    pub group_by_range: Option<NonZeroU32>,
+    /// Write the locked and the active clients as two separate groups, each sorted by client id.
+    /// Ignored when `group_by_range` is set.
+    pub group_by_locked: bool,
+    /// Add the diagnostic columns, i.e. `deposits_retained` and `locked_by_tx`.
+    pub verbose: bool,
+    /// Add a `negative_available` column marking clients whose available balance is negative,
+    /// i.e. that withdrew funds of a deposit that was disputed afterwards.
+    pub flag_negative_available: bool,
+    /// Zero-pad the client ids to this many digits, e.g. `00042` for width 5.
+    pub client_id_width: Option<usize>,
+    /// Replace the client ids by their pseudonym under this key, see `ClientID::pseudonym`.
+    /// Takes precedence over `client_id_width`, bincode output can't be anonymized.
+    pub anonymize_key: Option<String>,
+    /// Field delimiter of the CSV output, `None` uses a comma.
+    pub delimiter: Option<u8>,
+    /// Locale used to format the balances of the clients, `group_by_range` rows are not localized.
+    pub locale: OutputLocale,
+    /// Order of the client rows, `None` keeps the default order of the format: CSV in the order the
+    /// clients were first seen, JSON sorted by client id.
+    pub order_by: Option<OrderBy>,
+    /// Write only this many client rows, followed by the summed balances of the remaining clients.
+    /// Ignored when `group_by_range` or `group_by_locked` is set.
+    pub top: Option<NonZeroUsize>,
+}
+
+/// Order of the client rows, ties are broken by ascending client id in every order, so the output
+/// doesn't depend on the order the clients were first seen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrderBy {
+    /// Ascending client id
+    Client,
+    /// Highest total balance first
+    TotalDesc,
+    /// Lowest total balance first
+    TotalAsc,
+    /// Highest available balance first
+    AvailableDesc,
+    /// Lowest available balance first
+    AvailableAsc,
+    /// Highest held balance first
+    HeldDesc,
+    /// Lowest held balance first
+    HeldAsc,
+}
+
+impl OrderBy {
+    fn sort(self, entries: &mut [ProcessorStatusEntry]) {
+        let descending = |value: fn(&ProcessorStatusEntry) -> Decimal| {
+            move |a: &ProcessorStatusEntry, b: &ProcessorStatusEntry| {
+                value(b).cmp(&value(a)).then(a.client.cmp(&b.client))
+            }
+        };
+        let ascending = |value: fn(&ProcessorStatusEntry) -> Decimal| {
+            move |a: &ProcessorStatusEntry, b: &ProcessorStatusEntry| {
+                value(a).cmp(&value(b)).then(a.client.cmp(&b.client))
+            }
+        };
+        match self {
+            OrderBy::Client => entries.sort_by_key(|entry| entry.client),
+            OrderBy::TotalDesc => entries.sort_by(descending(|entry| entry.total)),
+            OrderBy::TotalAsc => entries.sort_by(ascending(|entry| entry.total)),
+            OrderBy::AvailableDesc => entries.sort_by(descending(|entry| entry.available)),
+            OrderBy::AvailableAsc => entries.sort_by(ascending(|entry| entry.available)),
+            OrderBy::HeldDesc => entries.sort_by(descending(|entry| entry.held)),
+            OrderBy::HeldAsc => entries.sort_by(ascending(|entry| entry.held)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// One CSV row per client
+    #[default]
+    Csv,
+    /// A pretty-printed JSON array sorted by client id, stable for diffing
+    Json,
+    /// One compact JSON object per line sorted by client id, for log pipelines
+    Ndjson,
+    /// A compact binary list sorted by client id for handing the states to another process, see
+    /// `read_bincode`. All other output options are ignored
+    Bincode,
+}
+
+/// How trailing zeros of the amounts are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrailingZeros {
+    /// Remove insignificant trailing zeros, e.g. 5.1000 becomes 5.1 and 5.0000 becomes 5
+    Trim,
+    /// Pad to the output scale, e.g. 5.1 becomes 5.1000 with scale 4. Amounts are written as is
+    /// without an output scale
+    Pad,
+}
+
+/// Number formatting conventions for the balances.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputLocale {
+    /// Decimal point, e.g. 1.5
+    #[default]
+    En,
+    /// Decimal comma, e.g. 1,5. Needs a CSV delimiter other than a comma
+    De,
+}
+
+impl OutputLocale {
+    fn decimal_separator(self) -> char {
+        match self {
+            OutputLocale::En => '.',
+            OutputLocale::De => ',',
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum OutputError {
+    #[error("write csv: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("write json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("bincode: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("write output: {0}")]
+    Io(#[from] io::Error),
+    #[error("balance {value} doesn't fit in minor units with scale {scale}")]
+    MinorUnitsOverflow { value: Decimal, scale: u32 },
+    #[error("decimal separator '{0}' is the same as the csv delimiter")]
+    DelimiterCollision(char),
+    #[error("bincode output can't anonymize client ids")]
+    AnonymizedBincode,
+}
+
+impl OutputError {
+    /// Returns whether the write failed because the reader closed the pipe, e.g. `| head`.
+    pub fn is_broken_pipe(&self) -> bool {
+        let kind = match self {
+            OutputError::Csv(err) => match err.kind() {
+                csv::ErrorKind::Io(err) => Some(err.kind()),
+                _ => None,
+            },
+            OutputError::Json(err) => err.io_error_kind(),
+            OutputError::Bincode(err) => match err.as_ref() {
+                bincode::ErrorKind::Io(err) => Some(err.kind()),
+                _ => None,
+            },
+            OutputError::Io(err) => Some(err.kind()),
+            OutputError::MinorUnitsOverflow { .. }
+            | OutputError::DelimiterCollision(_)
+            | OutputError::AnonymizedBincode => None,
+        };
+
+        kind == Some(io::ErrorKind::BrokenPipe)
+    }
+}
+
+/// Byte order mark written before CSV output with `OutputConfig::bom`.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Writes the client states in the configured format.
+pub fn write<W, I>(mut writer: W, entries: I, config: &OutputConfig) -> Result<(), OutputError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = ProcessorStatusEntry>,
+{
+    if config.format == OutputFormat::Bincode {
+        // the client ids are stored as numbers
+        if config.anonymize_key.is_some() {
+            return Err(OutputError::AnonymizedBincode);
+        }
+        return write_bincode(writer, entries);
+    }
+
+    // a comma decimal separator would split every amount into two columns
+    let separator = config.locale.decimal_separator();
+    if config.format == OutputFormat::Csv && config.delimiter.unwrap_or(b',') == separator as u8 {
+        return Err(OutputError::DelimiterCollision(separator));
+    }
+
+    if config.bom && config.format == OutputFormat::Csv {
+        writer.write_all(UTF8_BOM)?;
+    }
+
+    let entries = entries
+        .into_iter()
+        .map(|mut entry| {
+            if config.state_hash {
+                entry.state_hash = Some(entry.compute_state_hash());
+            }
+            if let Some(scale) = config.minor_units_scale {
+                let convert = |value| {
+                    to_minor_units(value, scale)
+                        .ok_or(OutputError::MinorUnitsOverflow { value, scale })
+                };
+                entry.available = convert(entry.available)?;
+                entry.held = convert(entry.held)?;
+                entry.total = convert(entry.total)?;
+                entry.max_deposit = entry.max_deposit.map(convert).transpose()?;
+                entry.max_withdrawal = entry.max_withdrawal.map(convert).transpose()?;
+            } else {
+                if let Some(scale) = config.scale {
+                    map_amounts(&mut entry, |value| {
+                        value.round_dp_with_strategy(scale, MidpointAwayFromZero)
+                    });
+                }
+                match (config.trailing_zeros, config.scale) {
+                    (Some(TrailingZeros::Trim), _) => {
+                        map_amounts(&mut entry, |value| value.normalize())
+                    }
+                    (Some(TrailingZeros::Pad), Some(scale)) => {
+                        map_amounts(&mut entry, |mut value| {
+                            value.rescale(scale);
+                            value
+                        })
+                    }
+                    (Some(TrailingZeros::Pad), None) | (None, _) => {}
+                }
+            }
+            Ok(entry)
+        })
+        .collect::<Result<Vec<_>, OutputError>>()?;
+
+    if let Some(size) = config.group_by_range {
+        return write_rows(writer, aggregate::group_by_range(entries, size), config);
+    }
+
+    if config.group_by_locked {
+        return write_locked_groups(writer, entries, config);
+    }
+
+    let mut entries = entries;
+    match config.order_by {
+        Some(order_by) => order_by.sort(&mut entries),
+        // sort the clients by id to keep the output stable for diffing
+        None if matches!(config.format, OutputFormat::Json | OutputFormat::Ndjson) => {
+            entries.sort_by_key(|entry| entry.client)
+        }
+        None => {}
+    }
+
+    if let Some(top) = config.top {
+        return write_top(writer, entries, top, config);
+    }
+    write_rows(writer, client_rows(entries, config), config)
+}
+
+/// A client state in the bincode output.
+///
+/// The balances are stored as the 16 byte representation of `Decimal`, its serde implementation
+/// relies on self-describing formats.
+#[derive(Deserialize, Serialize)]
+struct BinaryEntry {
+    client: ClientID,
+    available: [u8; 16],
+    held: [u8; 16],
+    total: [u8; 16],
+    locked: bool,
+}
+
+/// Same as `write`, but stops without an error once the reader closes the pipe, since nobody
+/// is interested in the rest of the output then.
+pub fn write_until_closed<W, I>(
+    writer: W,
+    entries: I,
+    config: &OutputConfig,
+) -> Result<(), OutputError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = ProcessorStatusEntry>,
+{
+    match write(writer, entries, config) {
+        Err(err) if err.is_broken_pipe() => Ok(()),
+        result => result,
+    }
+}
+
+/// Replaces every amount of the entry, i.e. the balances and the largest deposit and withdrawal.
+fn map_amounts(entry: &mut ProcessorStatusEntry, f: impl Fn(Decimal) -> Decimal) {
+    entry.available = f(entry.available);
+    entry.held = f(entry.held);
+    entry.total = f(entry.total);
+    entry.max_deposit = entry.max_deposit.map(&f);
+    entry.max_withdrawal = entry.max_withdrawal.map(&f);
+}
+
+/// Writes the client states sorted by client id as a bincode encoded list.
+fn write_bincode<W, I>(writer: W, entries: I) -> Result<(), OutputError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = ProcessorStatusEntry>,
+{
+    let mut entries: Vec<_> = entries
+        .into_iter()
+        .map(|entry| BinaryEntry {
+            client: entry.client,
+            available: entry.available.serialize(),
+            held: entry.held.serialize(),
+            total: entry.total.serialize(),
+            locked: entry.locked,
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.client);
+
+    bincode::serialize_into(writer, &entries)?;
+    Ok(())
+}
+
+/// Reads client states written with `OutputFormat::Bincode`.
+///
+/// # Errors
+/// - `Bincode`: The input isn't a valid bincode output or can't be read
+pub fn read_bincode<R: io::Read>(reader: R) -> Result<Vec<ProcessorStatusEntry>, OutputError> {
+    let entries: Vec<BinaryEntry> = bincode::deserialize_from(reader)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            ProcessorStatusEntry::new(
+                entry.client,
+                Decimal::deserialize(entry.available),
+                Decimal::deserialize(entry.held),
+                Decimal::deserialize(entry.total),
+                entry.locked,
+            )
+        })
+        .collect())
+}
+
+/// A client row as written, with the client id optionally zero-padded and localized balances.
+#[derive(Serialize)]
+struct ClientRow {
+    client: ClientColumn,
+    available: BalanceColumn,
+    held: BalanceColumn,
+    total: BalanceColumn,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deposits_retained: Option<usize>,
+    /// Present in verbose mode, empty for accounts that aren't locked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locked_by_tx: Option<Option<TransactionID>>,
+    /// Present in verbose mode, empty for clients without deposits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_deposit: Option<Option<BalanceColumn>>,
+    /// Present in verbose mode, empty for clients without withdrawals.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_withdrawal: Option<Option<BalanceColumn>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dispute_ratio_exceeded: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    negative_available: Option<bool>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ClientColumn {
+    Id(ClientID),
+    Padded(String),
+    Pseudonym(String),
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BalanceColumn {
+    Decimal(Decimal),
+    Localized(String),
+}
+
+impl BalanceColumn {
+    fn new(value: Decimal, locale: OutputLocale) -> Self {
+        match locale {
+            OutputLocale::En => BalanceColumn::Decimal(value),
+            OutputLocale::De => BalanceColumn::Localized(
+                value
+                    .to_string()
+                    .replace('.', &locale.decimal_separator().to_string()),
+            ),
+        }
+    }
+}
+
+/// Converts the entries to the rows to write, padding the client ids and localizing the balances.
+fn client_rows(entries: Vec<ProcessorStatusEntry>, config: &OutputConfig) -> Vec<ClientRow> {
+    entries
+        .into_iter()
+        .map(|entry| ClientRow {
+            client: match (&config.anonymize_key, config.client_id_width) {
+                (Some(key), _) => ClientColumn::Pseudonym(entry.client.pseudonym(key.as_bytes())),
+                (None, Some(width)) => ClientColumn::Padded(entry.client.to_padded_string(width)),
+                (None, None) => ClientColumn::Id(entry.client),
+            },
+            available: BalanceColumn::new(entry.available, config.locale),
+            held: BalanceColumn::new(entry.held, config.locale),
+            total: BalanceColumn::new(entry.total, config.locale),
+            locked: entry.locked,
+            state_hash: entry.state_hash,
+            deposits_retained: entry.deposits_retained,
+            locked_by_tx: config.verbose.then_some(entry.locked_by_tx),
+            max_deposit: config.verbose.then(|| {
+                entry
+                    .max_deposit
+                    .map(|value| BalanceColumn::new(value, config.locale))
+            }),
+            max_withdrawal: config.verbose.then(|| {
+                entry
+                    .max_withdrawal
+                    .map(|value| BalanceColumn::new(value, config.locale))
+            }),
+            dispute_ratio_exceeded: entry.dispute_ratio_exceeded,
+            negative_available: config
+                .flag_negative_available
+                .then(|| entry.available.is_sign_negative() && !entry.available.is_zero()),
+        })
+        .collect()
+}
+
+/// The clients split by their locked status, for review workflows.
+#[derive(Serialize)]
+struct LockedGroups {
+    locked: Vec<ClientRow>,
+    active: Vec<ClientRow>,
+}
+
+/// Writes the locked clients followed by the active ones, each group sorted by client id.
+///
+/// CSV output has a `# locked` and an `# active` label line before each block, separated by a
+/// blank line. JSON output is an object with a `locked` and an `active` array.
+fn write_locked_groups<W>(
+    mut writer: W,
+    entries: Vec<ProcessorStatusEntry>,
+    config: &OutputConfig,
+) -> Result<(), OutputError>
+where
+    W: io::Write,
+{
+    let (mut locked, mut active): (Vec<_>, Vec<_>) =
+        entries.into_iter().partition(|entry| entry.locked);
+    locked.sort_by_key(|entry| entry.client);
+    active.sort_by_key(|entry| entry.client);
+    let (locked, active) = (client_rows(locked, config), client_rows(active, config));
+
+    match config.format {
+        OutputFormat::Csv => {
+            writeln!(writer, "# locked")?;
+            write_csv(&mut writer, locked, config)?;
+            writeln!(writer)?;
+            writeln!(writer, "# active")?;
+            write_csv(&mut writer, active, config)
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &LockedGroups { locked, active })?;
+            writeln!(writer)?;
+            writer.flush()?;
+            Ok(())
+        }
+        // every object has the `locked` field, so the groups are just written one after another
+        OutputFormat::Ndjson => write_ndjson(writer, locked.into_iter().chain(active)),
+        OutputFormat::Bincode => unreachable!("bincode output is written by `write_bincode`"),
+    }
+}
+
+/// The first clients and the summed balances of the remaining ones.
+#[derive(Serialize)]
+struct TopClients {
+    top: Vec<ClientRow>,
+    others: Aggregate,
+}
+
+/// Writes the first `top` clients followed by the summed balances of the remaining ones.
+///
+/// CSV output has the aggregate in a `# others` group after the client rows, separated by a blank
+/// line. JSON output is an object with a `top` array and an `others` object, NDJSON output ends
+/// with the aggregate on its own line.
+fn write_top<W>(
+    mut writer: W,
+    mut entries: Vec<ProcessorStatusEntry>,
+    top: NonZeroUsize,
+    config: &OutputConfig,
+) -> Result<(), OutputError>
+where
+    W: io::Write,
+{
+    let mut others = Aggregate::default();
+    for entry in entries.split_off(top.get().min(entries.len())) {
+        others.add(&entry);
+    }
+    let top = client_rows(entries, config);
+
+    match config.format {
+        OutputFormat::Csv => {
+            write_csv(&mut writer, top, config)?;
+            writeln!(writer)?;
+            writeln!(writer, "# others")?;
+            write_csv(&mut writer, [others], config)
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &TopClients { top, others })?;
+            writeln!(writer)?;
+            writer.flush()?;
+            Ok(())
+        }
+        OutputFormat::Ndjson => {
+            write_ndjson(&mut writer, top)?;
+            write_ndjson(writer, [others])
+        }
+        OutputFormat::Bincode => unreachable!("bincode output is written by `write_bincode`"),
+    }
+}
+
+/// Writes the rows in the configured format.
+fn write_rows<W, I, T>(writer: W, rows: I, config: &OutputConfig) -> Result<(), OutputError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = T>,
+    T: Serialize,
+{
+    match config.format {
+        OutputFormat::Csv => write_csv(writer, rows, config),
+        OutputFormat::Json => write_json(writer, rows),
+        OutputFormat::Ndjson => write_ndjson(writer, rows),
+        OutputFormat::Bincode => unreachable!("bincode output is written by `write_bincode`"),
+    }
+}
+
+/// Writes the rows as CSV.
+fn write_csv<W, I, T>(writer: W, rows: I, config: &OutputConfig) -> Result<(), OutputError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = T>,
+    T: Serialize,
+{
+    let mut csv_writer = WriterBuilder::new()
+        .has_headers(!config.omit_header)
+        .delimiter(config.delimiter.unwrap_or(b','))
+        .from_writer(writer);
+
+    for row in rows {
+        csv_writer.serialize(row)?;
+    }
+    csv_writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the rows as a pretty-printed JSON array.
+///
+/// The keys of every object are always in the same order as the struct fields (for clients:
+/// client, available, held, total, locked), so sorted rows are byte-for-byte stable for diffing.
+fn write_json<W, I, T>(mut writer: W, rows: I) -> Result<(), OutputError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = T>,
+    T: Serialize,
+{
+    let rows = rows.into_iter().collect::<Vec<_>>();
+
+    serde_json::to_writer_pretty(&mut writer, &rows)?;
+    writeln!(writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes every row as a compact JSON object on its own line.
+fn write_ndjson<W, I, T>(mut writer: W, rows: I) -> Result<(), OutputError>
+where
+    W: io::Write,
+    I: IntoIterator<Item = T>,
+    T: Serialize,
+{
+    for row in rows {
+        serde_json::to_writer(&mut writer, &row)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        ids::{ClientID, TransactionID},
+        processor::{Processor, ProcessorConfig},
+        transaction::{Chargeback, Deposit, Dispute, Transaction, Withdrawal},
+    };
+
+    use super::*;
+
+    fn write_csv_to_string(config: &OutputConfig) -> String {
+        let entries = [ProcessorStatusEntry::new(
+            ClientID::new(1),
+            dec!(1.5),
+            dec!(0),
+            dec!(1.5),
+            false,
+        )];
+
+        let mut output = Vec::new();
+        write(&mut output, entries, config).expect("write should succeed");
+        String::from_utf8(output).expect("output should be utf-8")
+    }
+
+    #[test]
+    fn test_write_csv_with_header() {
+        assert_eq!(
+            write_csv_to_string(&OutputConfig::default()),
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_csv_without_header() {
+        let config = OutputConfig {
+            omit_header: true,
+            ..Default::default()
+        };
+        assert_eq!(write_csv_to_string(&config), "1,1.5,0,1.5,false\n");
+    }
+
+    #[test]
+    fn test_write_csv_with_state_hash() {
+        let config = OutputConfig {
+            state_hash: true,
+            ..Default::default()
+        };
+        let output = write_csv_to_string(&config);
+
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next(),
+            Some("client,available,held,total,locked,state_hash")
+        );
+        let row = lines.next().unwrap();
+        assert!(row.starts_with("1,1.5,0,1.5,false,"));
+        assert_eq!(row.rsplit(',').next().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn test_write_csv_with_bom() {
+        let config = OutputConfig {
+            bom: true,
+            ..Default::default()
+        };
+        let output = write_csv_to_string(&config);
+        assert!(output.starts_with("\u{feff}client,available,held,total,locked\n"));
+
+        // JSON has no BOM
+        let config = OutputConfig {
+            bom: true,
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+        assert!(write_csv_to_string(&config).starts_with('['));
+    }
+
+    #[test]
+    fn test_write_csv_minor_units() {
+        let config = OutputConfig {
+            minor_units_scale: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            write_csv_to_string(&config),
+            "client,available,held,total,locked\n1,150,0,150,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_csv_scale() {
+        // the balances are summed at full precision and only rounded when written
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for tx in 1..=5 {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    client: ClientID::new(1),
+                    tx: TransactionID::new(tx),
+                    amount: dec!(0.00003).try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+
+        for (scale, total) in [(Some(4), "0.0002"), (None, "0.00015")] {
+            let config = OutputConfig {
+                scale,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            write(&mut output, processor.status_entries(), &config).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!("client,available,held,total,locked\n1,{total},0,{total},false\n")
+            );
+        }
+    }
+
+    #[test]
+    fn test_trailing_zeros() {
+        let entry =
+            ProcessorStatusEntry::new(ClientID::new(1), dec!(5.1000), dec!(0), dec!(5.1000), false);
+
+        for (trailing_zeros, scale, expected) in [
+            (None, Some(4), "5.1000,0,5.1000"),
+            (Some(TrailingZeros::Trim), Some(4), "5.1,0,5.1"),
+            (Some(TrailingZeros::Pad), Some(4), "5.1000,0.0000,5.1000"),
+            (Some(TrailingZeros::Pad), Some(2), "5.10,0.00,5.10"),
+            (Some(TrailingZeros::Pad), None, "5.1000,0,5.1000"),
+        ] {
+            let config = OutputConfig {
+                scale,
+                trailing_zeros,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            write(&mut output, [entry.clone()], &config).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!("client,available,held,total,locked\n1,{expected},false\n"),
+                "{trailing_zeros:?} {scale:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_csv_and_json_are_consistent() {
+        let entries =
+            [(2, dec!(1.5), true), (1, dec!(20.25), false)].map(|(client, total, locked)| {
+                ProcessorStatusEntry::new(ClientID::new(client), total, dec!(0), total, locked)
+            });
+
+        let mut csv = Vec::new();
+        write(&mut csv, entries.clone(), &OutputConfig::default()).unwrap();
+        let mut json = Vec::new();
+        let config = OutputConfig {
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+        write(&mut json, entries.clone(), &config).unwrap();
+
+        let mut from_csv = csv::Reader::from_reader(csv.as_slice())
+            .deserialize()
+            .collect::<Result<Vec<ProcessorStatusEntry>, _>>()
+            .unwrap();
+        from_csv.sort_by_key(|entry| entry.client);
+        let from_json: Vec<ProcessorStatusEntry> = serde_json::from_slice(&json).unwrap();
+
+        assert_eq!(from_csv, from_json);
+        assert_eq!(from_json.len(), entries.len());
+    }
+
+    #[test]
+    fn test_write_top() {
+        let entries = [
+            (1, dec!(5)),
+            (2, dec!(20)),
+            (3, dec!(1)),
+            (4, dec!(20)),
+            (5, dec!(3)),
+        ]
+        .map(|(client, total)| {
+            ProcessorStatusEntry::new(ClientID::new(client), total, dec!(0), total, client == 3)
+        });
+        let config = OutputConfig {
+            order_by: Some(OrderBy::TotalDesc),
+            top: NonZeroUsize::new(2),
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        write(&mut output, entries, &config).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+             2,20,0,20,false\n\
+             4,20,0,20,false\n\
+             \n\
+             # others\n\
+             clients,available,held,total,locked\n\
+             3,9,0,9,1\n"
+        );
+    }
+
+    #[test]
+    fn test_order_by_ties() {
+        // equal totals at different scales, first seen in different orders
+        let balances = [
+            (3, dec!(5), dec!(5.00)),
+            (1, dec!(5.0), dec!(5.0)),
+            (4, dec!(2), dec!(7)),
+            (2, dec!(5.00), dec!(5)),
+        ];
+        let entries = |order: &[usize]| {
+            order
+                .iter()
+                .map(|index| {
+                    let (client, available, total) = balances[*index];
+                    ProcessorStatusEntry::new(
+                        ClientID::new(client),
+                        available,
+                        total - available,
+                        total,
+                        false,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (order_by, expected) in [
+            (OrderBy::Client, [1, 2, 3, 4]),
+            (OrderBy::TotalDesc, [4, 1, 2, 3]),
+            (OrderBy::TotalAsc, [1, 2, 3, 4]),
+            (OrderBy::AvailableDesc, [1, 2, 3, 4]),
+            (OrderBy::AvailableAsc, [4, 1, 2, 3]),
+            (OrderBy::HeldDesc, [4, 1, 2, 3]),
+            (OrderBy::HeldAsc, [1, 2, 3, 4]),
+        ] {
+            for order in [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1]] {
+                let mut entries = entries(&order);
+                order_by.sort(&mut entries);
+                let clients: Vec<_> = entries.iter().map(|entry| entry.client).collect();
+                assert_eq!(
+                    clients,
+                    expected.map(ClientID::new),
+                    "{order_by:?} {order:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_ndjson() {
+        let entries = [2, 1].map(|client| {
+            ProcessorStatusEntry::new(
+                ClientID::new(client),
+                dec!(1.5),
+                dec!(0.25),
+                dec!(1.75),
+                client == 2,
+            )
+        });
+        let config = OutputConfig {
+            format: OutputFormat::Ndjson,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write(&mut output, entries.clone(), &config).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"client":1,"available":"1.5","held":"0.25","total":"1.75","locked":false}"#
+        );
+        let parsed: Vec<ProcessorStatusEntry> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed, [entries[1].clone(), entries[0].clone()]);
+    }
+
+    #[test]
+    fn test_write_json_is_stable() {
+        let deposits = [(3, dec!(3.5)), (1, dec!(1)), (2, dec!(20.25))];
+
+        let write_json_to_string = |deposits: &[(u16, rust_decimal::Decimal)]| {
+            let mut processor = Processor::new(ProcessorConfig::default());
+            for (tx, (client, amount)) in deposits.iter().enumerate() {
+                processor
+                    .handle_transaction(Transaction::Deposit(Deposit {
+                        client: ClientID::new(*client),
+                        tx: TransactionID::new(tx as u32),
+                        amount: (*amount).try_into().unwrap(),
+                    }))
+                    .unwrap();
+            }
+
+            let config = OutputConfig {
+                format: OutputFormat::Json,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            write(&mut output, processor.status_entries(), &config).expect("write should succeed");
+            String::from_utf8(output).expect("output should be utf-8")
+        };
+
+        let json = write_json_to_string(&deposits);
+        let mut reversed = deposits;
+        reversed.reverse();
+        assert_eq!(json, write_json_to_string(&reversed));
+
+        let expected = r#"[
+  {
+    "client": 1,
+    "available": "1",
+    "held": "0",
+    "total": "1",
+    "locked": false
+  },
+  {
+    "client": 2,
+    "available": "20.25",
+    "held": "0",
+    "total": "20.25",
+    "locked": false
+  },
+  {
+    "client": 3,
+    "available": "3.5",
+    "held": "0",
+    "total": "3.5",
+    "locked": false
+  }
+]
+"#;
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn test_write_csv_group_by_range() {
+        let entries = [(5, dec!(1.5)), (1200, dec!(2)), (1999, dec!(3))].map(|(client, total)| {
+            ProcessorStatusEntry::new(ClientID::new(client), total, dec!(0), total, false)
+        });
+
+        let config = OutputConfig {
+            group_by_range: NonZeroU32::new(1000),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write(&mut output, entries, &config).expect("write should succeed");
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "range_start,range_end,clients,available,held,total,locked\n0,999,1,1.5,0,1.5,0\n1000,1999,2,5,0,5,0\n"
+        );
+    }
+
+    #[test]
+    fn test_group_by_locked() {
+        let entries = [(3, false), (2, true), (1, false), (4, true)].map(|(client, locked)| {
+            ProcessorStatusEntry::new(ClientID::new(client), dec!(1), dec!(0), dec!(1), locked)
+        });
+
+        let config = OutputConfig {
+            group_by_locked: true,
+            ..Default::default()
+        };
+        let mut csv = Vec::new();
+        write(&mut csv, entries.clone(), &config).expect("write should succeed");
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "# locked\nclient,available,held,total,locked\n2,1,0,1,true\n4,1,0,1,true\n\n\
+             # active\nclient,available,held,total,locked\n1,1,0,1,false\n3,1,0,1,false\n"
+        );
+
+        let config = OutputConfig {
+            format: OutputFormat::Json,
+            group_by_locked: true,
+            ..Default::default()
+        };
+        let mut json = Vec::new();
+        write(&mut json, entries, &config).expect("write should succeed");
+        let groups: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        let clients = |group: &str| {
+            groups[group]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|entry| entry["client"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clients("locked"), vec![2, 4]);
+        assert_eq!(clients("active"), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_client_id_width() {
+        let entries = [ProcessorStatusEntry::new(
+            ClientID::new(42),
+            dec!(1),
+            dec!(0),
+            dec!(1),
+            false,
+        )];
+
+        let config = OutputConfig {
+            client_id_width: Some(5),
+            ..Default::default()
+        };
+        let mut csv = Vec::new();
+        write(&mut csv, entries.clone(), &config).expect("write should succeed");
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,available,held,total,locked\n00042,1,0,1,false\n"
+        );
+
+        let config = OutputConfig {
+            format: OutputFormat::Json,
+            client_id_width: Some(5),
+            ..Default::default()
+        };
+        let mut json = Vec::new();
+        write(&mut json, entries, &config).expect("write should succeed");
+        let rows: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(rows[0]["client"], "00042");
+    }
+
+    #[test]
+    fn test_anonymize_clients() {
+        let entry = |client| {
+            ProcessorStatusEntry::new(ClientID::new(client), dec!(1), dec!(0), dec!(1), false)
+        };
+        let config = |key: &str| OutputConfig {
+            format: OutputFormat::Json,
+            anonymize_key: Some(key.to_string()),
+            ..Default::default()
+        };
+        let clients = |config: &OutputConfig| {
+            let mut json = Vec::new();
+            write(&mut json, [entry(1), entry(2), entry(42)], config).unwrap();
+            let rows: serde_json::Value = serde_json::from_slice(&json).unwrap();
+            rows.as_array()
+                .unwrap()
+                .iter()
+                .map(|row| row["client"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // the same key gives the same pseudonyms, another key different ones
+        let pseudonyms = clients(&config("secret"));
+        assert_eq!(pseudonyms, clients(&config("secret")));
+        assert_eq!(pseudonyms[2], ClientID::new(42).pseudonym(b"secret"));
+        assert!(!pseudonyms.contains(&"42".to_string()));
+        assert!(clients(&config("other"))
+            .iter()
+            .all(|pseudonym| !pseudonyms.contains(pseudonym)));
+
+        // every possible client id gets its own pseudonym under this key, which isn't
+        // guaranteed for every key
+        let all: HashSet<_> = (0..=u16::MAX)
+            .map(|client| ClientID::new(client).pseudonym(b"secret"))
+            .collect();
+        assert_eq!(all.len(), usize::from(u16::MAX) + 1);
+
+        let config = OutputConfig {
+            format: OutputFormat::Bincode,
+            ..config("secret")
+        };
+        assert!(matches!(
+            write(Vec::new(), [entry(1)], &config),
+            Err(OutputError::AnonymizedBincode)
+        ));
+    }
+
+    #[test]
+    fn test_output_locale() {
+        let config = OutputConfig {
+            delimiter: Some(b'\t'),
+            locale: OutputLocale::De,
+            ..Default::default()
+        };
+        assert_eq!(
+            write_csv_to_string(&config),
+            "client\tavailable\theld\ttotal\tlocked\n1\t1,5\t0\t1,5\tfalse\n"
+        );
+
+        // the decimal comma can't be used with the default delimiter
+        let config = OutputConfig {
+            locale: OutputLocale::De,
+            ..Default::default()
+        };
+        assert!(matches!(
+            write(Vec::new(), [], &config),
+            Err(OutputError::DelimiterCollision(','))
+        ));
+    }
+
+    #[test]
+    fn test_verbose_locked_by_tx() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for (client, tx) in [(1, 1), (2, 2)] {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    client: ClientID::new(client),
+                    tx: TransactionID::new(tx),
+                    amount: dec!(1).try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+        processor
+            .handle_transaction(Transaction::Dispute(Dispute {
+                client: ClientID::new(2),
+                tx: TransactionID::new(2),
+                amount: None,
+                reason: None,
+            }))
+            .unwrap();
+        processor
+            .handle_transaction(Transaction::Chargeback(Chargeback {
+                client: ClientID::new(2),
+                tx: TransactionID::new(2),
+            }))
+            .unwrap();
+
+        let config = OutputConfig {
+            verbose: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write(&mut output, processor.verbose_status_entries(), &config).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,deposits_retained,locked_by_tx,max_deposit,max_withdrawal\n\
+             1,1,0,1,false,1,,1,\n\
+             2,0,0,0,true,1,2,1,\n"
+        );
+    }
+
+    #[test]
+    fn test_flag_negative_available() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        let client = ClientID::new(1);
+        for transaction in [
+            Transaction::Deposit(Deposit {
+                client,
+                tx: TransactionID::new(1),
+                amount: dec!(10).try_into().unwrap(),
+            }),
+            Transaction::Withdrawal(Withdrawal {
+                client,
+                tx: TransactionID::new(2),
+                amount: dec!(8).try_into().unwrap(),
+            }),
+            // the withdrawn funds can't be held anymore
+            Transaction::Dispute(Dispute {
+                client,
+                tx: TransactionID::new(1),
+                amount: None,
+                reason: None,
+            }),
+            Transaction::Deposit(Deposit {
+                client: ClientID::new(2),
+                tx: TransactionID::new(3),
+                amount: dec!(1).try_into().unwrap(),
+            }),
+        ] {
+            processor.handle_transaction(transaction).unwrap();
+        }
+
+        let config = OutputConfig {
+            flag_negative_available: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write(&mut output, processor.status_entries(), &config).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,negative_available\n\
+             1,-8,10,2,false,true\n\
+             2,1,0,1,false,false\n"
+        );
+    }
+
+    #[test]
+    fn test_bincode_round_trip() {
+        let entry = |client, total, locked| {
+            ProcessorStatusEntry::new(ClientID::new(client), total, dec!(0), total, locked)
+        };
+        let entries = vec![entry(2, dec!(-1.5), true), entry(1, dec!(0.0001), false)];
+
+        let config = OutputConfig {
+            format: OutputFormat::Bincode,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write(&mut output, entries.clone(), &config).unwrap();
+
+        let read = read_bincode(output.as_slice()).unwrap();
+        assert_eq!(read, vec![entries[1].clone(), entries[0].clone()]);
+        // the scale survives the round trip
+        assert_eq!(read[0].total.to_string(), "0.0001");
+        assert!(read_bincode(&output[..output.len() - 1]).is_err());
+    }
+
+    /// A pipe whose reader stops reading after `capacity` bytes.
+    struct ClosingPipe {
+        capacity: usize,
+    }
+
+    impl io::Write for ClosingPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self.capacity {
+                0 => Err(io::ErrorKind::BrokenPipe.into()),
+                capacity => {
+                    let written = buf.len().min(capacity);
+                    self.capacity -= written;
+                    Ok(written)
+                }
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_until_closed() {
+        let entries: Vec<_> = (1..=10_000)
+            .map(|client| {
+                ProcessorStatusEntry::new(
+                    ClientID::new(client),
+                    dec!(1.5),
+                    dec!(0),
+                    dec!(1.5),
+                    false,
+                )
+            })
+            .collect();
+
+        for format in [
+            OutputFormat::Csv,
+            OutputFormat::Json,
+            OutputFormat::Ndjson,
+            OutputFormat::Bincode,
+        ] {
+            let config = OutputConfig {
+                format,
+                ..Default::default()
+            };
+            let err = write(ClosingPipe { capacity: 100 }, entries.clone(), &config)
+                .expect_err("the pipe is closed");
+            assert!(err.is_broken_pipe(), "{format:?}: {err}");
+            write_until_closed(ClosingPipe { capacity: 100 }, entries.clone(), &config)
+                .expect("a closed pipe ends the output");
+        }
+
+        // other errors are still reported
+        let config = OutputConfig {
+            minor_units_scale: Some(2),
+            ..Default::default()
+        };
+        let entry = ProcessorStatusEntry {
+            total: Decimal::MAX,
+            ..entries[0].clone()
+        };
+        let err = write_until_closed(ClosingPipe { capacity: 100 }, [entry], &config)
+            .expect_err("overflow should fail");
+        assert!(!err.is_broken_pipe());
+    }
+}
@@ -1,6 +1,6 @@
 use derive_more::{Display, Into};
-use rust_decimal::Decimal;
-use serde::Deserialize;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// A wrapper type around `Decimal` that guarantees the value is strictly positive (greater than zero).
@@ -10,12 +10,13 @@ use thiserror::Error;
 ///
 /// # Examples
 /// ```
+/// # use toy_stream_processor::positive_decimal::PositiveDecimal;
 /// use rust_decimal_macros::dec;
 /// let valid = PositiveDecimal::new(dec!(10.5)).unwrap();
 /// assert!(PositiveDecimal::new(dec!(-1.0)).is_err());
 /// assert!(PositiveDecimal::new(dec!(0.0)).is_err());
 /// ```
-#[derive(Clone, Copy, Debug, Display, Eq, Hash, Into, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Display, Eq, Hash, Into, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct PositiveDecimal(Decimal);
 
 #[derive(Debug, Error)]
@@ -24,6 +25,8 @@ pub enum PositiveDecimalError {
     NegativeAmount,
     #[error("zero amount")]
     ZeroAmount,
+    #[error("amount rounds to zero at {scale} decimal places")]
+    RoundsToZero { scale: u32 },
 }
 
 impl PositiveDecimal {
@@ -38,6 +41,17 @@ impl PositiveDecimal {
 
         Ok(PositiveDecimal(value))
     }
+
+    /// Rounds to `scale` decimal places, with halves rounded away from zero.
+    ///
+    /// # Errors
+    /// - `RoundsToZero`: The amount is too small to be represented at this scale, e.g. `0.00001` at scale 4
+    pub fn round(self, scale: u32) -> Result<Self, PositiveDecimalError> {
+        let rounded = self
+            .0
+            .round_dp_with_strategy(scale, RoundingStrategy::MidpointAwayFromZero);
+        PositiveDecimal::new(rounded).map_err(|_| PositiveDecimalError::RoundsToZero { scale })
+    }
 }
 
 impl<'de> Deserialize<'de> for PositiveDecimal {
@@ -74,4 +88,20 @@ mod tests {
         assert!(PositiveDecimal::new(dec!(-5.0)).is_err());
         assert!(PositiveDecimal::new(dec!(0.0)).is_err());
     }
+
+    #[test]
+    fn positive_decimal_round() {
+        let amount = PositiveDecimal::new(dec!(1.23456)).unwrap();
+        assert_eq!(
+            amount.round(4).unwrap(),
+            PositiveDecimal::new(dec!(1.2346)).unwrap()
+        );
+
+        let tiny = PositiveDecimal::new(dec!(0.00001)).unwrap();
+        assert!(matches!(
+            tiny.round(4),
+            Err(PositiveDecimalError::RoundsToZero { scale: 4 })
+        ));
+        assert_eq!(tiny.round(5).unwrap(), tiny);
+    }
 }
@@ -1,80 +1,958 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::Display,
+    sync::mpsc::{SendError, Sender, SyncSender},
+    time::{Duration, Instant},
+};
 
+use indexmap::IndexMap;
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
 
 use crate::{
-    client::{Client, ProcessingError},
-    ids::ClientID,
+    client::{Client, ClientConfig, ClientOp, ProcessingError},
+    export::{ExportError, FlatClientState},
+    ids::{ClientID, TransactionID},
+    snapshot::Snapshot,
     transaction::Transaction,
 };
 
 /// A transaction processor that manages client accounts and handles their transactions.
+#[derive(Debug)]
 pub struct Processor {
-    clients: HashMap<ClientID, Client>,
+    /// Client accounts in the order they were first seen.
+    ///
+    /// Compared to a `HashMap`, an `IndexMap` stores an extra index per client and a lookup goes
+    /// through one more indirection, which is negligible next to the size of a `Client`. In return
+    /// iteration is deterministic and faster, since the entries are stored contiguously.
+    clients: IndexMap<ClientID, Client>,
+    /// Idempotency keys of all transactions seen so far, when enabled in the config.
+    seen_keys: HashSet<String>,
+    /// Number of transactions rejected because their client isn't on the roster.
+    off_roster: usize,
+    /// Number of disputes of already disputed deposits that were tolerated as no-ops.
+    redundant_disputes: usize,
+    /// Resolves and chargebacks rejected because their deposit was never disputed, until a
+    /// dispute of the deposit shows that they arrived too early.
+    premature: PrematureWindow,
+    /// Number of resolves and chargebacks that arrived before their dispute.
+    ordering_anomalies: BTreeMap<OrderingAnomaly, usize>,
+    /// Time spent per transaction type, when enabled in the config.
+    profile: Option<Profile>,
+    /// Receives the outcome of every transaction, see `with_event_sender`.
+    events: Option<EventSender>,
+    config: ProcessorConfig,
+    /// Clock the time of `tx_timeout` is measured with, a fake one in tests.
+    clock: fn() -> Instant,
+}
+
+/// Options controlling how the `Processor` handles transactions.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessorConfig {
+    /// Config used for every client account created by the processor.
+    pub client: ClientConfig,
+    /// Drop transactions whose idempotency key was already seen, see `handle_keyed_transaction`.
+    pub use_idempotency_keys: bool,
+    /// Only these clients are accepted, transactions for other clients are rejected before
+    /// their account is created.
+    pub roster: Option<HashSet<ClientID>>,
+    /// Accounts must be opened by an `open` transaction, transactions for clients without an
+    /// account are rejected instead of creating it.
+    pub require_open_account: bool,
+    /// Measure the time spent applying transactions per type, see `Processor::profile`.
+    pub profile: bool,
+    /// A dispute of an already disputed deposit is a no-op instead of an `AlreadyDisputed`
+    /// rejection, see `Processor::redundant_disputes`.
+    pub tolerate_redundant_disputes: bool,
+    /// Reject transactions that took longer than this to apply with `TimedOut`, undoing them.
+    ///
+    /// A watchdog against pathologically slow operations, not a hard limit: the operation can't be
+    /// interrupted, so it runs to completion and is only undone afterwards, and a transaction that
+    /// never finishes still hangs the processor. The time is wall-clock time, so the outcome can
+    /// depend on the load of the machine.
+    pub tx_timeout: Option<Duration>,
 }
 
+/// Most clients a processor can have, since client ids are `u16`.
+const MAX_CLIENTS: usize = u16::MAX as usize + 1;
+
+/// Number of premature resolves and chargebacks remembered until their dispute arrives.
+const PREMATURE_WINDOW: usize = 100_000;
+
 impl Processor {
-    pub fn new() -> Self {
+    pub fn new(config: ProcessorConfig) -> Self {
+        Self::with_capacity(config, 0)
+    }
+
+    /// Creates a processor with room for about `clients` clients, so the client map isn't rehashed
+    /// while it grows. The estimate is capped at the number of possible client ids.
+    pub fn with_capacity(config: ProcessorConfig, clients: usize) -> Self {
+        Self {
+            clients: IndexMap::with_capacity(clients.min(MAX_CLIENTS)),
+            seen_keys: HashSet::new(),
+            off_roster: 0,
+            redundant_disputes: 0,
+            premature: PrematureWindow::default(),
+            ordering_anomalies: BTreeMap::new(),
+            profile: config.profile.then(Profile::new),
+            events: None,
+            config,
+            clock: Instant::now,
+        }
+    }
+
+    /// Creates a processor from a snapshot taken by `snapshot`.
+    ///
+    /// The clients are considered to be seen in the order of the snapshot, i.e. sorted by id.
+    pub fn restore(snapshot: &Snapshot, config: ProcessorConfig) -> Self {
+        let clients = snapshot
+            .clients
+            .iter()
+            .map(|client| (client.client, Client::restore(client, config.client)))
+            .collect();
+
         Self {
-            clients: HashMap::new(),
+            clients,
+            seen_keys: HashSet::new(),
+            off_roster: 0,
+            redundant_disputes: 0,
+            premature: PrematureWindow::default(),
+            ordering_anomalies: BTreeMap::new(),
+            profile: config.profile.then(Profile::new),
+            events: None,
+            config,
+            clock: Instant::now,
+        }
+    }
+
+    /// Pushes a `ProcessorEvent` with the outcome of every transaction handled from now on into
+    /// the channel, for a consumer draining it concurrently.
+    ///
+    /// Events are sent in order and blocking, none are dropped. With a bounded `sync_channel` a
+    /// slow consumer holds up processing once the channel is full, an unbounded `channel` buffers
+    /// events without limit instead. Once the receiver is dropped no more events are sent.
+    pub fn with_event_sender(mut self, sender: impl Into<EventSender>) -> Self {
+        self.events = Some(sender.into());
+        self
+    }
+
+    /// Restores a processor from a snapshot and applies further transactions to it.
+    ///
+    /// Returns the final processor and the outcome of every transaction, in order.
+    pub fn replay<I>(
+        snapshot: &Snapshot,
+        config: ProcessorConfig,
+        transactions: I,
+    ) -> (Self, Vec<Result<(), ProcessingError>>)
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut processor = Self::restore(snapshot, config);
+        let outcomes = transactions
+            .into_iter()
+            .map(|transaction| processor.handle_transaction(transaction))
+            .collect();
+
+        (processor, outcomes)
+    }
+
+    /// Moves the clients of another processor into this one, e.g. of a shard processed on another
+    /// thread. The merged clients are considered seen after the existing ones.
+    ///
+    /// The config and event sender of this processor are kept, the seen idempotency keys, roster
+    /// rejections, redundant disputes, ordering anomalies and profile of the other one are added.
+    ///
+    /// # Errors
+    /// - `OverlappingClient`: A client is in both processors, nothing is merged
+    pub fn merge(&mut self, other: Processor) -> Result<(), MergeError> {
+        if let Some(client) = other
+            .clients
+            .keys()
+            .find(|client| self.clients.contains_key(*client))
+        {
+            return Err(MergeError::OverlappingClient(*client));
+        }
+
+        self.clients.extend(other.clients);
+        self.seen_keys.extend(other.seen_keys);
+        self.off_roster += other.off_roster;
+        self.redundant_disputes += other.redundant_disputes;
+        self.premature.extend(other.premature);
+        for (anomaly, count) in other.ordering_anomalies {
+            *self.ordering_anomalies.entry(anomaly).or_default() += count;
+        }
+        if let (Some(profile), Some(other)) = (&mut self.profile, other.profile) {
+            for (type_name, nanos) in other.nanos {
+                *profile.nanos.entry(type_name).or_default() += nanos;
+            }
         }
+
+        Ok(())
+    }
+
+    /// Captures the complete state of all client accounts, sorted by client id.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut clients = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| client.snapshot(*client_id))
+            .collect::<Vec<_>>();
+        clients.sort_by_key(|client| client.client);
+
+        Snapshot { clients }
     }
 
-    /// Processes a single transaction for a client, creating the client account if it doesn't exist.
+    /// Processes a single transaction for a client, creating the client account if it doesn't exist
+    /// (unless accounts must be opened explicitly, see `ProcessorConfig::require_open_account`).
     /// Returns an error if the transaction fails to be processed.
     pub fn handle_transaction(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
-        let client = self.clients.entry(transaction.client()).or_default();
+        if self.profile.is_none() && self.events.is_none() {
+            return self.apply_transaction(transaction);
+        }
 
-        match transaction {
-            Transaction::Deposit(deposit) => client.deposit(deposit.tx, deposit.amount),
-            Transaction::Withdrawal(withdrawal) => {
-                client.withdrawal(withdrawal.tx, withdrawal.amount)
+        let (client, tx, type_name) = (
+            transaction.client(),
+            transaction.tx(),
+            transaction.type_name(),
+        );
+        let start = Instant::now();
+        let result = self.apply_transaction(transaction);
+        if let Some(profile) = &mut self.profile {
+            profile.record(type_name, start.elapsed());
+        }
+        self.send_event(ProcessorEvent {
+            client,
+            tx,
+            type_name,
+            result,
+        });
+
+        result
+    }
+
+    /// Sends an event if there's a sender, dropping the sender once the receiver is gone.
+    fn send_event(&mut self, event: ProcessorEvent) {
+        if let Some(Err(_)) = self.events.as_ref().map(|events| events.send(event)) {
+            warn!("event receiver disconnected, no longer sending processor events");
+            self.events = None;
+        }
+    }
+
+    fn apply_transaction(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        // in closed systems only pre-registered clients get an account
+        if let Some(roster) = &self.config.roster {
+            if !roster.contains(&transaction.client()) {
+                self.off_roster += 1;
+                return Err(ProcessingError::ClientNotOnRoster);
             }
-            Transaction::Dispute(dispute) => client.dispute(dispute.tx),
-            Transaction::Resolve(resolve) => client.resolve(resolve.tx),
-            Transaction::Chargeback(chargeback) => client.chargeback(chargeback.tx),
         }
+
+        let is_open = self.clients.contains_key(&transaction.client());
+        if self.config.require_open_account
+            && !is_open
+            && !matches!(transaction, Transaction::Open(_))
+        {
+            return Err(ProcessingError::AccountNotOpen);
+        }
+
+        let client_config = self.config.client;
+        let (client_id, tx) = (transaction.client(), transaction.tx());
+        let premature = OrderingAnomaly::premature(&transaction);
+        let is_dispute = matches!(transaction, Transaction::Dispute(_));
+        let (timeout, clock) = (self.config.tx_timeout, self.clock);
+        let client = self
+            .clients
+            .entry(client_id)
+            .or_insert_with(|| Client::with_config(client_config));
+
+        let apply = |client: &mut Client, op: ClientOp| match timeout {
+            None => client.apply(op),
+            // the operation can't be interrupted, it's undone once it turns out to be too slow
+            Some(timeout) => client.apply_atomically(|client| {
+                let start = clock();
+                let result = client.apply(op);
+                match clock().saturating_duration_since(start) > timeout {
+                    true => Err(ProcessingError::TimedOut),
+                    false => result,
+                }
+            }),
+        };
+        let result = match ClientOp::from_transaction(transaction).map(|op| apply(client, op)) {
+            // feeds often repeat disputes, the deposit is held already
+            Some(Err(ProcessingError::AlreadyDisputed))
+                if self.config.tolerate_redundant_disputes =>
+            {
+                self.redundant_disputes += 1;
+                Ok(())
+            }
+            Some(result) => result,
+            None if is_open => Err(ProcessingError::AccountAlreadyOpen),
+            None => Ok(()),
+        };
+
+        // a resolve or chargeback of a deposit that was never disputed may have overtaken its
+        // dispute, which is only known once the dispute arrives
+        match (premature, result) {
+            (Some(anomaly), Err(ProcessingError::NotDisputed)) if !client.was_disputed(tx) => {
+                self.premature.insert((client_id, tx), anomaly);
+            }
+            (None, Ok(())) if is_dispute => {
+                if let Some(anomaly) = self.premature.remove(&(client_id, tx)) {
+                    *self.ordering_anomalies.entry(anomaly).or_default() += 1;
+                }
+            }
+            _ => {}
+        }
+
+        // an undone transaction leaves no trace, not even the account it would have created
+        if !is_open && result == Err(ProcessingError::TimedOut) {
+            self.clients.shift_remove(&client_id);
+        }
+
+        result
+    }
+
+    /// Processes transactions from a fallible source, e.g. a reader that can fail per item.
+    ///
+    /// Source errors and rejected transactions are logged and counted separately, processing
+    /// continues with the next item either way.
+    pub fn process_results<I, E>(&mut self, results: I) -> ProcessCounts
+    where
+        I: IntoIterator<Item = Result<Transaction, E>>,
+        E: Display,
+    {
+        let mut counts = ProcessCounts::default();
+        for result in results {
+            let transaction = match result {
+                Ok(transaction) => transaction,
+                Err(err) => {
+                    warn!("skipping invalid transaction: {}", err);
+                    counts.source_errors += 1;
+                    continue;
+                }
+            };
+
+            match self.handle_transaction(transaction) {
+                Ok(()) => counts.processed += 1,
+                Err(err) => {
+                    warn!(%err, "transaction processing failed");
+                    counts.rejected += 1;
+                }
+            }
+        }
+
+        counts
+    }
+
+    /// Applies transactions in order and stops at the first one that fails to be processed.
+    ///
+    /// The fail-fast counterpart of `process_results`, like `--strict` is for invalid input rows.
+    /// Transactions applied before the failing one are kept.
+    ///
+    /// # Errors
+    /// Returns the failing transaction together with the reason it was rejected.
+    pub fn process_all_strict<I>(
+        &mut self,
+        transactions: I,
+    ) -> Result<(), (Transaction, ProcessingError)>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        for transaction in transactions {
+            self.handle_transaction(transaction.clone())
+                .map_err(|err| (transaction, err))?;
+        }
+
+        Ok(())
     }
 
-    /// Returns an iterator over all client accounts, providing their current status including
+    /// Processes a transaction unless its idempotency key was already seen, regardless of the tx id.
+    ///
+    /// Keys are only checked when `use_idempotency_keys` is enabled, a transaction without a key is
+    /// always processed. The keys aren't part of snapshots.
+    ///
+    /// # Errors
+    /// - `DuplicateIdempotencyKey`: A transaction with the same key was already seen
+    /// - Any error of `handle_transaction`
+    pub fn handle_keyed_transaction(
+        &mut self,
+        transaction: Transaction,
+        key: Option<String>,
+    ) -> Result<(), ProcessingError> {
+        if let (true, Some(key)) = (self.config.use_idempotency_keys, key) {
+            // the key counts as seen even if the transaction is rejected, a redelivery would be too
+            if !self.seen_keys.insert(key) {
+                let result = Err(ProcessingError::DuplicateIdempotencyKey);
+                self.send_event(ProcessorEvent {
+                    client: transaction.client(),
+                    tx: transaction.tx(),
+                    type_name: transaction.type_name(),
+                    result,
+                });
+                return result;
+            }
+        }
+
+        self.handle_transaction(transaction)
+    }
+
+    /// Returns whether the given transaction has been processed for the client.
+    ///
+    /// Only deposits are tracked, so this returns `false` for withdrawals and rejected transactions.
+    pub fn has_transaction(&self, client: ClientID, tx: TransactionID) -> bool {
+        self.clients
+            .get(&client)
+            .is_some_and(|client| client.has_deposit(tx))
+    }
+
+    /// Returns the highest transaction id of the deposits kept by any client, e.g. restored from a
+    /// snapshot.
+    pub fn last_deposit_tx(&self) -> Option<TransactionID> {
+        self.clients
+            .values()
+            .filter_map(Client::last_deposit_tx)
+            .max()
+    }
+
+    /// Returns an iterator over all client accounts in the order they were first seen, providing their current status including
     /// available funds, held funds, total balance, and whether the account is locked.
     pub fn status_entries<'a>(&'a self) -> impl Iterator<Item = ProcessorStatusEntry> + 'a {
-        self.clients
+        self.clients.iter().map(|(client_id, client)| {
+            ProcessorStatusEntry::new(
+                *client_id,
+                client.available(),
+                client.held(),
+                client.total(),
+                client.locked(),
+            )
+        })
+    }
+
+    /// Returns the current status of a single client, `None` if the client hasn't been seen.
+    pub fn status_entry(&self, client: ClientID) -> Option<ProcessorStatusEntry> {
+        self.clients.get(&client).map(|account| {
+            ProcessorStatusEntry::new(
+                client,
+                account.available(),
+                account.held(),
+                account.total(),
+                account.locked(),
+            )
+        })
+    }
+
+    /// Returns a deterministic hash of all client states as 16 hex digits, combining the
+    /// `ProcessorStatusEntry::compute_state_hash` of every client in client id order.
+    pub fn state_hash(&self) -> String {
+        let mut entries: Vec<_> = self.status_entries().collect();
+        entries.sort_by_key(|entry| entry.client);
+        let hashes = entries
+            .iter()
+            .map(ProcessorStatusEntry::compute_state_hash)
+            .collect::<Vec<_>>()
+            .join("|");
+
+        format!("{:016x}", fnv1a64(hashes.as_bytes()))
+    }
+
+    /// Same as `status_entries`, but also fills in the diagnostic columns: the number of deposits
+    /// retained per client, to find the clients driving memory usage, the chargeback that locked
+    /// the account, and the largest single deposit and withdrawal for risk reporting.
+    pub fn verbose_status_entries<'a>(&'a self) -> impl Iterator<Item = ProcessorStatusEntry> + 'a {
+        self.status_entries()
+            .zip(self.clients.values())
+            .map(|(entry, client)| ProcessorStatusEntry {
+                deposits_retained: Some(client.deposit_count()),
+                locked_by_tx: client.locked_by(),
+                max_deposit: client.max_deposit(),
+                max_withdrawal: client.max_withdrawal(),
+                dispute_ratio_exceeded: None,
+                ..entry
+            })
+    }
+
+    /// Returns the sum of the held balances of all clients, for quick solvency checks.
+    pub fn total_held(&self) -> Decimal {
+        self.clients.values().map(Client::held).sum()
+    }
+
+    /// Returns the sum of the available balances of all clients, for quick solvency checks.
+    pub fn total_available(&self) -> Decimal {
+        self.clients.values().map(Client::available).sum()
+    }
+
+    /// Returns the number of clients the processor has room for without reallocating.
+    pub fn clients_capacity(&self) -> usize {
+        self.clients.capacity()
+    }
+
+    /// Returns the time spent per transaction type, `None` unless profiling is enabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Returns the number of transactions rejected because their client isn't on the roster.
+    pub fn off_roster_rejections(&self) -> usize {
+        self.off_roster
+    }
+
+    /// Returns the number of disputes of already disputed deposits tolerated as no-ops, see
+    /// `ProcessorConfig::tolerate_redundant_disputes`. Without it they are rejected instead.
+    pub fn redundant_disputes(&self) -> usize {
+        self.redundant_disputes
+    }
+
+    /// Returns how many resolves and chargebacks arrived before the dispute of their deposit,
+    /// indicating upstream ordering problems.
+    ///
+    /// They were rejected as `NotDisputed` when they arrived, and are only counted here once the
+    /// dispute follows. Resolves of deposits that were disputed before aren't counted, they are
+    /// more likely duplicates.
+    pub fn ordering_anomalies(&self) -> &BTreeMap<OrderingAnomaly, usize> {
+        &self.ordering_anomalies
+    }
+
+    /// Calls `f` once per client with its final status, in first-seen order.
+    ///
+    /// Meant for finalization tasks at the end of the stream, e.g. emitting a completion event
+    /// per client. Consumes the processor, so no transactions can be handled afterwards.
+    pub fn for_each_final(self, f: impl FnMut(ProcessorStatusEntry)) {
+        self.status_entries().for_each(f);
+    }
+
+    /// Exports the state of all client accounts in a flat, C-compatible layout, in first-seen order.
+    ///
+    /// # Errors
+    /// Fails if a balance doesn't fit in 64-bit minor units.
+    pub fn export_flat(&self) -> Result<Vec<FlatClientState>, ExportError> {
+        self.status_entries()
+            .map(|entry| {
+                FlatClientState::new(
+                    entry.client,
+                    entry.available,
+                    entry.held,
+                    entry.total,
+                    entry.locked,
+                )
+            })
+            .collect()
+    }
+
+    /// Recomputes the total balance of every client from its stored operations and returns the
+    /// clients whose running total differs, sorted by id. Any discrepancy indicates an accounting bug.
+    pub fn audit_balances(&self) -> Vec<BalanceDiscrepancy> {
+        let mut discrepancies = self
+            .clients
+            .iter()
+            .filter_map(|(client_id, client)| {
+                let recomputed = client.audited_total();
+                (client.total() != recomputed).then_some(BalanceDiscrepancy {
+                    client: *client_id,
+                    total: client.total(),
+                    recomputed,
+                })
+            })
+            .collect::<Vec<_>>();
+        discrepancies.sort_by_key(|discrepancy| discrepancy.client);
+
+        discrepancies
+    }
+
+    /// Lists the deposits eligible for a dispute per client, see `Client::eligible_dispute_tx_ids`.
+    /// Returns the clients with at least one eligible deposit, sorted by id.
+    pub fn eligible_disputes(&self) -> Vec<EligibleDisputes> {
+        let mut eligible = self
+            .clients
             .iter()
-            .map(|(client_id, client)| ProcessorStatusEntry {
+            .map(|(client_id, client)| EligibleDisputes {
                 client: *client_id,
-                available: client.available(),
-                held: client.held(),
-                total: client.total(),
-                locked: client.locked(),
+                txs: client.eligible_dispute_tx_ids(),
             })
+            .filter(|eligible| !eligible.txs.is_empty())
+            .collect::<Vec<_>>();
+        eligible.sort_by_key(|eligible| eligible.client);
+
+        eligible
+    }
+
+    /// Flags the clients that disputed more than `max_ratio` of their deposits, a fraud indicator.
+    /// Every deposit counts once, no matter how often it was disputed. Returns the flagged clients
+    /// sorted by id.
+    pub fn dispute_ratio_alerts(&self, max_ratio: f64) -> Vec<DisputeRatioAlert> {
+        let mut alerts = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| DisputeRatioAlert {
+                client: *client_id,
+                disputed: client.disputed_deposit_count(),
+                deposits: client.deposit_count(),
+            })
+            .filter(|alert| alert.disputed as f64 > max_ratio * alert.deposits as f64)
+            .collect::<Vec<_>>();
+        alerts.sort_by_key(|alert| alert.client);
+
+        alerts
+    }
+
+    /// Verifies for every client that the held balance equals the sum of its disputed deposits.
+    /// Returns the mismatching clients sorted by id, any issue indicates an accounting bug.
+    pub fn reconcile(&self) -> Vec<ReconcileIssue> {
+        let mut issues = self
+            .clients
+            .iter()
+            .filter_map(|(client_id, client)| {
+                let disputed = client.disputed_total() - client.released();
+                (client.held() != disputed).then_some(ReconcileIssue {
+                    client: *client_id,
+                    held: client.held(),
+                    disputed,
+                })
+            })
+            .collect::<Vec<_>>();
+        issues.sort_by_key(|issue| issue.client);
+
+        issues
     }
 }
 
-#[derive(Debug, Serialize, PartialEq, Eq)]
+/// Collects transactions into a processor using the default config.
+///
+/// Transactions that fail to be processed are skipped with a warning, the same way the
+/// command line tool handles them.
+impl FromIterator<Transaction> for Processor {
+    fn from_iter<I: IntoIterator<Item = Transaction>>(iter: I) -> Self {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for transaction in iter {
+            if let Err(err) = processor.handle_transaction(transaction) {
+                warn!(%err, "transaction processing failed");
+            }
+        }
+
+        processor
+    }
+}
+
+/// A client whose total balance doesn't match the total recomputed from its operations.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BalanceDiscrepancy {
+    pub client: ClientID,
+    pub total: Decimal,
+    /// Deposits that weren't charged back, minus all withdrawals.
+    pub recomputed: Decimal,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum MergeError {
+    #[error("client {0} is in both processors")]
+    OverlappingClient(ClientID),
+}
+
+/// Outcome counts of `Processor::process_results`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessCounts {
+    /// Transactions applied to the client accounts.
+    pub processed: usize,
+    /// Items the source failed to produce a transaction for.
+    pub source_errors: usize,
+    /// Transactions rejected by the processor, e.g. because of insufficient funds.
+    pub rejected: usize,
+}
+
+/// Time spent applying transactions, accumulated per transaction type.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    nanos: IndexMap<&'static str, u128>,
+}
+
+impl Profile {
+    fn new() -> Self {
+        Self {
+            nanos: Transaction::TYPE_NAMES
+                .into_iter()
+                .map(|type_name| (type_name, 0))
+                .collect(),
+        }
+    }
+
+    fn record(&mut self, type_name: &'static str, elapsed: Duration) {
+        *self.nanos.entry(type_name).or_default() += elapsed.as_nanos();
+    }
+
+    /// Returns the total nanoseconds spent on transactions of the given type.
+    pub fn nanos(&self, type_name: &str) -> Option<u128> {
+        self.nanos.get(type_name).copied()
+    }
+}
+
+impl Display for Profile {
+    /// One line per transaction type, e.g. `deposit: 1200 ns`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, (type_name, nanos)) in self.nanos.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{type_name}: {nanos} ns")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A client that disputed more than the allowed fraction of its deposits.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DisputeRatioAlert {
+    pub client: ClientID,
+    /// Number of deposits disputed at least once.
+    pub disputed: usize,
+    pub deposits: usize,
+}
+
+/// A client whose held balance doesn't match the sum of its disputed deposits.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReconcileIssue {
+    pub client: ClientID,
+    pub held: Decimal,
+    /// Sum of the disputed deposits, minus the funds released by the `release` admin operation.
+    pub disputed: Decimal,
+}
+
+/// A transaction that arrived before the dispute it depends on, see
+/// `Processor::ordering_anomalies`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OrderingAnomaly {
+    ResolveBeforeDispute,
+    ChargebackBeforeDispute,
+}
+
+impl OrderingAnomaly {
+    /// Returns the anomaly a transaction would be if it arrived before its dispute.
+    fn premature(transaction: &Transaction) -> Option<Self> {
+        match transaction {
+            Transaction::Resolve(_) => Some(OrderingAnomaly::ResolveBeforeDispute),
+            Transaction::Chargeback(_) => Some(OrderingAnomaly::ChargebackBeforeDispute),
+            _ => None,
+        }
+    }
+}
+
+impl Display for OrderingAnomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderingAnomaly::ResolveBeforeDispute => write!(f, "resolve_before_dispute"),
+            OrderingAnomaly::ChargebackBeforeDispute => write!(f, "chargeback_before_dispute"),
+        }
+    }
+}
+
+/// The most recent resolves and chargebacks that arrived before their dispute, using bounded
+/// memory.
+///
+/// Only the last `PREMATURE_WINDOW` of them are kept, a dispute arriving later than that isn't
+/// counted as an ordering anomaly.
+#[derive(Debug, Default)]
+struct PrematureWindow {
+    /// Anomaly of each transaction, with the sequence number it was inserted with.
+    anomalies: HashMap<(ClientID, TransactionID), (OrderingAnomaly, u64)>,
+    /// Transactions in the order they were inserted, the oldest one is evicted first. Entries
+    /// already removed by a dispute stay here until they're evicted.
+    order: VecDeque<((ClientID, TransactionID), u64)>,
+    next_seq: u64,
+}
+
+impl PrematureWindow {
+    /// Adds a transaction, unless it's in the window already, evicting the oldest one when full.
+    fn insert(&mut self, key: (ClientID, TransactionID), anomaly: OrderingAnomaly) {
+        if self.anomalies.contains_key(&key) {
+            return;
+        }
+
+        if self.order.len() == PREMATURE_WINDOW {
+            if let Some((oldest, seq)) = self.order.pop_front() {
+                // the key may have been removed and inserted again since
+                if self.anomalies.get(&oldest).map(|(_, current)| *current) == Some(seq) {
+                    self.anomalies.remove(&oldest);
+                }
+            }
+        }
+        self.anomalies.insert(key, (anomaly, self.next_seq));
+        self.order.push_back((key, self.next_seq));
+        self.next_seq += 1;
+    }
+
+    /// Removes a transaction, returning its anomaly if it was in the window.
+    fn remove(&mut self, key: &(ClientID, TransactionID)) -> Option<OrderingAnomaly> {
+        self.anomalies.remove(key).map(|(anomaly, _)| anomaly)
+    }
+
+    /// Adds the transactions of another window, in their order.
+    fn extend(&mut self, other: PrematureWindow) {
+        for (key, seq) in other.order {
+            match other.anomalies.get(&key) {
+                Some(&(anomaly, current)) if current == seq => self.insert(key, anomaly),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Outcome of a transaction handled by a processor, see `Processor::with_event_sender`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessorEvent {
+    pub client: ClientID,
+    pub tx: TransactionID,
+    /// Name of the transaction type, as used in the type column.
+    pub type_name: &'static str,
+    /// `Ok` if the transaction was applied, otherwise the reason it was rejected.
+    pub result: Result<(), ProcessingError>,
+}
+
+/// Sending half of a bounded or unbounded channel for `ProcessorEvent`s.
+#[derive(Clone, Debug)]
+pub enum EventSender {
+    Unbounded(Sender<ProcessorEvent>),
+    Bounded(SyncSender<ProcessorEvent>),
+}
+
+impl EventSender {
+    /// Sends an event, blocking while a bounded channel is full.
+    fn send(&self, event: ProcessorEvent) -> Result<(), SendError<ProcessorEvent>> {
+        match self {
+            EventSender::Unbounded(sender) => sender.send(event),
+            EventSender::Bounded(sender) => sender.send(event),
+        }
+    }
+}
+
+impl From<Sender<ProcessorEvent>> for EventSender {
+    fn from(sender: Sender<ProcessorEvent>) -> Self {
+        EventSender::Unbounded(sender)
+    }
+}
+
+impl From<SyncSender<ProcessorEvent>> for EventSender {
+    fn from(sender: SyncSender<ProcessorEvent>) -> Self {
+        EventSender::Bounded(sender)
+    }
+}
+
+/// The deposits of a client that can currently be disputed, sorted by tx id.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EligibleDisputes {
+    pub client: ClientID,
+    pub txs: Vec<TransactionID>,
+}
+
+impl Display for EligibleDisputes {
+    /// The client followed by the tx ids, e.g. `client 1: 3, 7`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "client {}: ", self.client)?;
+        for (index, tx) in self.txs.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{tx}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct ProcessorStatusEntry {
     pub client: ClientID,
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
     pub locked: bool,
+    /// Optional output column, filled in using `compute_state_hash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_hash: Option<String>,
+    /// Optional output column with the number of deposits the client keeps for disputes,
+    /// filled in by `verbose_status_entries`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deposits_retained: Option<usize>,
+    /// Optional output column with the chargeback that locked the account, filled in by
+    /// `verbose_status_entries`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_by_tx: Option<TransactionID>,
+    /// Optional output column with the largest single deposit of the client, filled in by
+    /// `verbose_status_entries`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_deposit: Option<Decimal>,
+    /// Optional output column with the largest single withdrawal of the client, filled in by
+    /// `verbose_status_entries`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_withdrawal: Option<Decimal>,
+    /// Optional output column telling whether the client disputed more of its deposits than
+    /// allowed, filled in for `Processor::dispute_ratio_alerts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispute_ratio_exceeded: Option<bool>,
+}
+
+impl ProcessorStatusEntry {
+    /// Creates an entry with the balances and the lock of a client, without the optional columns.
+    pub fn new(
+        client: ClientID,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+        locked: bool,
+    ) -> Self {
+        Self {
+            client,
+            available,
+            held,
+            total,
+            locked,
+            state_hash: None,
+            deposits_retained: None,
+            locked_by_tx: None,
+            max_deposit: None,
+            max_withdrawal: None,
+            dispute_ratio_exceeded: None,
+        }
+    }
+
+    /// Returns a deterministic hash of the client's balances and locked flag as 16 hex digits.
+    ///
+    /// Equal states always hash the same, regardless of the decimal scale (e.g. `1.0` and `1`).
+    /// FNV-1a is used since it's stable across runs and platforms, it's not cryptographically secure.
+    pub fn compute_state_hash(&self) -> String {
+        let canonical = format!(
+            "{}|{}|{}|{}|{}",
+            self.client,
+            self.available.normalize(),
+            self.held.normalize(),
+            self.total.normalize(),
+            self.locked
+        );
+
+        format!("{:016x}", fnv1a64(canonical.as_bytes()))
+    }
+}
+
+/// 64-bit FNV-1a hash.
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use rust_decimal_macros::dec;
 
-    use crate::{
-        ids::TransactionID,
-        transaction::{Deposit, Dispute, Resolve, Withdrawal},
-    };
+    use crate::transaction::{Chargeback, Deposit, Dispute, Open, Resolve, Withdrawal};
+
+    use std::{cell::Cell, collections::HashMap};
 
     use super::*;
 
     #[test]
     fn basic_transactions_test() {
-        let mut processor = Processor::new();
+        let mut processor = Processor::new(ProcessorConfig::default());
         processor
             .handle_transaction(Transaction::Deposit(Deposit {
                 tx: TransactionID::new(1),
@@ -95,6 +973,8 @@ mod tests {
             .handle_transaction(Transaction::Dispute(Dispute {
                 tx: TransactionID::new(1),
                 client: ClientID::new(1),
+                amount: None,
+                reason: None,
             }))
             .unwrap();
 
@@ -119,27 +999,907 @@ mod tests {
             .collect::<HashMap<ClientID, _>>();
 
         assert_eq!(
-            Some(ProcessorStatusEntry {
+            Some(ProcessorStatusEntry::new(
+                ClientID::new(1),
+                dec!(5.0),
+                dec!(0.0),
+                dec!(5.0),
+                false
+            )),
+            entries.remove(&ClientID::new(1))
+        );
+
+        assert_eq!(
+            Some(ProcessorStatusEntry::new(
+                ClientID::new(2),
+                dec!(1000.0),
+                dec!(0.0),
+                dec!(1000.0),
+                false
+            )),
+            entries.remove(&ClientID::new(2))
+        );
+
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn process_results_test() {
+        let deposit = |tx, amount: Decimal| {
+            Ok(Transaction::Deposit(Deposit {
+                tx: TransactionID::new(tx),
                 client: ClientID::new(1),
-                available: dec!(5.0),
-                held: dec!(0.0),
-                total: dec!(5.0),
-                locked: false,
+                amount: amount.try_into().unwrap(),
+            }))
+        };
+        let results = vec![
+            deposit(1, dec!(10.0)),
+            Err("line 3: invalid amount"),
+            // rejected as a duplicate tx id
+            deposit(1, dec!(5.0)),
+            Err("line 5: invalid amount"),
+            deposit(2, dec!(2.5)),
+        ];
+
+        let mut processor = Processor::new(ProcessorConfig::default());
+        let counts = processor.process_results(results);
+
+        assert_eq!(
+            counts,
+            ProcessCounts {
+                processed: 2,
+                source_errors: 2,
+                rejected: 1,
+            }
+        );
+        assert_eq!(processor.status_entries().next().unwrap().total, dec!(12.5));
+    }
+
+    #[test]
+    fn process_all_strict_test() {
+        let withdrawal = |tx, amount: Decimal| {
+            Transaction::Withdrawal(Withdrawal {
+                tx: TransactionID::new(tx),
+                client: ClientID::new(1),
+                amount: amount.try_into().unwrap(),
+            })
+        };
+        let transactions = vec![
+            Transaction::Deposit(Deposit {
+                tx: TransactionID::new(1),
+                client: ClientID::new(1),
+                amount: dec!(10.0).try_into().unwrap(),
             }),
-            entries.remove(&ClientID::new(1))
+            withdrawal(2, dec!(4.0)),
+            // fails due to insufficient funds and stops processing
+            withdrawal(3, dec!(50.0)),
+            withdrawal(4, dec!(1.0)),
+        ];
+
+        let mut processor = Processor::new(ProcessorConfig::default());
+        let (transaction, err) = processor
+            .process_all_strict(transactions)
+            .expect_err("withdrawal should fail");
+
+        assert_eq!(transaction, withdrawal(3, dec!(50.0)));
+        assert!(matches!(err, ProcessingError::InsufficientFunds));
+        // the last withdrawal was never applied
+        assert_eq!(processor.status_entries().next().unwrap().total, dec!(6.0));
+    }
+
+    #[test]
+    fn from_iterator_test() {
+        let transactions = vec![
+            Transaction::Deposit(Deposit {
+                tx: TransactionID::new(1),
+                client: ClientID::new(1),
+                amount: dec!(10.0).try_into().unwrap(),
+            }),
+            // fails due to insufficient funds and is skipped
+            Transaction::Withdrawal(Withdrawal {
+                tx: TransactionID::new(2),
+                client: ClientID::new(1),
+                amount: dec!(50.0).try_into().unwrap(),
+            }),
+            Transaction::Withdrawal(Withdrawal {
+                tx: TransactionID::new(3),
+                client: ClientID::new(1),
+                amount: dec!(2.5).try_into().unwrap(),
+            }),
+        ];
+
+        let processor: Processor = transactions.into_iter().collect();
+
+        assert_eq!(
+            processor.status_entries().collect::<Vec<_>>(),
+            vec![ProcessorStatusEntry::new(
+                ClientID::new(1),
+                dec!(7.5),
+                dec!(0.0),
+                dec!(7.5),
+                false
+            )]
         );
+    }
+
+    #[test]
+    fn first_seen_order_test() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for (tx, client) in [(1, 5), (2, 1), (3, 5), (4, 3), (5, 1)] {
+            // all transactions create the client, even the rejected disputes
+            let _ = processor.handle_transaction(Transaction::Dispute(Dispute {
+                tx: TransactionID::new(tx),
+                client: ClientID::new(client),
+                amount: None,
+                reason: None,
+            }));
+        }
+
+        let order = processor
+            .status_entries()
+            .map(|entry| entry.client)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [5, 1, 3].map(ClientID::new));
+    }
+
+    #[test]
+    fn export_flat_test() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for (tx, client, amount) in [(1, 7, dec!(10.5)), (2, 3, dec!(0.0001)), (3, 7, dec!(2))] {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(tx),
+                    client: ClientID::new(client),
+                    amount: amount.try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+        processor
+            .handle_transaction(Transaction::Dispute(Dispute {
+                tx: TransactionID::new(3),
+                client: ClientID::new(7),
+                amount: None,
+                reason: None,
+            }))
+            .unwrap();
 
         assert_eq!(
-            Some(ProcessorStatusEntry {
+            processor.export_flat().unwrap(),
+            vec![
+                FlatClientState {
+                    client: 7,
+                    locked: 0,
+                    available: 105_000,
+                    held: 20_000,
+                    total: 125_000,
+                },
+                FlatClientState {
+                    client: 3,
+                    locked: 0,
+                    available: 1,
+                    held: 0,
+                    total: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn has_transaction_test() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        processor
+            .handle_transaction(Transaction::Deposit(Deposit {
+                tx: TransactionID::new(42),
+                client: ClientID::new(1),
+                amount: dec!(10.0).try_into().unwrap(),
+            }))
+            .unwrap();
+
+        assert!(processor.has_transaction(ClientID::new(1), TransactionID::new(42)));
+        assert!(!processor.has_transaction(ClientID::new(1), TransactionID::new(43)));
+        assert!(!processor.has_transaction(ClientID::new(2), TransactionID::new(42)));
+    }
+
+    #[test]
+    fn snapshot_replay_test() {
+        let transactions = || {
+            vec![
+                Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(1),
+                    client: ClientID::new(1),
+                    amount: dec!(10.0).try_into().unwrap(),
+                }),
+                Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(2),
+                    client: ClientID::new(2),
+                    amount: dec!(3.0).try_into().unwrap(),
+                }),
+                Transaction::Dispute(Dispute {
+                    tx: TransactionID::new(1),
+                    client: ClientID::new(1),
+                    amount: None,
+                    reason: None,
+                }),
+                // refers to a deposit from before the snapshot
+                Transaction::Resolve(Resolve {
+                    tx: TransactionID::new(1),
+                    client: ClientID::new(1),
+                }),
+                Transaction::Withdrawal(Withdrawal {
+                    tx: TransactionID::new(3),
+                    client: ClientID::new(1),
+                    amount: dec!(4.0).try_into().unwrap(),
+                }),
+                // duplicate of a deposit from before the snapshot
+                Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(2),
+                    client: ClientID::new(2),
+                    amount: dec!(3.0).try_into().unwrap(),
+                }),
+            ]
+        };
+
+        let full_run: Processor = transactions().into_iter().collect();
+
+        let mut first_half = transactions();
+        let second_half = first_half.split_off(3);
+        let interrupted: Processor = first_half.into_iter().collect();
+
+        let (resumed, outcomes) = Processor::replay(
+            &interrupted.snapshot(),
+            ProcessorConfig::default(),
+            second_half,
+        );
+
+        assert_eq!(outcomes.len(), 3);
+        assert!(matches!(
+            outcomes[2],
+            Err(ProcessingError::DuplicateTransactionID)
+        ));
+        assert_eq!(resumed.snapshot(), full_run.snapshot());
+    }
+
+    #[test]
+    fn state_hash_test() {
+        let deposit = |client, tx, amount: Decimal| {
+            Transaction::Deposit(Deposit {
+                tx: TransactionID::new(tx),
+                client: ClientID::new(client),
+                amount: amount.try_into().unwrap(),
+            })
+        };
+        let state_hashes = |processor: &Processor| {
+            processor
+                .status_entries()
+                .map(|entry| (entry.client, entry.compute_state_hash()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let first_run: Processor = [deposit(1, 1, dec!(10)), deposit(2, 2, dec!(5))]
+            .into_iter()
+            .collect();
+        let second_run: Processor = [
+            deposit(2, 2, dec!(5)),
+            deposit(1, 1, dec!(10.00)),
+            deposit(2, 3, dec!(1)),
+        ]
+        .into_iter()
+        .collect();
+
+        let first_hashes = state_hashes(&first_run);
+        let second_hashes = state_hashes(&second_run);
+
+        assert_eq!(first_hashes, state_hashes(&first_run));
+        assert_eq!(first_hashes[&ClientID::new(1)].len(), 16);
+        assert_eq!(
+            first_hashes[&ClientID::new(1)],
+            second_hashes[&ClientID::new(1)]
+        );
+        assert_ne!(
+            first_hashes[&ClientID::new(2)],
+            second_hashes[&ClientID::new(2)]
+        );
+
+        // the combined hash doesn't depend on the order the clients were seen in
+        let reordered: Processor = [deposit(2, 2, dec!(5)), deposit(1, 1, dec!(10))]
+            .into_iter()
+            .collect();
+        assert_eq!(first_run.state_hash(), reordered.state_hash());
+        assert_ne!(first_run.state_hash(), second_run.state_hash());
+    }
+
+    #[test]
+    fn reconcile_test() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for (tx, amount) in [(1, dec!(10.0)), (2, dec!(2.5)), (3, dec!(7.25))] {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(tx),
+                    client: ClientID::new(1),
+                    amount: amount.try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+
+        processor
+            .handle_transaction(Transaction::Dispute(Dispute {
+                tx: TransactionID::new(1),
+                client: ClientID::new(1),
+                amount: None,
+                reason: None,
+            }))
+            .unwrap();
+        processor
+            .handle_transaction(Transaction::Dispute(Dispute {
+                tx: TransactionID::new(3),
+                client: ClientID::new(1),
+                amount: None,
+                reason: None,
+            }))
+            .unwrap();
+        processor
+            .handle_transaction(Transaction::Resolve(Resolve {
+                tx: TransactionID::new(1),
+                client: ClientID::new(1),
+            }))
+            .unwrap();
+
+        assert_eq!(processor.reconcile(), vec![]);
+    }
+
+    #[test]
+    fn audit_balances_test() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for (client, tx, amount) in [(1, 1, dec!(10.0)), (1, 2, dec!(2.5)), (2, 3, dec!(7.25))] {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(tx),
+                    client: ClientID::new(client),
+                    amount: amount.try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+        processor
+            .handle_transaction(Transaction::Withdrawal(Withdrawal {
+                tx: TransactionID::new(4),
+                client: ClientID::new(1),
+                amount: dec!(3.0).try_into().unwrap(),
+            }))
+            .unwrap();
+        processor
+            .handle_transaction(Transaction::Dispute(Dispute {
+                tx: TransactionID::new(2),
+                client: ClientID::new(1),
+                amount: None,
+                reason: None,
+            }))
+            .unwrap();
+        processor
+            .handle_transaction(Transaction::Chargeback(Chargeback {
+                tx: TransactionID::new(2),
+                client: ClientID::new(1),
+            }))
+            .unwrap();
+        processor
+            .handle_transaction(Transaction::Dispute(Dispute {
+                tx: TransactionID::new(3),
                 client: ClientID::new(2),
-                available: dec!(1000.0),
-                held: dec!(0.0),
-                total: dec!(1000.0),
-                locked: false,
+                amount: None,
+                reason: None,
+            }))
+            .unwrap();
+
+        assert_eq!(processor.audit_balances(), vec![]);
+    }
+
+    #[test]
+    fn idempotency_keys_test() {
+        let deposit = |tx| {
+            Transaction::Deposit(Deposit {
+                tx: TransactionID::new(tx),
+                client: ClientID::new(1),
+                amount: dec!(1.0).try_into().unwrap(),
+            })
+        };
+        let key = |key: &str| Some(key.to_string());
+
+        let mut processor = Processor::new(ProcessorConfig {
+            use_idempotency_keys: true,
+            ..Default::default()
+        });
+        processor
+            .handle_keyed_transaction(deposit(1), key("a"))
+            .unwrap();
+        assert!(matches!(
+            processor.handle_keyed_transaction(deposit(2), key("a")),
+            Err(ProcessingError::DuplicateIdempotencyKey)
+        ));
+        processor
+            .handle_keyed_transaction(deposit(3), key("b"))
+            .unwrap();
+        processor
+            .handle_keyed_transaction(deposit(4), None)
+            .unwrap();
+        assert_eq!(processor.status_entries().next().unwrap().total, dec!(3.0));
+
+        // without the option the keys are ignored
+        let mut processor = Processor::new(ProcessorConfig::default());
+        processor
+            .handle_keyed_transaction(deposit(1), key("a"))
+            .unwrap();
+        processor
+            .handle_keyed_transaction(deposit(2), key("a"))
+            .unwrap();
+        assert_eq!(processor.status_entries().next().unwrap().total, dec!(2.0));
+    }
+
+    #[test]
+    fn for_each_final_test() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for (client, tx) in [(2, 1), (1, 2), (2, 3)] {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(tx),
+                    client: ClientID::new(client),
+                    amount: dec!(1.0).try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+
+        let mut finalized = Vec::new();
+        processor.for_each_final(|entry| finalized.push((u16::from(entry.client), entry.total)));
+        assert_eq!(finalized, vec![(2, dec!(2.0)), (1, dec!(1.0))]);
+    }
+
+    #[test]
+    fn roster_test() {
+        let mut processor = Processor::new(ProcessorConfig {
+            roster: Some(HashSet::from([ClientID::new(1)])),
+            ..Default::default()
+        });
+        for (client, tx) in [(1, 1), (2, 2), (2, 3)] {
+            let result = processor.handle_transaction(Transaction::Deposit(Deposit {
+                tx: TransactionID::new(tx),
+                client: ClientID::new(client),
+                amount: dec!(1.0).try_into().unwrap(),
+            }));
+            assert_eq!(result.is_ok(), client == 1);
+        }
+
+        assert_eq!(processor.off_roster_rejections(), 2);
+        let clients: Vec<_> = processor
+            .status_entries()
+            .map(|entry| entry.client)
+            .collect();
+        assert_eq!(clients, vec![ClientID::new(1)]);
+    }
+
+    #[test]
+    fn merge_test() {
+        let processor = |clients: &[u16]| {
+            clients
+                .iter()
+                .map(|client| {
+                    Transaction::Deposit(Deposit {
+                        tx: TransactionID::new(u32::from(*client)),
+                        client: ClientID::new(*client),
+                        amount: dec!(1.0).try_into().unwrap(),
+                    })
+                })
+                .collect::<Processor>()
+        };
+
+        let mut merged = processor(&[2, 1]);
+        merged.merge(processor(&[3])).unwrap();
+        let clients: Vec<_> = merged.status_entries().map(|entry| entry.client).collect();
+        assert_eq!(clients, [2, 1, 3].map(ClientID::new));
+
+        assert_eq!(
+            merged.merge(processor(&[4, 1])),
+            Err(MergeError::OverlappingClient(ClientID::new(1)))
+        );
+        assert_eq!(merged.status_entries().count(), 3);
+    }
+
+    #[test]
+    fn with_capacity_test() {
+        let deposit = |client| {
+            Transaction::Deposit(Deposit {
+                client: ClientID::new(client),
+                tx: TransactionID::new(client.into()),
+                amount: dec!(1.0).try_into().unwrap(),
+            })
+        };
+
+        let mut preallocated = Processor::with_capacity(ProcessorConfig::default(), 1000);
+        let capacity = preallocated.clients_capacity();
+        assert!(capacity >= 1000);
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for client in 1..=1000 {
+            preallocated.handle_transaction(deposit(client)).unwrap();
+            processor.handle_transaction(deposit(client)).unwrap();
+        }
+
+        // no reallocation, and the clients are still in the order they were seen
+        assert_eq!(preallocated.clients_capacity(), capacity);
+        assert!(preallocated.status_entries().eq(processor.status_entries()));
+
+        // estimates beyond the possible client ids are capped
+        let capped = Processor::with_capacity(ProcessorConfig::default(), usize::MAX);
+        assert!(capped.clients_capacity() < 2 * MAX_CLIENTS);
+    }
+
+    #[test]
+    fn event_sender_test() {
+        let (client, tx) = (ClientID::new(1), TransactionID::new);
+        let transactions: Vec<_> = (1..=100)
+            .map(|i| {
+                Transaction::Deposit(Deposit {
+                    client,
+                    tx: tx(i),
+                    amount: dec!(1.0).try_into().unwrap(),
+                })
+            })
+            .chain([
+                Transaction::Withdrawal(Withdrawal {
+                    client,
+                    tx: tx(101),
+                    amount: dec!(1000.0).try_into().unwrap(),
+                }),
+                Transaction::Dispute(Dispute {
+                    client,
+                    tx: tx(1),
+                    amount: None,
+                    reason: None,
+                }),
+            ])
+            .collect();
+
+        // a channel much smaller than the number of events makes the processor wait for the
+        // consumer, which is slower on purpose
+        let (sender, receiver) = std::sync::mpsc::sync_channel(4);
+        let consumer = std::thread::spawn(move || {
+            receiver
+                .into_iter()
+                .inspect(|_| std::thread::sleep(Duration::from_micros(10)))
+                .collect::<Vec<ProcessorEvent>>()
+        });
+
+        let mut processor = Processor::new(ProcessorConfig::default()).with_event_sender(sender);
+        let results: Vec<_> = transactions
+            .iter()
+            .map(|transaction| processor.handle_transaction(transaction.clone()))
+            .collect();
+        // dropping the processor closes the channel, which ends the consumer
+        drop(processor);
+        let events = consumer.join().unwrap();
+
+        assert_eq!(events.len(), transactions.len());
+        for ((event, transaction), result) in events.iter().zip(&transactions).zip(results) {
+            assert_eq!(
+                (event.client, event.tx, event.type_name, event.result),
+                (client, transaction.tx(), transaction.type_name(), result)
+            );
+        }
+        assert_eq!(events[100].result, Err(ProcessingError::InsufficientFunds));
+
+        // a processor whose receiver is gone keeps processing
+        let (sender, receiver) = std::sync::mpsc::channel();
+        drop(receiver);
+        let mut processor = Processor::new(ProcessorConfig::default()).with_event_sender(sender);
+        for transaction in transactions {
+            processor
+                .handle_transaction(transaction)
+                .unwrap_or_default();
+        }
+        assert_eq!(processor.status_entries().count(), 1);
+    }
+
+    #[test]
+    fn redundant_dispute_test() {
+        let (client, tx) = (ClientID::new(1), TransactionID::new(1));
+        let dispute = || {
+            Transaction::Dispute(Dispute {
+                client,
+                tx,
+                amount: None,
+                reason: None,
+            })
+        };
+
+        for tolerate_redundant_disputes in [false, true] {
+            let mut processor = Processor::new(ProcessorConfig {
+                tolerate_redundant_disputes,
+                ..Default::default()
+            });
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    client,
+                    tx,
+                    amount: dec!(3.0).try_into().unwrap(),
+                }))
+                .unwrap();
+            processor.handle_transaction(dispute()).unwrap();
+
+            let result = processor.handle_transaction(dispute());
+            match tolerate_redundant_disputes {
+                true => assert!(result.is_ok()),
+                false => assert!(matches!(result, Err(ProcessingError::AlreadyDisputed))),
+            }
+            assert_eq!(
+                processor.redundant_disputes(),
+                usize::from(tolerate_redundant_disputes)
+            );
+
+            // either way the deposit is held once
+            let entry = processor.status_entry(client).unwrap();
+            assert_eq!((entry.held, entry.available), (dec!(3.0), dec!(0)));
+        }
+    }
+
+    #[test]
+    fn ordering_anomalies_test() {
+        let (client, tx) = (ClientID::new(1), TransactionID::new);
+        let deposit = |id| {
+            Transaction::Deposit(Deposit {
+                client,
+                tx: tx(id),
+                amount: dec!(2.0).try_into().unwrap(),
+            })
+        };
+        let dispute = |id| {
+            Transaction::Dispute(Dispute {
+                client,
+                tx: tx(id),
+                amount: None,
+                reason: None,
+            })
+        };
+        let resolve = |id| Transaction::Resolve(Resolve { client, tx: tx(id) });
+        let chargeback = |id| Transaction::Chargeback(Chargeback { client, tx: tx(id) });
+
+        let mut processor = Processor::new(ProcessorConfig::default());
+        for id in 1..=4 {
+            processor.handle_transaction(deposit(id)).unwrap();
+        }
+
+        // resolve before dispute, rejected when it arrives and counted once the dispute follows
+        assert!(matches!(
+            processor.handle_transaction(resolve(1)),
+            Err(ProcessingError::NotDisputed)
+        ));
+        assert!(processor.ordering_anomalies().is_empty());
+        processor.handle_transaction(dispute(1)).unwrap();
+
+        // a resolve of a deposit that was disputed before is a duplicate, not an anomaly
+        processor.handle_transaction(resolve(1)).unwrap();
+        assert!(processor.handle_transaction(resolve(1)).is_err());
+        processor.handle_transaction(dispute(1)).unwrap();
+
+        // a premature resolve without a later dispute isn't counted
+        assert!(processor.handle_transaction(resolve(2)).is_err());
+
+        // chargeback before dispute
+        assert!(matches!(
+            processor.handle_transaction(chargeback(3)),
+            Err(ProcessingError::NotDisputed)
+        ));
+        processor.handle_transaction(dispute(3)).unwrap();
+
+        assert_eq!(
+            processor.ordering_anomalies(),
+            &BTreeMap::from([
+                (OrderingAnomaly::ResolveBeforeDispute, 1),
+                (OrderingAnomaly::ChargebackBeforeDispute, 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn premature_window_test() {
+        let key = |tx| (ClientID::new(1), TransactionID::new(tx));
+        let mut window = PrematureWindow::default();
+        window.insert(key(0), OrderingAnomaly::ResolveBeforeDispute);
+        assert_eq!(
+            window.remove(&key(0)),
+            Some(OrderingAnomaly::ResolveBeforeDispute)
+        );
+        // inserted again after its removal, its first place in the order doesn't evict it
+        window.insert(key(0), OrderingAnomaly::ChargebackBeforeDispute);
+        for tx in 1..PREMATURE_WINDOW as u32 {
+            window.insert(key(tx), OrderingAnomaly::ResolveBeforeDispute);
+        }
+        assert_eq!(window.anomalies.len(), PREMATURE_WINDOW);
+
+        // a full window evicts the oldest transaction
+        let mut other = PrematureWindow::default();
+        other.insert(key(u32::MAX), OrderingAnomaly::ResolveBeforeDispute);
+        window.extend(other);
+        assert_eq!(window.anomalies.len(), PREMATURE_WINDOW);
+        assert_eq!(window.remove(&key(0)), None);
+        assert_eq!(
+            window.remove(&key(1)),
+            Some(OrderingAnomaly::ResolveBeforeDispute)
+        );
+        assert!(window.remove(&key(u32::MAX)).is_some());
+    }
+
+    #[test]
+    fn require_open_account_test() {
+        let mut processor = Processor::new(ProcessorConfig {
+            require_open_account: true,
+            ..Default::default()
+        });
+        let deposit = |tx| {
+            Transaction::Deposit(Deposit {
+                tx: TransactionID::new(tx),
+                client: ClientID::new(1),
+                amount: dec!(1.0).try_into().unwrap(),
+            })
+        };
+        let open = Transaction::Open(Open {
+            tx: TransactionID::new(2),
+            client: ClientID::new(1),
+        });
+
+        assert!(matches!(
+            processor.handle_transaction(deposit(1)),
+            Err(ProcessingError::AccountNotOpen)
+        ));
+        assert_eq!(processor.status_entries().count(), 0);
+
+        processor.handle_transaction(open.clone()).unwrap();
+        processor.handle_transaction(deposit(3)).unwrap();
+        assert!(matches!(
+            processor.handle_transaction(open),
+            Err(ProcessingError::AccountAlreadyOpen)
+        ));
+        assert_eq!(processor.total_available(), dec!(1.0));
+    }
+
+    #[test]
+    fn profile_test() {
+        let mut processor = Processor::new(ProcessorConfig {
+            profile: true,
+            ..Default::default()
+        });
+        let (client, tx) = (ClientID::new(1), TransactionID::new(1));
+        for transaction in [
+            Transaction::Deposit(Deposit {
+                client,
+                tx,
+                amount: dec!(2.0).try_into().unwrap(),
             }),
-            entries.remove(&ClientID::new(2))
+            Transaction::Withdrawal(Withdrawal {
+                client,
+                tx: TransactionID::new(2),
+                amount: dec!(1.0).try_into().unwrap(),
+            }),
+            Transaction::Dispute(Dispute {
+                client,
+                tx,
+                amount: None,
+                reason: None,
+            }),
+            Transaction::Resolve(Resolve { client, tx }),
+            Transaction::Chargeback(Chargeback { client, tx }),
+        ] {
+            // only the time matters, not whether the transaction is accepted
+            processor.handle_transaction(transaction).ok();
+        }
+
+        let output = processor.profile().unwrap().to_string();
+        for type_name in ["deposit", "withdrawal", "dispute", "resolve", "chargeback"] {
+            let line = output
+                .lines()
+                .find(|line| line.starts_with(&format!("{type_name}: ")))
+                .unwrap();
+            let nanos = line
+                .trim_start_matches(&format!("{type_name}: "))
+                .trim_end_matches(" ns");
+            assert!(nanos.parse::<u128>().is_ok(), "{line}");
+        }
+
+        assert!(Processor::new(ProcessorConfig::default())
+            .profile()
+            .is_none());
+    }
+
+    #[test]
+    fn total_held_and_available_test() {
+        let mut processor = Processor::new(ProcessorConfig::default());
+        assert_eq!(processor.total_held(), dec!(0));
+        assert_eq!(processor.total_available(), dec!(0));
+
+        for (client, tx, amount) in [(1, 1, dec!(10.0)), (2, 2, dec!(2.5)), (2, 3, dec!(1.25))] {
+            processor
+                .handle_transaction(Transaction::Deposit(Deposit {
+                    tx: TransactionID::new(tx),
+                    client: ClientID::new(client),
+                    amount: amount.try_into().unwrap(),
+                }))
+                .unwrap();
+        }
+        processor
+            .handle_transaction(Transaction::Dispute(Dispute {
+                tx: TransactionID::new(2),
+                client: ClientID::new(2),
+                amount: None,
+                reason: None,
+            }))
+            .unwrap();
+
+        assert_eq!(processor.total_held(), dec!(2.5));
+        assert_eq!(processor.total_available(), dec!(11.25));
+    }
+
+    #[test]
+    fn tx_timeout_test() {
+        let client = ClientID::new(1);
+        let tx = TransactionID::new;
+        let deposit = |client, id| {
+            Transaction::Deposit(Deposit {
+                client,
+                tx: tx(id),
+                amount: dec!(10).try_into().unwrap(),
+            })
+        };
+        let dispute = |id| {
+            Transaction::Dispute(Dispute {
+                client,
+                tx: tx(id),
+                amount: None,
+                reason: None,
+            })
+        };
+        // every reading is 100ms after the previous one, so every transaction is too slow
+        fn slow_clock() -> Instant {
+            thread_local! {
+                static NOW: Cell<Instant> = Cell::new(Instant::now());
+            }
+            NOW.with(|now| {
+                now.set(now.get() + Duration::from_millis(100));
+                now.get()
+            })
+        }
+
+        let mut processor = Processor::new(ProcessorConfig {
+            tx_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        });
+        processor.handle_transaction(deposit(client, 1)).unwrap();
+
+        // a slow deposit is rejected and undone, processing continues
+        processor.clock = slow_clock;
+        assert_eq!(
+            processor.handle_transaction(deposit(client, 2)),
+            Err(ProcessingError::TimedOut)
+        );
+        processor.clock = Instant::now;
+        assert_eq!(
+            processor.handle_transaction(dispute(2)),
+            Err(ProcessingError::DepositNotFound)
         );
+        processor.handle_transaction(deposit(client, 3)).unwrap();
+        assert_eq!(processor.total_available(), dec!(20));
 
-        assert!(entries.is_empty());
+        // a slow dispute doesn't hold the funds
+        processor.clock = slow_clock;
+        assert_eq!(
+            processor.handle_transaction(dispute(1)),
+            Err(ProcessingError::TimedOut)
+        );
+        assert_eq!(processor.total_held(), dec!(0));
+
+        // a slow deposit of a new client doesn't create its account
+        assert_eq!(
+            processor.handle_transaction(deposit(ClientID::new(2), 4)),
+            Err(ProcessingError::TimedOut)
+        );
+        assert!(processor.status_entry(ClientID::new(2)).is_none());
+
+        processor.clock = Instant::now;
+        processor.handle_transaction(dispute(1)).unwrap();
+        assert_eq!(processor.total_held(), dec!(10));
     }
 }
@@ -0,0 +1,100 @@
+use std::io::{self, BufRead, Read};
+
+use super::raw_transaction::RawTransaction;
+use crate::transaction::Transaction;
+
+/// Version of the columns written by `TransactionWriter`, bumped whenever they change.
+pub const SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA_COMMENT_PREFIX: &str = "# schema_version: ";
+
+/// Longest first line that is checked for a schema comment, longer lines can't be one.
+const MAX_SCHEMA_COMMENT_LEN: usize = 64;
+
+/// Writes transactions as canonical CSV that the `TransactionReader` reads back unchanged.
+///
+/// Types without an amount get an empty amount column. With a schema comment the output starts
+/// with a `# schema_version: N` line before the header, which the reader checks on re-ingestion.
+pub struct TransactionWriter<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W> TransactionWriter<W>
+where
+    W: io::Write,
+{
+    pub fn new(mut writer: W, schema_comment: bool) -> Result<Self, csv::Error> {
+        if schema_comment {
+            writeln!(writer, "{SCHEMA_COMMENT_PREFIX}{SCHEMA_VERSION}")?;
+        }
+
+        Ok(Self {
+            writer: csv::Writer::from_writer(writer),
+        })
+    }
+
+    pub fn write(&mut self, transaction: Transaction) -> Result<(), csv::Error> {
+        self.writer.serialize(RawTransaction::from(transaction))
+    }
+
+    pub fn flush(&mut self) -> Result<(), csv::Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Reads a schema comment from the start of the input.
+///
+/// Returns the version if the first line is a schema comment, which is consumed. Otherwise nothing
+/// is consumed, except for a first line starting with `#`, which is returned to be put back in
+/// front of the input. Read errors end the check, the csv reader runs into them again.
+pub(super) fn read_schema_comment<R: BufRead>(input: &mut R) -> (Vec<u8>, Option<u32>) {
+    // only peek otherwise, the csv reader needs to see a leading BOM in one piece
+    let is_comment = input
+        .fill_buf()
+        .is_ok_and(|buffer| buffer.first() == Some(&b'#'));
+    if !is_comment {
+        return (Vec::new(), None);
+    }
+
+    let mut line = Vec::new();
+    let _ = input
+        .take(MAX_SCHEMA_COMMENT_LEN as u64)
+        .read_until(b'\n', &mut line);
+    let version = std::str::from_utf8(&line)
+        .ok()
+        .and_then(|line| line.trim_end().strip_prefix(SCHEMA_COMMENT_PREFIX))
+        .and_then(|version| version.parse().ok());
+    match version {
+        Some(version) => (Vec::new(), Some(version)),
+        None => (line, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_schema_comment() {
+        let mut input = "# schema_version: 7\r\ntype,client,tx,amount\n".as_bytes();
+        assert_eq!(read_schema_comment(&mut input), (vec![], Some(7)));
+        assert_eq!(input, b"type,client,tx,amount\n");
+
+        // a leading BOM is left in place for the csv reader
+        let mut input = "\u{feff}type,client,tx,amount\n".as_bytes();
+        assert_eq!(read_schema_comment(&mut input), (vec![], None));
+        assert_eq!(input, "\u{feff}type,client,tx,amount\n".as_bytes());
+
+        // other first lines are put back
+        for first_line in [
+            "type,client,tx,amount\n",
+            "# comment\n",
+            "# schema_version: x\n",
+        ] {
+            let mut input = first_line.as_bytes();
+            let (prefix, version) = read_schema_comment(&mut input);
+            assert_eq!(version, None);
+            assert_eq!([prefix.as_slice(), input].concat(), first_line.as_bytes());
+        }
+    }
+}
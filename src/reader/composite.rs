@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    ids::{ClientID, TransactionID},
+    positive_decimal::PositiveDecimal,
+    transaction::Transaction,
+};
+
+/// How disputes, resolves and chargebacks find the deposit they refer to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchBy {
+    /// By the tx id of the deposit
+    #[default]
+    Tx,
+    /// By client and amount, for feeds whose tx ids aren't unique. The tx column of a dispute,
+    /// resolve or chargeback holds the occurrence (1 = first deposit of that amount, ...) or 0
+    /// if the amount is unique
+    Composite,
+}
+
+#[derive(Debug, Error)]
+pub enum CompositeMatchError {
+    #[error("missing amount, composite matching needs the amount of the referenced deposit")]
+    MissingAmount,
+    #[error("no deposit of {amount} for client {client} at occurrence {occurrence}")]
+    NotFound {
+        client: ClientID,
+        amount: PositiveDecimal,
+        occurrence: u32,
+    },
+    #[error(
+        "{matches} deposits of {amount} for client {client}, the tx column must select the occurrence"
+    )]
+    Ambiguous {
+        client: ClientID,
+        amount: PositiveDecimal,
+        matches: usize,
+    },
+    #[error("no tx id left to assign to the deposit")]
+    TxIdsExhausted,
+}
+
+/// Matches disputes, resolves and chargebacks to deposits by `(client, amount, occurrence)`.
+///
+/// The tx ids of the input aren't reliable, so every deposit gets a new unique tx id and the
+/// transactions referring to it are rewritten to that id. Matching is lossy: two deposits of the
+/// same amount can only be told apart by their order.
+///
+/// The assigned ids are above the highest id seen so far, i.e. the ids of the withdrawals read
+/// before the deposit and the id the matcher was created `after`. A withdrawal read later can
+/// still reuse an assigned id, which is harmless since only deposits must be unique per client.
+#[derive(Debug, Default)]
+pub struct CompositeMatcher {
+    /// Assigned tx ids per client and amount, in input order.
+    deposits: HashMap<(ClientID, PositiveDecimal), Vec<TransactionID>>,
+    /// Highest tx id in use, the next deposit gets the one after it.
+    last_tx: u32,
+}
+
+impl CompositeMatcher {
+    /// Creates a matcher assigning tx ids above `last_tx`, e.g. the highest id of the deposits
+    /// restored from a snapshot.
+    pub fn after(last_tx: TransactionID) -> Self {
+        Self {
+            last_tx: last_tx.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Rewrites the tx id of a transaction for composite matching.
+    ///
+    /// `amount` is the amount column of the row, which deposits are matched against.
+    ///
+    /// # Errors
+    /// - `MissingAmount`: A dispute, resolve or chargeback without an amount
+    /// - `NotFound`: No deposit matches the amount and occurrence
+    /// - `Ambiguous`: The occurrence is 0, but several deposits match the amount
+    /// - `TxIdsExhausted`: A deposit after one that was assigned the highest tx id
+    pub fn assign(
+        &mut self,
+        transaction: Transaction,
+        amount: Option<PositiveDecimal>,
+    ) -> Result<Transaction, CompositeMatchError> {
+        Ok(match transaction {
+            Transaction::Deposit(mut deposit) => {
+                self.last_tx = self
+                    .last_tx
+                    .checked_add(1)
+                    .ok_or(CompositeMatchError::TxIdsExhausted)?;
+                deposit.tx = TransactionID::new(self.last_tx);
+                self.deposits
+                    .entry((deposit.client, deposit.amount))
+                    .or_default()
+                    .push(deposit.tx);
+                Transaction::Deposit(deposit)
+            }
+            Transaction::Dispute(mut dispute) => {
+                dispute.tx = self.find(dispute.client, dispute.tx, amount)?;
+                Transaction::Dispute(dispute)
+            }
+            Transaction::Resolve(mut resolve) => {
+                resolve.tx = self.find(resolve.client, resolve.tx, amount)?;
+                Transaction::Resolve(resolve)
+            }
+            Transaction::Chargeback(mut chargeback) => {
+                chargeback.tx = self.find(chargeback.client, chargeback.tx, amount)?;
+                Transaction::Chargeback(chargeback)
+            }
+            transaction @ (Transaction::Withdrawal(_)
+            | Transaction::Release(_)
+            | Transaction::Open(_)) => {
+                self.last_tx = self.last_tx.max(transaction.tx().into());
+                transaction
+            }
+        })
+    }
+
+    /// Returns the assigned tx id of the deposit matching the client, amount and occurrence.
+    fn find(
+        &self,
+        client: ClientID,
+        occurrence: TransactionID,
+        amount: Option<PositiveDecimal>,
+    ) -> Result<TransactionID, CompositeMatchError> {
+        let amount = amount.ok_or(CompositeMatchError::MissingAmount)?;
+        let matches = self
+            .deposits
+            .get(&(client, amount))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let occurrence = u32::from(occurrence);
+        let not_found = CompositeMatchError::NotFound {
+            client,
+            amount,
+            occurrence,
+        };
+        match (occurrence, matches) {
+            (0, [tx]) => Ok(*tx),
+            (0, []) => Err(not_found),
+            (0, _) => Err(CompositeMatchError::Ambiguous {
+                client,
+                amount,
+                matches: matches.len(),
+            }),
+            (occurrence, _) => matches
+                .get(occurrence as usize - 1)
+                .copied()
+                .ok_or(not_found),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction::{Deposit, Dispute, Withdrawal};
+
+    fn deposit(tx: u32, amount: rust_decimal::Decimal) -> Transaction {
+        Transaction::Deposit(Deposit {
+            client: ClientID::new(1),
+            tx: TransactionID::new(tx),
+            amount: amount.try_into().unwrap(),
+        })
+    }
+
+    fn dispute(occurrence: u32) -> Transaction {
+        Transaction::Dispute(Dispute {
+            client: ClientID::new(1),
+            tx: TransactionID::new(occurrence),
+            amount: None,
+            reason: None,
+        })
+    }
+
+    fn amount(amount: rust_decimal::Decimal) -> Option<PositiveDecimal> {
+        Some(amount.try_into().unwrap())
+    }
+
+    #[test]
+    fn test_composite_unique_match() {
+        let mut matcher = CompositeMatcher::default();
+        // the repeated tx id gets a new unique id
+        for transaction in [deposit(7, dec!(1.0)), deposit(7, dec!(2.0))] {
+            matcher.assign(transaction, None).unwrap();
+        }
+
+        let disputed = matcher.assign(dispute(0), amount(dec!(2.00))).unwrap();
+        assert_eq!(disputed, dispute(2));
+    }
+
+    #[test]
+    fn test_composite_ambiguous_match() {
+        let mut matcher = CompositeMatcher::default();
+        for transaction in [
+            deposit(1, dec!(1.0)),
+            deposit(1, dec!(2.0)),
+            deposit(1, dec!(1.0)),
+        ] {
+            matcher.assign(transaction, None).unwrap();
+        }
+
+        assert!(matches!(
+            matcher.assign(dispute(0), amount(dec!(1.0))),
+            Err(CompositeMatchError::Ambiguous { matches: 2, .. })
+        ));
+        // the occurrence picks one of them
+        assert_eq!(
+            matcher.assign(dispute(2), amount(dec!(1.0))).unwrap(),
+            dispute(3)
+        );
+        assert!(matches!(
+            matcher.assign(dispute(3), amount(dec!(1.0))),
+            Err(CompositeMatchError::NotFound { occurrence: 3, .. })
+        ));
+        assert!(matches!(
+            matcher.assign(dispute(1), None),
+            Err(CompositeMatchError::MissingAmount)
+        ));
+    }
+
+    #[test]
+    fn test_composite_ids_above_seen_ids() {
+        // e.g. a snapshot with a deposit 10 was restored
+        let mut matcher = CompositeMatcher::after(TransactionID::new(10));
+        let assigned = matcher.assign(deposit(1, dec!(1.0)), None).unwrap();
+        assert_eq!(assigned, deposit(11, dec!(1.0)));
+
+        let withdrawal = Transaction::Withdrawal(Withdrawal {
+            client: ClientID::new(1),
+            tx: TransactionID::new(20),
+            amount: dec!(1.0).try_into().unwrap(),
+        });
+        matcher.assign(withdrawal, None).unwrap();
+        let assigned = matcher.assign(deposit(2, dec!(1.0)), None).unwrap();
+        assert_eq!(assigned, deposit(21, dec!(1.0)));
+
+        let mut matcher = CompositeMatcher::after(TransactionID::new(u32::MAX));
+        assert!(matches!(
+            matcher.assign(deposit(1, dec!(1.0)), None),
+            Err(CompositeMatchError::TxIdsExhausted)
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    num::NonZeroUsize,
+};
+
+use csv::StringRecord;
+
+/// Detects duplicate records among the most recent ones, using bounded memory.
+///
+/// Only the hashes of the last `size` distinct records are kept, so a duplicate further apart
+/// than that isn't detected. Records are compared by a 64 bit hash of their fields, a collision
+/// makes a record look like a duplicate, which is unlikely enough to be ignored.
+#[derive(Debug)]
+pub struct DedupWindow {
+    size: NonZeroUsize,
+    /// Hashes in the order they were seen, the oldest one is evicted first.
+    order: VecDeque<u64>,
+    hashes: HashSet<u64>,
+}
+
+impl DedupWindow {
+    pub fn new(size: NonZeroUsize) -> Self {
+        Self {
+            size,
+            order: VecDeque::with_capacity(size.get()),
+            hashes: HashSet::with_capacity(size.get()),
+        }
+    }
+
+    /// Adds a record to the window, returns `false` if it's a duplicate of a record in the window.
+    pub fn insert(&mut self, record: &StringRecord) -> bool {
+        // hashing the fields one by one keeps their boundaries, `a,bc` and `ab,c` differ
+        let mut hasher = DefaultHasher::new();
+        record.iter().for_each(|field| field.hash(&mut hasher));
+        let hash = hasher.finish();
+
+        if self.hashes.contains(&hash) {
+            return false;
+        }
+
+        if self.order.len() == self.size.get() {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        self.order.push_back(hash);
+        self.hashes.insert(hash);
+        true
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+
+use crate::transaction::Transaction;
+
+/// Expression selecting the transactions to process, e.g. `type=deposit && amount>100`.
+///
+/// ```text
+/// filter     = all ("||" all)*
+/// all        = term ("&&" term)*
+/// term       = comparison | "(" filter ")"
+/// comparison = field op value
+/// field      = "type" | "client" | "tx" | "amount"
+/// op         = "=" | "==" | "!=" | "<" | "<=" | ">" | ">="
+/// ```
+///
+/// `&&` binds tighter than `||`. Types are compared by name and only with `=` and `!=`, the other
+/// fields by number. Comparisons with the amount of a transaction without one are false.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Any(Vec<Filter>),
+    All(Vec<Filter>),
+    Compare(Field, Op, Value),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    Type(&'static str),
+    Number(Decimal),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FilterError {
+    #[error("unexpected end of the filter")]
+    UnexpectedEnd,
+    #[error("unexpected '{0}' in the filter")]
+    UnexpectedToken(String),
+    #[error("unknown field '{0}', expected type, client, tx or amount")]
+    UnknownField(String),
+    #[error("invalid value '{value}' for {field}")]
+    InvalidValue { field: &'static str, value: String },
+    #[error("type can only be compared with = and !=")]
+    TypeOrdering,
+}
+
+impl Filter {
+    /// Returns whether the transaction matches, `amount` being its amount as read.
+    pub fn matches(&self, transaction: &Transaction, amount: Option<Decimal>) -> bool {
+        match self {
+            Filter::Any(filters) => filters
+                .iter()
+                .any(|filter| filter.matches(transaction, amount)),
+            Filter::All(filters) => filters
+                .iter()
+                .all(|filter| filter.matches(transaction, amount)),
+            Filter::Compare(field, op, value) => {
+                let ordering = match (field, value) {
+                    (Field::Type, Value::Type(name)) => {
+                        return (transaction.type_name() == *name) == (*op == Op::Eq);
+                    }
+                    (Field::Client, Value::Number(number)) => {
+                        Decimal::from(u16::from(transaction.client())).cmp(number)
+                    }
+                    (Field::Tx, Value::Number(number)) => {
+                        Decimal::from(u32::from(transaction.tx())).cmp(number)
+                    }
+                    (Field::Amount, Value::Number(number)) => match amount {
+                        Some(amount) => amount.cmp(number),
+                        None => return false,
+                    },
+                    // the parser only pairs types with type values
+                    _ => return false,
+                };
+                op.holds(ordering)
+            }
+        }
+    }
+}
+
+impl Display for Filter {
+    /// Writes the filter as an expression that parses back to the same filter, with parentheses
+    /// only where the precedence requires them.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (filters, separator) = match self {
+            Filter::Any(filters) => (filters, " || "),
+            Filter::All(filters) => (filters, " && "),
+            Filter::Compare(field, op, value) => {
+                let field = match field {
+                    Field::Type => "type",
+                    Field::Client => "client",
+                    Field::Tx => "tx",
+                    Field::Amount => "amount",
+                };
+                let op = match op {
+                    Op::Eq => "=",
+                    Op::Ne => "!=",
+                    Op::Lt => "<",
+                    Op::Le => "<=",
+                    Op::Gt => ">",
+                    Op::Ge => ">=",
+                };
+                return match value {
+                    Value::Type(name) => write!(f, "{field}{op}{name}"),
+                    Value::Number(number) => write!(f, "{field}{op}{number}"),
+                };
+            }
+        };
+
+        for (index, filter) in filters.iter().enumerate() {
+            if index > 0 {
+                write!(f, "{separator}")?;
+            }
+            match (self, filter) {
+                (Filter::Any(_), Filter::Any(_))
+                | (Filter::All(_), Filter::Any(_) | Filter::All(_)) => write!(f, "({filter})")?,
+                _ => write!(f, "{filter}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Serialized as the expression, e.g. for the effective config in the run metadata.
+impl Serialize for Filter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Op {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s),
+            position: 0,
+        };
+        let filter = parser.any()?;
+        match parser.next() {
+            None => Ok(filter),
+            Some(token) => Err(FilterError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+/// Splits a filter into words (fields and values), operators and parentheses.
+fn tokenize(s: &str) -> Vec<&str> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == '-';
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if is_word(c) {
+            rest.find(|c| !is_word(c)).unwrap_or(rest.len())
+        } else if ["&&", "||", "==", "!=", "<=", ">="]
+            .iter()
+            .any(|op| rest.starts_with(op))
+        {
+            2
+        } else {
+            c.len_utf8()
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn any(&mut self) -> Result<Filter, FilterError> {
+        let mut filters = vec![self.all()?];
+        while self.peek() == Some("||") {
+            self.next();
+            filters.push(self.all()?);
+        }
+
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::Any(filters),
+        })
+    }
+
+    fn all(&mut self) -> Result<Filter, FilterError> {
+        let mut filters = vec![self.term()?];
+        while self.peek() == Some("&&") {
+            self.next();
+            filters.push(self.term()?);
+        }
+
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::All(filters),
+        })
+    }
+
+    fn term(&mut self) -> Result<Filter, FilterError> {
+        let field = match self.next().ok_or(FilterError::UnexpectedEnd)? {
+            "(" => {
+                let filter = self.any()?;
+                return match self.next() {
+                    Some(")") => Ok(filter),
+                    Some(token) => Err(FilterError::UnexpectedToken(token.to_string())),
+                    None => Err(FilterError::UnexpectedEnd),
+                };
+            }
+            "type" => Field::Type,
+            "client" => Field::Client,
+            "tx" => Field::Tx,
+            "amount" => Field::Amount,
+            field => return Err(FilterError::UnknownField(field.to_string())),
+        };
+        let op = match self.next().ok_or(FilterError::UnexpectedEnd)? {
+            "=" | "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            token => return Err(FilterError::UnexpectedToken(token.to_string())),
+        };
+        let value = self.next().ok_or(FilterError::UnexpectedEnd)?;
+        let invalid = |field| FilterError::InvalidValue {
+            field,
+            value: value.to_string(),
+        };
+        let value = match field {
+            Field::Type if !matches!(op, Op::Eq | Op::Ne) => return Err(FilterError::TypeOrdering),
+            Field::Type => Transaction::TYPE_NAMES
+                .into_iter()
+                .find(|name| *name == value)
+                .map(Value::Type)
+                .ok_or_else(|| invalid("type"))?,
+            Field::Client => value
+                .parse::<u16>()
+                .map(|client| Value::Number(client.into()))
+                .map_err(|_| invalid("client"))?,
+            Field::Tx => value
+                .parse::<u32>()
+                .map(|tx| Value::Number(tx.into()))
+                .map_err(|_| invalid("tx"))?,
+            Field::Amount => Decimal::from_str(value)
+                .map(Value::Number)
+                .map_err(|_| invalid("amount"))?,
+        };
+
+        Ok(Filter::Compare(field, op, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        ids::{ClientID, TransactionID},
+        transaction::{Deposit, Resolve, Withdrawal},
+    };
+
+    #[test]
+    fn test_filter() {
+        let deposit = |client, tx, amount: Decimal| {
+            Transaction::Deposit(Deposit {
+                client: ClientID::new(client),
+                tx: TransactionID::new(tx),
+                amount: amount.try_into().unwrap(),
+            })
+        };
+        let withdrawal = Transaction::Withdrawal(Withdrawal {
+            client: ClientID::new(2),
+            tx: TransactionID::new(3),
+            amount: dec!(500).try_into().unwrap(),
+        });
+        let resolve = Transaction::Resolve(Resolve {
+            client: ClientID::new(1),
+            tx: TransactionID::new(1),
+        });
+
+        let filter: Filter = "type=deposit && amount>100".parse().unwrap();
+        assert!(filter.matches(&deposit(1, 1, dec!(100.5)), Some(dec!(100.5))));
+        assert!(!filter.matches(&deposit(1, 1, dec!(100)), Some(dec!(100))));
+        assert!(!filter.matches(&withdrawal, Some(dec!(500))));
+
+        // && binds tighter than ||, comparing a missing amount is false
+        let filter: Filter = "client == 2 || type != deposit && amount <= 1"
+            .parse()
+            .unwrap();
+        assert!(filter.matches(&withdrawal, Some(dec!(500))));
+        assert!(!filter.matches(&resolve, None));
+        assert!(!filter.matches(&deposit(1, 1, dec!(1)), Some(dec!(1))));
+
+        let filter: Filter = "(client=1 || client=2) && (tx<2 || tx>=3)".parse().unwrap();
+        assert!(filter.matches(&resolve, None));
+        assert!(filter.matches(&withdrawal, Some(dec!(500))));
+        assert!(!filter.matches(&deposit(1, 2, dec!(1)), Some(dec!(1))));
+
+        // the expression written back parses to the same filter
+        for expression in [
+            "type=deposit && amount>100",
+            "client=2 || type!=deposit && amount<=1.5",
+            "(client=1 || client=2) && (tx<2 || tx>=3)",
+        ] {
+            let filter: Filter = expression.parse().unwrap();
+            assert_eq!(filter.to_string(), expression);
+        }
+
+        for (filter, err) in [
+            ("", FilterError::UnexpectedEnd),
+            ("type=deposit &&", FilterError::UnexpectedEnd),
+            ("(tx=1", FilterError::UnexpectedEnd),
+            ("tx=1 tx=2", FilterError::UnexpectedToken("tx".into())),
+            ("fee>1", FilterError::UnknownField("fee".into())),
+            ("type>deposit", FilterError::TypeOrdering),
+            (
+                "client=70000",
+                FilterError::InvalidValue {
+                    field: "client",
+                    value: "70000".into(),
+                },
+            ),
+        ] {
+            assert_eq!(filter.parse::<Filter>(), Err(err), "{filter}");
+        }
+    }
+}
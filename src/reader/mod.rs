@@ -1,54 +1,828 @@
-use std::io;
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    num::NonZeroUsize,
+};
 
-use csv::{ReaderBuilder, Trim};
-use raw_transaction::RawTransaction;
+use clap::ValueEnum;
+use composite::CompositeMatcher;
+use csv::{Position, ReaderBuilder, StringRecord, Terminator, Trim};
+use dedup::DedupWindow;
+use normalize_lines::NormalizeLines;
+use raw_transaction::{
+    amount_scale, RawTransaction, RawTransactionConvertError, RawTransactionType, COLUMNS,
+};
+use retry::RetryInterrupted;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
 use tracing::warn;
 
-use crate::transaction::Transaction;
+use crate::{
+    ids::{ClientID, TransactionID},
+    positive_decimal::{PositiveDecimal, PositiveDecimalError},
+    transaction::Transaction,
+};
 
+mod canonical;
+mod composite;
+mod dedup;
+mod filter;
+mod normalize_lines;
 mod raw_transaction;
+mod replay_log;
+mod retry;
+
+pub use canonical::{TransactionWriter, SCHEMA_VERSION};
+pub use composite::{CompositeMatchError, MatchBy};
+pub use filter::{Filter, FilterError};
+pub use replay_log::{ReplayLogError, ReplayLogReader, ReplayLogWriter};
+
+/// Options controlling how the `TransactionReader` treats its input.
+#[derive(Clone, Debug, Default)]
+pub struct ReaderConfig {
+    /// Abort on a header row repeated mid-file instead of skipping it, other invalid rows are
+    /// skipped either way.
+    pub strict: bool,
+    /// Reject rows when the input has columns other than the known transaction columns.
+    pub strict_columns: bool,
+    /// Ignore columns after the known transaction columns, e.g. a comment appended by some feeds.
+    /// Rows may have more fields than the header, and unknown columns at the end of the header
+    /// pass `strict_columns`. Rows with fewer fields than the header miss their last columns.
+    pub ignore_trailing_columns: bool,
+    /// Abort if a deposit or withdrawal has no amount instead of skipping the row.
+    pub fail_on_missing_amount: bool,
+    /// Report deposits and withdrawals whose tx id differs by more than this from the previous one,
+    /// which indicates dropped messages in a gapless feed. Warns, or fails in strict mode.
+    pub max_tx_gap: Option<u32>,
+    /// Reject amounts with more decimal places than this. Checked on the raw text, so inputs with
+    /// huge scales are rejected without parsing them.
+    pub max_amount_scale: Option<u32>,
+    /// Reject amounts with fewer decimal places than this, e.g. a bare `5` in a feed whose amounts
+    /// always have 2 decimal places. Checked on the raw text like `max_amount_scale`.
+    pub require_fractional_scale: Option<u32>,
+    /// The type column holds numeric codes (1 = deposit, 2 = withdrawal, ...) instead of names,
+    /// see `RawTransactionType::from_code`.
+    pub type_codes: bool,
+    /// Remove all whitespace (including non-breaking spaces) from the type before matching it.
+    pub normalize_type_whitespace: bool,
+    /// Round amounts to this many decimal places, with halves rounded away from zero.
+    /// Amounts that round to zero are invalid.
+    pub round_amounts: Option<u32>,
+    /// Warn about amounts with more significant decimal places than this, e.g. more than the
+    /// output keeps. They are read at full precision all the same, see `precision_warnings`.
+    pub warn_amount_scale: Option<u32>,
+    /// How disputes, resolves and chargebacks refer to deposits.
+    pub match_by: MatchBy,
+    /// Highest tx id already in use, e.g. by the deposits restored from a snapshot. Composite
+    /// matching assigns the deposits tx ids above it.
+    pub last_used_tx: Option<TransactionID>,
+    /// Only return a deterministic sample of the transactions, see `Sample`.
+    pub sample: Option<Sample>,
+    /// Only return transactions matching this expression, see `Filter`.
+    pub filter: Option<Filter>,
+    /// Reject transactions for client id 0, for systems that reserve it as a sentinel.
+    pub reject_client_zero: bool,
+    /// Drop withdrawals of zero as no-ops, some feeds send them as heartbeats. Zero deposits are
+    /// invalid either way.
+    pub allow_zero_withdrawals: bool,
+    /// Abort once the input has more than this many lines, including the header. Guards against
+    /// runaway inputs independently of how many rows are valid.
+    pub max_line_count: Option<u64>,
+    /// Drop records identical to one of this many previous records, see `DedupWindow`.
+    pub dedup_window: Option<NonZeroUsize>,
+    /// Size of the read buffer in bytes, `None` uses the csv default of 8 KiB.
+    pub buffer_capacity: Option<NonZeroUsize>,
+    /// Which whitespace around fields is removed.
+    pub trim: TrimMode,
+    /// What to do with rows that have a negative amount.
+    pub negative_amount: NegativeAmount,
+}
+
+/// Format of the transactions to read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFormat {
+    /// CSV with a header, read by the `TransactionReader`
+    #[default]
+    Csv,
+    /// JSON lines of applied transactions written by `--replay-log-out`, see `ReplayLogReader`
+    ReplayLog,
+}
+
+/// Policy for rows with a negative amount, which is invalid for every transaction type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NegativeAmount {
+    /// Skip the row like any other invalid row
+    #[default]
+    Skip,
+    /// Abort reading
+    Abort,
+    /// Treat a deposit of a negative amount as a withdrawal of the absolute amount, for feeds that
+    /// encode withdrawals that way. Other negative amounts are skipped
+    Withdrawal,
+}
+
+/// Which leading and trailing whitespace the reader removes, see `csv::Trim`.
+///
+/// The `\r` of CRLF line endings is removed in every mode, see `NormalizeLines`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrimMode {
+    /// Trim the header and all fields
+    #[default]
+    All,
+    /// Trim only the header, e.g. for `type, client, tx, amount` headers
+    Headers,
+    /// Trim only the fields
+    Fields,
+    /// Keep all whitespace, e.g. in reason codes. Ids and amounts with whitespace are invalid
+    None,
+}
+
+impl From<TrimMode> for Trim {
+    fn from(mode: TrimMode) -> Self {
+        match mode {
+            TrimMode::All => Trim::All,
+            TrimMode::Headers => Trim::Headers,
+            TrimMode::Fields => Trim::Fields,
+            TrimMode::None => Trim::None,
+        }
+    }
+}
+
+/// Selects a reproducible fraction of the transactions by hashing their tx id.
+///
+/// Disputes, resolves and chargebacks are selected together with the deposit they refer to, but
+/// balances are still meaningless, since e.g. withdrawals may be kept while deposits are dropped.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    /// Fraction of the transactions to keep, between 0 and 1.
+    pub rate: f64,
+    /// Seed mixed into the hash, different seeds select different samples.
+    pub seed: u64,
+}
+
+impl Sample {
+    /// Returns whether the transaction with this tx id is part of the sample.
+    pub fn contains(&self, tx: TransactionID) -> bool {
+        // splitmix64 finalizer, spreads consecutive tx ids uniformly over the u64 range
+        let mut hash = u64::from(u32::from(tx)) ^ self.seed;
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+
+        (hash as f64) < self.rate * u64::MAX as f64
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReaderError {
+    #[error("failed to read input: {0}")]
+    Io(#[source] csv::Error),
+    #[error(
+        "line {line}: header row found mid-file, the input is likely several concatenated files"
+    )]
+    MidFileHeader { line: u64 },
+    #[error("line {line}: unknown columns: {}", columns.join(", "))]
+    UnknownColumns { line: u64, columns: Vec<String> },
+    #[error(
+        "line {line}: tx id jumped from {previous} to {current}, messages may have been dropped"
+    )]
+    TxGap {
+        line: u64,
+        previous: TransactionID,
+        current: TransactionID,
+    },
+    #[error("input has schema version {found}, expected {expected}")]
+    SchemaVersion { found: u32, expected: u32 },
+    #[error("input exceeds the maximum of {max} lines")]
+    LineCount { max: u64 },
+    #[error("line {line}: unknown transaction type code '{code}'")]
+    UnknownTypeCode { line: u64, code: String },
+    #[error("line {line}: client id 0 is reserved")]
+    ClientZero { line: u64 },
+    #[error("line {line}: amount has {scale} decimal places, at most {max} are allowed")]
+    AmountScale { line: u64, scale: usize, max: u32 },
+    #[error("line {line}: amount has {scale} decimal places, at least {min} are required")]
+    AmountScaleBelowMinimum { line: u64, scale: usize, min: u32 },
+    #[error("line {line}: invalid amount {amount}: {source}")]
+    InvalidAmount {
+        line: u64,
+        amount: PositiveDecimal,
+        #[source]
+        source: PositiveDecimalError,
+    },
+    #[error("line {line}: {source}")]
+    CompositeMatch {
+        line: u64,
+        #[source]
+        source: CompositeMatchError,
+    },
+    #[error("line {line}: {source}")]
+    InvalidRecord {
+        line: u64,
+        #[source]
+        source: csv::Error,
+    },
+    #[error("line {line}: {source}")]
+    InvalidTransaction {
+        line: u64,
+        #[source]
+        source: RawTransactionConvertError,
+    },
+}
+
+impl ReaderError {
+    /// Returns a short snake case name of the error, used to count errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReaderError::Io(_) => "io",
+            ReaderError::MidFileHeader { .. } => "mid_file_header",
+            ReaderError::UnknownColumns { .. } => "unknown_columns",
+            ReaderError::TxGap { .. } => "tx_gap",
+            ReaderError::SchemaVersion { .. } => "schema_version",
+            ReaderError::LineCount { .. } => "line_count",
+            ReaderError::UnknownTypeCode { .. } => "unknown_type_code",
+            ReaderError::ClientZero { .. } => "client_zero",
+            ReaderError::AmountScale { .. } => "amount_scale",
+            ReaderError::AmountScaleBelowMinimum { .. } => "amount_scale_below_minimum",
+            ReaderError::InvalidAmount { .. } => "invalid_amount",
+            ReaderError::CompositeMatch { .. } => "composite_match",
+            ReaderError::InvalidRecord { .. } => "invalid_record",
+            ReaderError::InvalidTransaction {
+                source: RawTransactionConvertError::MissingAmount { .. },
+                ..
+            } => "missing_amount",
+            ReaderError::InvalidTransaction {
+                source: RawTransactionConvertError::InvalidAmount { .. },
+                ..
+            } => "invalid_amount",
+        }
+    }
+}
+
+/// Input of the csv reader, a first line starting with `#` put back in front of the rest.
+type CsvInput<R> =
+    io::Chain<io::Cursor<Vec<u8>>, NormalizeLines<io::BufReader<RetryInterrupted<R>>>>;
 
 pub struct TransactionReader<R> {
-    iter: csv::DeserializeRecordsIntoIter<R, RawTransaction>,
+    reader: csv::Reader<CsvInput<R>>,
+    /// Schema version of a schema comment that doesn't match `SCHEMA_VERSION`, until reported.
+    schema_mismatch: Option<u32>,
+    /// Lines the csv reader didn't see so far, i.e. 1 if a schema comment was consumed and the
+    /// blank lines dropped by `NormalizeLines`.
+    line_offset: u64,
+    headers: StringRecord,
+    type_index: Option<usize>,
+    amount_index: Option<usize>,
+    unknown_columns: Vec<String>,
+    record: StringRecord,
+    /// Number of raw records read so far, including the header.
+    lines: u64,
+    last_tx: Option<TransactionID>,
+    skipped: usize,
+    /// Number of skipped rows per `ReaderError::kind`.
+    skip_reasons: BTreeMap<&'static str, usize>,
+    /// Number of transactions whose amount exceeded `warn_amount_scale`.
+    precision_warnings: usize,
+    matcher: CompositeMatcher,
+    dedup: Option<DedupWindow>,
+    config: ReaderConfig,
 }
 
 impl<R> TransactionReader<R>
 where
     R: io::Read,
 {
-    pub fn new(reader: R) -> Self {
-        let reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
-        let iter = reader.into_deserialize();
+    pub fn new(reader: R, config: ReaderConfig) -> Self {
+        let (reader, schema_version) = Self::csv_reader(reader, &config);
+        let mut transaction_reader = Self {
+            reader,
+            schema_mismatch: None,
+            line_offset: 0,
+            headers: StringRecord::new(),
+            type_index: None,
+            amount_index: None,
+            unknown_columns: Vec::new(),
+            record: StringRecord::new(),
+            lines: 0,
+            last_tx: None,
+            skipped: 0,
+            skip_reasons: BTreeMap::new(),
+            precision_warnings: 0,
+            matcher: config
+                .last_used_tx
+                .map(CompositeMatcher::after)
+                .unwrap_or_default(),
+            dedup: config.dedup_window.map(DedupWindow::new),
+            config,
+        };
+        transaction_reader.read_headers(schema_version);
+        transaction_reader
+    }
+
+    /// Continues with the next of several inputs, e.g. the files of a directory, which starts
+    /// with its own header.
+    ///
+    /// The state spanning the inputs is kept, i.e. the composite tx ids, the dedup window, the tx
+    /// gap check and the skip counts, while line numbers restart at the new input.
+    pub fn continue_with(&mut self, reader: R) {
+        let (reader, schema_version) = Self::csv_reader(reader, &self.config);
+        self.reader = reader;
+        self.read_headers(schema_version);
+    }
+
+    /// Creates the csv reader of an input, returning it with the version of its schema comment.
+    fn csv_reader(reader: R, config: &ReaderConfig) -> (csv::Reader<CsvInput<R>>, Option<u32>) {
+        let mut builder = ReaderBuilder::new();
+        // with the default CRLF terminator the csv reader reports the line of every record after
+        // a CRLF line ending one too low, so CRLF is turned into `\n` before the csv reader, see
+        // `NormalizeLines`
+        builder
+            .terminator(Terminator::Any(b'\n'))
+            .trim(config.trim.into())
+            .flexible(config.ignore_trailing_columns);
+        if let Some(capacity) = config.buffer_capacity {
+            builder.buffer_capacity(capacity.get());
+        }
+        // a schema comment of a re-ingested canonical file isn't part of the csv input
+        let mut input = NormalizeLines::new(io::BufReader::new(RetryInterrupted(reader)));
+        let (prefix, schema_version) = canonical::read_schema_comment(&mut input);
+        let reader = builder.from_reader(io::Cursor::new(prefix).chain(input));
+
+        (reader, schema_version)
+    }
+
+    /// Reads the header of the current input and resets the line count.
+    fn read_headers(&mut self, schema_version: Option<u32>) {
+        // in case the header can't be read, the same error is reported by the first `next_transaction` call
+        let headers = self.reader.headers().cloned().unwrap_or_default();
+        self.type_index = headers.iter().position(|column| column == "type");
+        self.amount_index = headers.iter().position(|column| column == "amount");
+        let known_end = match self.config.ignore_trailing_columns {
+            true => headers
+                .iter()
+                .enumerate()
+                .filter(|(_, column)| COLUMNS.contains(column))
+                .last()
+                .map_or(0, |(index, _)| index + 1),
+            false => headers.len(),
+        };
+        self.unknown_columns = headers
+            .iter()
+            .take(known_end)
+            .filter(|column| !COLUMNS.contains(column))
+            .map(String::from)
+            .collect();
+        self.headers = headers;
 
-        Self { iter }
+        self.schema_mismatch = schema_version.filter(|version| *version != SCHEMA_VERSION);
+        self.line_offset = u64::from(schema_version.is_some());
+        self.lines = 1 + self.line_offset;
     }
 
-    pub fn next(&mut self) -> Option<Transaction> {
+    /// Returns the next valid transaction, or `None` once the input is exhausted.
+    ///
+    /// Invalid rows are skipped with a warning, except for the ones the config aborts on.
+    ///
+    /// # Errors
+    /// - `Io`: The underlying reader failed
+    /// - `MidFileHeader`: Header row repeated mid-file in strict mode
+    /// - `InvalidTransaction`: Missing amount with `fail_on_missing_amount`, negative amount with
+    ///   `NegativeAmount::Abort`
+    pub fn next_transaction(&mut self) -> Result<Option<Transaction>, ReaderError> {
+        Ok(self
+            .next_keyed_transaction()?
+            .map(|(transaction, _)| transaction))
+    }
+
+    /// Same as `next_transaction`, but also returns the optional idempotency key of the row.
+    ///
+    /// # Errors
+    /// See `next_transaction`.
+    pub fn next_keyed_transaction(
+        &mut self,
+    ) -> Result<Option<(Transaction, Option<String>)>, ReaderError> {
+        // a mismatching schema version is reported once, before the first row
+        if let Some(found) = self.schema_mismatch.take() {
+            let err = ReaderError::SchemaVersion {
+                found,
+                expected: SCHEMA_VERSION,
+            };
+            if self.config.strict {
+                return Err(err);
+            }
+            warn!("{}", err);
+        }
+
         // loop until we are able to return a valid transaction
         loop {
-            // try to get the next raw transaction
-            let raw_transaction_result = self.iter.next()?;
-
-            // in case we fail, print a warning and continue
-            let raw_transaction = match raw_transaction_result {
-                Ok(raw_transaction) => raw_transaction,
+            // try to read the next raw record, stop at the end of the input
+            let result = self.reader.read_record(&mut self.record);
+            let has_record = match &result {
+                Ok(read) => *read,
+                Err(err) => !err.is_io_error(),
+            };
+            if has_record {
+                self.lines += 1;
+                // the limit is a safety net, so it aborts even when not in strict mode
+                if let Some(max) = self.config.max_line_count.filter(|max| self.lines > *max) {
+                    return Err(ReaderError::LineCount { max });
+                }
+            }
+            match result {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(err) if err.is_io_error() => return Err(ReaderError::Io(err)),
                 Err(err) => {
-                    warn!("skipping invalid transaction: {}", err);
+                    let line = self.line(err.position());
+                    self.skip(ReaderError::InvalidRecord { line, source: err })?;
                     continue;
                 }
-            };
+            }
+
+            let position = self.record.position().cloned();
+            let line = self.line(position.as_ref());
+
+            // fields without a header, e.g. a trailing comment, aren't part of the transaction
+            if self.config.ignore_trailing_columns {
+                self.record.truncate(self.headers.len());
+            }
+
+            // a repeated header row usually means that several files were concatenated
+            if self.is_repeated_header() {
+                self.skip(ReaderError::MidFileHeader { line })?;
+                continue;
+            }
+
+            // redelivered records are dropped, they aren't invalid so strict mode doesn't apply
+            if let Some(dedup) = &mut self.dedup {
+                if !dedup.insert(&self.record) {
+                    warn!(line, "dropping duplicate record");
+                    self.skipped += 1;
+                    *self.skip_reasons.entry("duplicate_record").or_default() += 1;
+                    continue;
+                }
+            }
+
+            // unknown columns are ignored, unless they are explicitly rejected
+            if self.config.strict_columns && !self.unknown_columns.is_empty() {
+                let columns = self.unknown_columns.clone();
+                self.skip(ReaderError::UnknownColumns { line, columns })?;
+                continue;
+            }
+
+            // some feeds have internal or non-breaking whitespace in the type, e.g. `de posit`
+            if self.config.normalize_type_whitespace {
+                self.normalize_type_whitespace();
+            }
+
+            // legacy feeds encode the type as a number, replace it by the name before deserializing
+            if self.config.type_codes {
+                if let Err(code) = self.decode_type_code() {
+                    self.skip(ReaderError::UnknownTypeCode { line, code })?;
+                    continue;
+                }
+            }
+
+            // reject over-scaled amounts before parsing them
+            if let Some(scale) = self.excess_amount_scale() {
+                let max = self.config.max_amount_scale.unwrap_or_default();
+                self.skip(ReaderError::AmountScale { line, scale, max })?;
+                continue;
+            }
+
+            // fixed-scale feeds write every amount with all decimal places, anything else is a
+            // formatting error
+            if let Some(scale) = self.insufficient_amount_scale() {
+                let min = self.config.require_fractional_scale.unwrap_or_default();
+                self.skip(ReaderError::AmountScaleBelowMinimum { line, scale, min })?;
+                continue;
+            }
+
+            // try to deserialize the record into a raw transaction
+            let mut raw_transaction: RawTransaction =
+                match self.record.deserialize(Some(&self.headers)) {
+                    Ok(raw_transaction) => raw_transaction,
+                    Err(source) => {
+                        self.skip(ReaderError::InvalidRecord { line, source })?;
+                        continue;
+                    }
+                };
+
+            // heartbeats are valid rows, so they don't count as skipped
+            if self.config.allow_zero_withdrawals && raw_transaction.is_zero_withdrawal() {
+                continue;
+            }
+
+            // some feeds send withdrawals as negative deposits
+            if self.config.negative_amount == NegativeAmount::Withdrawal
+                && raw_transaction.transaction_type == RawTransactionType::Deposit
+            {
+                if let Some(amount) = raw_transaction.amount.filter(Decimal::is_sign_negative) {
+                    raw_transaction.transaction_type = RawTransactionType::Withdrawal;
+                    raw_transaction.amount = Some(-amount);
+                }
+            }
+
+            // the precision of the amount as read, rounding it below is a loss the user asked for
+            let excess_scale = self
+                .config
+                .warn_amount_scale
+                .zip(raw_transaction.amount)
+                .map(|(max, amount)| (max, amount.normalize().scale()))
+                .filter(|(max, scale)| scale > max);
+
+            // round the amount, an amount that rounds to zero is no longer a valid amount
+            if let (Some(scale), Some(amount)) =
+                (self.config.round_amounts, raw_transaction.positive_amount())
+            {
+                match amount.round(scale) {
+                    Ok(rounded) => raw_transaction.amount = Some(rounded.into()),
+                    Err(source) => {
+                        self.skip(ReaderError::InvalidAmount {
+                            line,
+                            amount,
+                            source,
+                        })?;
+                        continue;
+                    }
+                }
+            }
 
             // try to convert the raw transaction to a transaction
-            // in case we fail, print a warning and continue
-            match raw_transaction.try_into() {
-                Ok(transaction) => return Some(transaction),
-                Err(err) => {
-                    warn!("skipping invalid transaction: {}", err);
+            let amount = raw_transaction.positive_amount();
+            let key = raw_transaction.idempotency_key.take();
+            match Transaction::try_from(raw_transaction) {
+                Ok(transaction) => {
+                    if self.config.reject_client_zero && transaction.client() == ClientID::new(0) {
+                        self.skip(ReaderError::ClientZero { line })?;
+                        continue;
+                    }
+                    // every transaction counts for the gap and the composite ids, also the ones
+                    // left out below, which then see the same ids as with the full input
+                    self.check_tx_gap(line, &transaction)?;
+                    let transaction = match self.config.match_by {
+                        MatchBy::Tx => transaction,
+                        MatchBy::Composite => match self.matcher.assign(transaction, amount) {
+                            Ok(transaction) => transaction,
+                            Err(source) => {
+                                self.skip(ReaderError::CompositeMatch { line, source })?;
+                                continue;
+                            }
+                        },
+                    };
+                    // transactions outside the sample are valid, so they don't count as skipped
+                    if let Some(sample) = self.config.sample {
+                        if !sample.contains(transaction.tx()) {
+                            continue;
+                        }
+                    }
+                    // same for transactions not matching the filter
+                    if let Some(filter) = &self.config.filter {
+                        if !filter.matches(&transaction, amount.map(Decimal::from)) {
+                            continue;
+                        }
+                    }
+                    if let Some((max, scale)) = excess_scale {
+                        warn!(
+                            line,
+                            scale, max, "amount has more decimal places than the output"
+                        );
+                        self.precision_warnings += 1;
+                    }
+                    return Ok(Some((transaction, key)));
+                }
+                Err(source) => {
+                    self.skip(ReaderError::InvalidTransaction { line, source })?;
                     continue;
                 }
             }
         }
     }
+
+    /// Returns the line of the input at a position of the csv reader.
+    fn line(&mut self, position: Option<&Position>) -> u64 {
+        let Some(position) = position else {
+            return self.line_offset;
+        };
+        let (_, lines) = self.reader.get_mut().get_mut();
+        self.line_offset += lines.dropped_before(position.byte());
+        position.line() + self.line_offset
+    }
+
+    /// Replaces the numeric type code of the current record by the name of the transaction type.
+    ///
+    /// Returns the code if it's unknown.
+    fn decode_type_code(&mut self) -> Result<(), String> {
+        // a missing type column is reported when deserializing the record
+        let Some(code) = self.type_index.and_then(|index| self.record.get(index)) else {
+            return Ok(());
+        };
+        let transaction_type =
+            RawTransactionType::from_code(code).ok_or_else(|| code.to_string())?;
+
+        self.replace_type(&transaction_type.to_string());
+
+        Ok(())
+    }
+
+    /// Removes all whitespace, including non-breaking spaces, from the type of the current record.
+    fn normalize_type_whitespace(&mut self) {
+        let Some(value) = self.type_index.and_then(|index| self.record.get(index)) else {
+            return;
+        };
+        if value.contains(char::is_whitespace) {
+            let normalized = value.split(char::is_whitespace).collect::<String>();
+            self.replace_type(&normalized);
+        }
+    }
+
+    /// Replaces the type field of the current record.
+    fn replace_type(&mut self, value: &str) {
+        self.record = self
+            .record
+            .iter()
+            .enumerate()
+            .map(|(index, field)| match Some(index) == self.type_index {
+                true => value,
+                false => field,
+            })
+            .collect();
+    }
+
+    /// Returns the scale of the current record's amount if it exceeds `max_amount_scale`.
+    fn excess_amount_scale(&self) -> Option<usize> {
+        let max = self.config.max_amount_scale?;
+        let amount = self.record.get(self.amount_index?)?;
+        let scale = amount_scale(amount);
+        (scale > max as usize).then_some(scale)
+    }
+
+    /// Returns the scale of the current record's amount if it's below `require_fractional_scale`.
+    /// Rows without an amount are left to the deserializer.
+    fn insufficient_amount_scale(&self) -> Option<usize> {
+        let min = self.config.require_fractional_scale?;
+        let amount = self.record.get(self.amount_index?)?;
+        let scale = amount_scale(amount);
+        (!amount.is_empty() && scale < min as usize).then_some(scale)
+    }
+
+    /// Tracks the tx ids of deposits and withdrawals and reports jumps larger than `max_tx_gap`.
+    ///
+    /// The transaction itself is still valid, so it's only rejected in strict mode.
+    fn check_tx_gap(&mut self, line: u64, transaction: &Transaction) -> Result<(), ReaderError> {
+        let (Some(max_tx_gap), Some(current)) = (self.config.max_tx_gap, new_tx_id(transaction))
+        else {
+            return Ok(());
+        };
+
+        let previous = self.last_tx.replace(current);
+        if let Some(previous) = previous {
+            if u32::from(current).abs_diff(u32::from(previous)) > max_tx_gap {
+                let err = ReaderError::TxGap {
+                    line,
+                    previous,
+                    current,
+                };
+                if self.config.strict {
+                    return Err(err);
+                }
+                warn!("{}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns the reader into an iterator over chunks of up to `size` transactions.
+    ///
+    /// The last chunk holds the remaining transactions and may be smaller than `size`.
+    /// A read error ends the iteration, after the transactions read before it have been returned.
+    ///
+    /// # Panics
+    /// Panics if `size` is zero.
+    pub fn chunks(
+        mut self,
+        size: usize,
+    ) -> impl Iterator<Item = Result<Vec<Transaction>, ReaderError>> {
+        assert!(size > 0, "chunk size must be non-zero");
+
+        let mut error = None;
+        let mut done = false;
+
+        std::iter::from_fn(move || {
+            // report a pending error after the chunk read before it
+            if let Some(err) = error.take() {
+                return Some(Err(err));
+            }
+
+            let mut chunk = Vec::with_capacity(size);
+            while !done && chunk.len() < size {
+                match self.next_transaction() {
+                    Ok(Some(transaction)) => chunk.push(transaction),
+                    Ok(None) => done = true,
+                    Err(err) => {
+                        error = Some(err);
+                        done = true;
+                    }
+                }
+            }
+
+            match chunk.is_empty() {
+                true => error.take().map(Err),
+                false => Some(Ok(chunk)),
+            }
+        })
+    }
+
+    /// Returns whether the current record repeats the header row.
+    ///
+    /// The csv reader only strips the byte order mark of the first line, a concatenated file
+    /// (e.g. an Excel export) still has it at the start of its header. The mark also keeps the
+    /// csv reader from recognizing the quotes of the first field, so they are removed as well.
+    fn is_repeated_header(&self) -> bool {
+        let mut fields = self.record.iter();
+        let first = fields
+            .next()
+            .map(|field| match field.strip_prefix('\u{feff}') {
+                Some(field) => field
+                    .strip_prefix('"')
+                    .and_then(|field| field.strip_suffix('"'))
+                    .unwrap_or(field),
+                None => field,
+            });
+        first.into_iter().chain(fields).eq(self.headers.iter())
+    }
+
+    /// Returns the number of invalid rows skipped so far.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Returns the number of rows skipped so far per kind of error, e.g. `missing_amount`.
+    /// Dropped duplicates are counted as `duplicate_record`.
+    pub fn skip_reasons(&self) -> &BTreeMap<&'static str, usize> {
+        &self.skip_reasons
+    }
+
+    /// Returns the number of transactions read so far whose amount has more decimal places than
+    /// `warn_amount_scale`.
+    pub fn precision_warnings(&self) -> usize {
+        self.precision_warnings
+    }
+
+    /// Prints a warning for an invalid row, or returns the error in case it should abort reading.
+    fn skip(&mut self, err: ReaderError) -> Result<(), ReaderError> {
+        let missing_amount = matches!(
+            err,
+            ReaderError::InvalidTransaction {
+                source: RawTransactionConvertError::MissingAmount { .. },
+                ..
+            }
+        );
+
+        let negative_amount = matches!(
+            err,
+            ReaderError::InvalidTransaction {
+                source: RawTransactionConvertError::InvalidAmount {
+                    source: PositiveDecimalError::NegativeAmount,
+                    ..
+                },
+                ..
+            }
+        );
+
+        // a repeated header means concatenated files, which strict mode doesn't put up with
+        let mid_file_header = matches!(err, ReaderError::MidFileHeader { .. });
+
+        if (self.config.strict && mid_file_header)
+            || (self.config.fail_on_missing_amount && missing_amount)
+            || (self.config.negative_amount == NegativeAmount::Abort && negative_amount)
+        {
+            return Err(err);
+        }
+
+        warn!("skipping invalid transaction: {}", err);
+        self.skipped += 1;
+        *self.skip_reasons.entry(err.kind()).or_default() += 1;
+        Ok(())
+    }
+}
+
+/// Returns the id of transactions that introduce a new tx id, i.e. deposits and withdrawals.
+fn new_tx_id(transaction: &Transaction) -> Option<TransactionID> {
+    match transaction {
+        Transaction::Deposit(deposit) => Some(deposit.tx),
+        Transaction::Withdrawal(withdrawal) => Some(withdrawal.tx),
+        Transaction::Dispute(_)
+        | Transaction::Resolve(_)
+        | Transaction::Chargeback(_)
+        | Transaction::Release(_)
+        | Transaction::Open(_) => None,
+    }
 }
 
 #[cfg(test)]
@@ -56,9 +830,9 @@ mod tests {
     use rust_decimal_macros::dec;
 
     use crate::{
-        ids::{ClientID, TransactionID},
+        ids::ClientID,
         positive_decimal::PositiveDecimal,
-        transaction::{Deposit, Withdrawal},
+        transaction::{Deposit, Dispute, Withdrawal},
     };
 
     use super::*;
@@ -70,10 +844,10 @@ deposit,1,1,1.1
 withdrawal,2,3,4.5678"#;
 
         let csv_bytes = csv.as_bytes();
-        let mut reader = TransactionReader::new(csv_bytes);
+        let mut reader = TransactionReader::new(csv_bytes, ReaderConfig::default());
 
         assert_eq!(
-            reader.next(),
+            reader.next_transaction().unwrap(),
             Some(Transaction::Deposit(Deposit {
                 amount: PositiveDecimal::new(dec!(1.1)).unwrap(),
                 client: ClientID::new(1),
@@ -82,7 +856,7 @@ withdrawal,2,3,4.5678"#;
         );
 
         assert_eq!(
-            reader.next(),
+            reader.next_transaction().unwrap(),
             Some(Transaction::Withdrawal(Withdrawal {
                 amount: PositiveDecimal::new(dec!(4.5678)).unwrap(),
                 client: ClientID::new(2),
@@ -90,23 +864,907 @@ withdrawal,2,3,4.5678"#;
             }))
         );
 
-        assert_eq!(reader.next(), None);
+        assert_eq!(reader.next_transaction().unwrap(), None);
     }
 
     #[test]
     fn test_skip_invalid() {
         let csv = "type, client, tx, amount\ninvalid,999,999,9.9999\ndeposit,-1,999,9.9999\ndeposit,-1,-999\ndeposit,1,1,1.1";
         let csv_bytes = csv.as_bytes();
-        let mut reader = TransactionReader::new(csv_bytes);
+        let mut reader = TransactionReader::new(csv_bytes, ReaderConfig::default());
+
+        assert_eq!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Deposit(Deposit {
+                amount: PositiveDecimal::new(dec!(1.1)).unwrap(),
+                client: ClientID::new(1),
+                tx: TransactionID::new(1)
+            }))
+        );
+        assert_eq!(reader.next_transaction().unwrap(), None);
+        assert_eq!(reader.skipped(), 3);
+    }
+
+    #[test]
+    fn test_mid_file_header_skipped() {
+        let csv =
+            "type, client, tx, amount\ndeposit,1,1,1.1\ntype, client, tx, amount\ndeposit,1,2,2.2";
+        let csv_bytes = csv.as_bytes();
+        let mut reader = TransactionReader::new(csv_bytes, ReaderConfig::default());
 
         assert_eq!(
-            reader.next(),
+            reader.next_transaction().unwrap(),
             Some(Transaction::Deposit(Deposit {
                 amount: PositiveDecimal::new(dec!(1.1)).unwrap(),
                 client: ClientID::new(1),
                 tx: TransactionID::new(1)
             }))
         );
-        assert_eq!(reader.next(), None);
+        assert_eq!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Deposit(Deposit {
+                amount: PositiveDecimal::new(dec!(2.2)).unwrap(),
+                client: ClientID::new(1),
+                tx: TransactionID::new(2)
+            }))
+        );
+        assert_eq!(reader.next_transaction().unwrap(), None);
+    }
+
+    #[test]
+    fn test_mid_file_header_strict_aborts() {
+        let csv =
+            "type, client, tx, amount\ndeposit,1,1,1.1\ntype, client, tx, amount\ndeposit,1,2,2.2";
+        let csv_bytes = csv.as_bytes();
+        let mut reader = TransactionReader::new(
+            csv_bytes,
+            ReaderConfig {
+                strict: true,
+                ..Default::default()
+            },
+        );
+
+        assert!(reader.next_transaction().unwrap().is_some());
+
+        let err = reader
+            .next_transaction()
+            .expect_err("mid-file header should abort in strict mode");
+        assert!(matches!(err, ReaderError::MidFileHeader { line: 3 }));
+        assert!(err.to_string().starts_with("line 3:"));
+    }
+
+    #[test]
+    fn test_chunks() {
+        let csv = "type, client, tx, amount\ndeposit,1,1,1\ndeposit,1,2,2\ninvalid,1,3,3\ndeposit,1,4,4\ndeposit,1,5,5\ndeposit,1,6,6";
+        let csv_bytes = csv.as_bytes();
+        let reader = TransactionReader::new(csv_bytes, ReaderConfig::default());
+
+        let chunks = reader
+            .chunks(2)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("chunks should be valid");
+
+        let sizes = chunks.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![2, 2, 1]);
+
+        assert_eq!(
+            chunks[2],
+            vec![Transaction::Deposit(Deposit {
+                amount: PositiveDecimal::new(dec!(6)).unwrap(),
+                client: ClientID::new(1),
+                tx: TransactionID::new(6)
+            })]
+        );
+    }
+
+    #[test]
+    fn test_buffer_capacity_does_not_change_result() {
+        let csv = "type, client, tx, amount\ndeposit,1,1,1.1\nwithdrawal,1,2,0.5\ninvalid,1,3,3\ndeposit,2,4,123.4567\ndispute,2,4,";
+
+        let read_all = |buffer_capacity| {
+            let config = ReaderConfig {
+                buffer_capacity,
+                ..Default::default()
+            };
+            TransactionReader::new(csv.as_bytes(), config)
+                .chunks(100)
+                .collect::<Result<Vec<_>, _>>()
+                .expect("transactions should be valid")
+        };
+
+        let expected = read_all(None);
+        assert_eq!(expected[0].len(), 4);
+        for buffer_capacity in [1, 7, 64, 1 << 20] {
+            assert_eq!(read_all(NonZeroUsize::new(buffer_capacity)), expected);
+        }
+    }
+
+    #[test]
+    fn test_fail_on_missing_amount() {
+        let csv = "type, client, tx, amount\ndeposit,1,1,1.1\ndeposit,1,2,\ndeposit,1,3,3.3";
+
+        // skipped by default
+        let reader = TransactionReader::new(csv.as_bytes(), ReaderConfig::default());
+        let transactions = reader
+            .chunks(10)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("amount-less deposit should be skipped");
+        assert_eq!(transactions[0].len(), 2);
+
+        // aborts with the flag
+        let config = ReaderConfig {
+            fail_on_missing_amount: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        assert!(reader.next_transaction().unwrap().is_some());
+
+        let err = reader
+            .next_transaction()
+            .expect_err("amount-less deposit should abort");
+        assert!(matches!(
+            err,
+            ReaderError::InvalidTransaction {
+                line: 3,
+                source: RawTransactionConvertError::MissingAmount { .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_schema_comment_round_trip() {
+        let transactions = vec![
+            Transaction::Deposit(Deposit {
+                client: ClientID::new(1),
+                tx: TransactionID::new(1),
+                amount: dec!(2.5).try_into().unwrap(),
+            }),
+            Transaction::Dispute(Dispute {
+                client: ClientID::new(1),
+                tx: TransactionID::new(1),
+                amount: None,
+                reason: Some("fraud".to_string()),
+            }),
+        ];
+        let mut output = Vec::new();
+        let mut writer = TransactionWriter::new(&mut output, true).unwrap();
+        for transaction in transactions.clone() {
+            writer.write(transaction).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        let csv = String::from_utf8(output).unwrap();
+        assert!(csv.starts_with(&format!("# schema_version: {SCHEMA_VERSION}\n")));
+
+        let read_all = |csv: &str| {
+            let config = ReaderConfig {
+                strict: true,
+                ..Default::default()
+            };
+            TransactionReader::new(csv.as_bytes(), config)
+                .chunks(10)
+                .collect::<Result<Vec<_>, _>>()
+                .map(|chunks| chunks.concat())
+        };
+
+        // matching version
+        assert_eq!(read_all(&csv).unwrap(), transactions);
+
+        // mismatching version, only fails in strict mode
+        let mismatching = csv.replacen(
+            &format!("schema_version: {SCHEMA_VERSION}"),
+            "schema_version: 99",
+            1,
+        );
+        assert!(matches!(
+            read_all(&mismatching),
+            Err(ReaderError::SchemaVersion {
+                found: 99,
+                expected: SCHEMA_VERSION
+            })
+        ));
+        let lenient = TransactionReader::new(mismatching.as_bytes(), ReaderConfig::default());
+        let read = lenient.chunks(10).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(read.concat(), transactions);
+
+        // line numbers still count the comment
+        let header = csv.lines().nth(1).unwrap();
+        let concatenated = format!("{csv}{header}\n");
+        assert!(matches!(
+            read_all(&concatenated),
+            Err(ReaderError::MidFileHeader { line: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_negative_amount() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,-5.0\ndeposit,1,2,1.0";
+        let reader = |negative_amount| {
+            let config = ReaderConfig {
+                negative_amount,
+                ..Default::default()
+            };
+            TransactionReader::new(csv.as_bytes(), config)
+        };
+
+        // skipped by default
+        let mut skip = reader(NegativeAmount::Skip);
+        assert!(matches!(
+            skip.next_transaction().unwrap(),
+            Some(Transaction::Deposit(Deposit { tx, .. })) if tx == TransactionID::new(2)
+        ));
+        assert_eq!(skip.skipped(), 1);
+
+        // aborts with the abort policy
+        let err = reader(NegativeAmount::Abort)
+            .next_transaction()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ReaderError::InvalidTransaction {
+                line: 2,
+                source: RawTransactionConvertError::InvalidAmount {
+                    source: PositiveDecimalError::NegativeAmount,
+                    ..
+                }
+            }
+        ));
+
+        // becomes a withdrawal of the absolute amount
+        let mut withdrawal = reader(NegativeAmount::Withdrawal);
+        assert_eq!(
+            withdrawal.next_transaction().unwrap(),
+            Some(Transaction::Withdrawal(Withdrawal {
+                client: ClientID::new(1),
+                tx: TransactionID::new(1),
+                amount: dec!(5.0).try_into().unwrap(),
+            }))
+        );
+        assert_eq!(withdrawal.skipped(), 0);
+    }
+
+    #[test]
+    fn test_strict_columns() {
+        let csv = "type, client, tx, amount, comment\ndeposit,1,1,1.1,hello";
+
+        let reader = TransactionReader::new(csv.as_bytes(), ReaderConfig::default());
+        let transactions = reader.chunks(10).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(transactions.len(), 1, "unknown column should be ignored");
+
+        let config = ReaderConfig {
+            strict_columns: true,
+            ..Default::default()
+        };
+        let reader = TransactionReader::new(csv.as_bytes(), config.clone());
+        let transactions = reader.chunks(10).collect::<Result<Vec<_>, _>>().unwrap();
+        assert!(transactions.is_empty(), "row should be rejected");
+
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        assert_eq!(reader.next_transaction().unwrap(), None);
+        assert_eq!(reader.skip_reasons().get("unknown_columns"), Some(&1));
+    }
+
+    #[test]
+    fn test_ignore_trailing_columns() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,1.5,note from ops\n\
+                   deposit,1,2,2.0\n\
+                   dispute,1,1,,duplicate, see ticket\n";
+
+        // without the flag the rows with extra fields are invalid
+        let reader = TransactionReader::new(csv.as_bytes(), ReaderConfig::default());
+        let transactions = reader.chunks(10).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(transactions.concat().len(), 1);
+
+        let config = ReaderConfig {
+            ignore_trailing_columns: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        let deposit = |tx, amount| {
+            Transaction::Deposit(Deposit {
+                client: ClientID::new(1),
+                tx: TransactionID::new(tx),
+                amount,
+            })
+        };
+        assert_eq!(
+            reader.next_transaction().unwrap(),
+            Some(deposit(1, dec!(1.5).try_into().unwrap()))
+        );
+        assert_eq!(
+            reader.next_transaction().unwrap(),
+            Some(deposit(2, dec!(2.0).try_into().unwrap()))
+        );
+        assert!(matches!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Dispute(Dispute { reason: None, .. }))
+        ));
+        assert_eq!(reader.skipped(), 0);
+
+        // a named trailing column passes strict columns, an unknown column before amount doesn't
+        for (header, valid) in [
+            ("type,client,tx,amount,comment", true),
+            ("type,client,tx,comment,amount", false),
+        ] {
+            let csv = format!("{header}\ndeposit,1,1,1.5,1.5\n");
+            let config = ReaderConfig {
+                strict_columns: true,
+                ignore_trailing_columns: true,
+                ..Default::default()
+            };
+            let mut reader = TransactionReader::new(csv.as_bytes(), config);
+            assert_eq!(
+                reader.next_transaction().unwrap().is_some(),
+                valid,
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_tx_gap() {
+        // the dispute doesn't count, only deposits and withdrawals introduce new tx ids
+        let csv = "type, client, tx, amount\ndeposit,1,1,1\nwithdrawal,1,3,1\ndispute,1,100,\ndeposit,1,4,1\ndeposit,1,10,1";
+
+        // the gap is only reported, all transactions are returned
+        let config = ReaderConfig {
+            max_tx_gap: Some(2),
+            ..Default::default()
+        };
+        let reader = TransactionReader::new(csv.as_bytes(), config);
+        let transactions = reader.chunks(10).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(transactions[0].len(), 5);
+
+        // strict mode fails on the gap
+        let config = ReaderConfig {
+            strict: true,
+            max_tx_gap: Some(2),
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        for _ in 0..4 {
+            assert!(reader.next_transaction().unwrap().is_some());
+        }
+        let err = reader.next_transaction().expect_err("gap should fail");
+        assert!(matches!(err, ReaderError::TxGap { line: 6, .. }));
+        assert_eq!(
+            err.to_string(),
+            "line 6: tx id jumped from 4 to 10, messages may have been dropped"
+        );
+    }
+
+    #[test]
+    fn test_max_amount_scale() {
+        let over_scaled = format!("1.{}1", "0".repeat(10_000));
+        let csv = format!(
+            "type, client, tx, amount\ndeposit,1,1,1.1234\ndeposit,1,2,{over_scaled}\ndispute,1,1,"
+        );
+
+        // the over-scaled deposit is skipped, rows without an amount are unaffected
+        let config = ReaderConfig {
+            max_amount_scale: Some(4),
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        assert!(reader.next_transaction().unwrap().is_some());
+        assert!(matches!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Dispute(_))
+        ));
+        assert_eq!(reader.skipped(), 1);
+
+        // rejected for its scale without parsing the amount
+        assert_eq!(reader.skip_reasons().get("amount_scale"), Some(&1));
+    }
+
+    #[test]
+    fn test_require_fractional_scale() {
+        let csv = "type, client, tx, amount\ndeposit,1,1,5\ndeposit,1,2,5.00\ndeposit,1,3,5.0\ndispute,1,2,";
+        let config = ReaderConfig {
+            require_fractional_scale: Some(2),
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        assert!(matches!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Deposit(Deposit { tx, .. })) if tx == TransactionID::new(2)
+        ));
+        // rows without an amount are unaffected
+        assert!(matches!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Dispute(_))
+        ));
+        assert_eq!(reader.skipped(), 2);
+        assert_eq!(
+            reader.skip_reasons().get("amount_scale_below_minimum"),
+            Some(&2)
+        );
+    }
+
+    #[test]
+    fn test_type_codes() {
+        let csv = "type, client, tx, amount\n1,1,1,1.0\n9,1,2,1.0\n3,1,1,";
+        let config = ReaderConfig {
+            type_codes: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config.clone());
+
+        assert_eq!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Deposit(Deposit {
+                amount: PositiveDecimal::new(dec!(1.0)).unwrap(),
+                client: ClientID::new(1),
+                tx: TransactionID::new(1)
+            }))
+        );
+        // the unknown code 9 is skipped
+        assert!(matches!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Dispute(_))
+        ));
+        assert_eq!(reader.next_transaction().unwrap(), None);
+        assert_eq!(reader.skipped(), 1);
+
+        // names aren't accepted in numeric-code mode
+        let mut reader =
+            TransactionReader::new("type,client,tx,amount\ndeposit,1,1,1.0".as_bytes(), config);
+        assert_eq!(reader.next_transaction().unwrap(), None);
+        assert_eq!(reader.skip_reasons().get("unknown_type_code"), Some(&1));
+    }
+
+    #[test]
+    fn test_warn_amount_scale() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,1.12345\n\
+                   deposit,1,2,1.12340\n\
+                   withdrawal,1,3,0.0001\n";
+        let config = ReaderConfig {
+            strict: true,
+            warn_amount_scale: Some(4),
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+
+        // the amount is kept at full precision, trailing zeros don't count
+        let amounts: Vec<_> = std::iter::from_fn(|| reader.next_transaction().unwrap())
+            .map(|transaction| match transaction {
+                Transaction::Deposit(Deposit { amount, .. })
+                | Transaction::Withdrawal(Withdrawal { amount, .. }) => Decimal::from(amount),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(amounts, [dec!(1.12345), dec!(1.12340), dec!(0.0001)]);
+        assert_eq!(reader.precision_warnings(), 1);
+        assert_eq!(reader.skipped(), 0);
+    }
+
+    #[test]
+    fn test_round_amounts() {
+        let csv = "type, client, tx, amount\ndeposit,1,1,1.23456\ndeposit,1,2,0.00001";
+        let config = ReaderConfig {
+            round_amounts: Some(4),
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+
+        assert_eq!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Deposit(Deposit {
+                amount: PositiveDecimal::new(dec!(1.2346)).unwrap(),
+                client: ClientID::new(1),
+                tx: TransactionID::new(1)
+            }))
+        );
+        // the amount rounds to zero
+        assert_eq!(reader.next_transaction().unwrap(), None);
+        assert_eq!(reader.skip_reasons().get("invalid_amount"), Some(&1));
+    }
+
+    #[test]
+    fn test_match_by_composite() {
+        let csv = "type, client, tx, amount\ndeposit,1,5,1.0\ndeposit,1,5,1.0\ndispute,1,0,1.0\ndispute,1,2,1.0";
+        let config = ReaderConfig {
+            match_by: MatchBy::Composite,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+
+        let deposit_ids = (0..2)
+            .map(|_| match reader.next_transaction().unwrap() {
+                Some(Transaction::Deposit(deposit)) => deposit.tx,
+                other => panic!("expected a deposit, got {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_ne!(deposit_ids[0], deposit_ids[1]);
+
+        // the ambiguous dispute is skipped, the one with an occurrence matches the second deposit
+        match reader.next_transaction().unwrap() {
+            Some(Transaction::Dispute(dispute)) => assert_eq!(dispute.tx, deposit_ids[1]),
+            other => panic!("expected a dispute, got {:?}", other),
+        }
+        assert_eq!(reader.skipped(), 1);
+    }
+
+    #[test]
+    fn test_continue_with() {
+        let config = ReaderConfig {
+            match_by: MatchBy::Composite,
+            strict: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(
+            "type,client,tx,amount\ndeposit,1,5,1.0\n".as_bytes(),
+            config,
+        );
+        let Some(Transaction::Deposit(deposit)) = reader.next_transaction().unwrap() else {
+            panic!("expected a deposit");
+        };
+        assert_eq!(reader.next_transaction().unwrap(), None);
+
+        // the header of the next input isn't a mid-file header, and a dispute in it matches the
+        // deposit of the first input
+        reader.continue_with("type,client,tx,amount\ndispute,1,0,1.0\n".as_bytes());
+        match reader.next_transaction().unwrap() {
+            Some(Transaction::Dispute(dispute)) => assert_eq!(dispute.tx, deposit.tx),
+            other => panic!("expected a dispute, got {:?}", other),
+        }
+        assert_eq!(reader.next_transaction().unwrap(), None);
+    }
+
+    #[test]
+    fn test_reject_client_zero() {
+        let csv = "type, client, tx, amount\ndeposit,0,1,1.0\ndeposit,1,2,1.0\ndispute,0,1,";
+        let config = ReaderConfig {
+            reject_client_zero: true,
+            ..Default::default()
+        };
+        let reader = TransactionReader::new(csv.as_bytes(), config.clone());
+        let transactions = reader.chunks(10).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].len(), 1);
+        assert_eq!(transactions[0][0].client(), ClientID::new(1));
+
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        while reader.next_transaction().unwrap().is_some() {}
+        assert_eq!(reader.skip_reasons().get("client_zero"), Some(&2));
+    }
+
+    #[test]
+    fn test_normalize_type_whitespace() {
+        let csv = "type, client, tx, amount\nde posit,1,1,1.0\nwith\u{a0}drawal,1,2,1.0";
+
+        // without normalization both rows are invalid
+        let mut reader = TransactionReader::new(csv.as_bytes(), ReaderConfig::default());
+        assert_eq!(reader.next_transaction().unwrap(), None);
+        assert_eq!(reader.skipped(), 2);
+
+        let config = ReaderConfig {
+            normalize_type_whitespace: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        assert!(matches!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Deposit(_))
+        ));
+        assert!(matches!(
+            reader.next_transaction().unwrap(),
+            Some(Transaction::Withdrawal(_))
+        ));
+        assert_eq!(reader.skipped(), 0);
+    }
+
+    #[test]
+    fn test_sample() {
+        let csv = (1..=10_000).fold("type,client,tx,amount\n".to_string(), |csv, tx| {
+            csv + &format!("deposit,1,{tx},1.0\n")
+        });
+        let sampled_ids = |seed| {
+            let config = ReaderConfig {
+                sample: Some(Sample { rate: 0.1, seed }),
+                ..Default::default()
+            };
+            let mut reader = TransactionReader::new(csv.as_bytes(), config);
+            let mut ids = Vec::new();
+            while let Some(transaction) = reader.next_transaction().unwrap() {
+                ids.push(transaction.tx());
+            }
+            assert_eq!(reader.skipped(), 0);
+            ids
+        };
+
+        let ids = sampled_ids(42);
+        assert!(
+            (900..=1100).contains(&ids.len()),
+            "sample size {}",
+            ids.len()
+        );
+        assert_eq!(ids, sampled_ids(42));
+        assert_ne!(ids, sampled_ids(7));
+
+        let all = Sample { rate: 1.0, seed: 0 };
+        let none = Sample { rate: 0.0, seed: 0 };
+        assert!(all.contains(TransactionID::new(1)));
+        assert!(!none.contains(TransactionID::new(1)));
+
+        // the ids left out by the sample aren't gaps in the input
+        let config = ReaderConfig {
+            strict: true,
+            max_tx_gap: Some(1),
+            sample: Some(Sample {
+                rate: 0.1,
+                seed: 42,
+            }),
+            ..Default::default()
+        };
+        let reader = TransactionReader::new(csv.as_bytes(), config);
+        let transactions = reader.chunks(100).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(transactions.concat().len(), ids.len());
+
+        // composite matching samples the assigned ids, so disputes stay with their deposits
+        let csv = (1..=100).fold("type,client,tx,amount\n".to_string(), |csv, amount| {
+            csv + &format!("deposit,1,1,{amount}.0\ndispute,1,0,{amount}.0\n")
+        });
+        let config = ReaderConfig {
+            match_by: MatchBy::Composite,
+            sample: Some(Sample {
+                rate: 0.5,
+                seed: 42,
+            }),
+            ..Default::default()
+        };
+        let reader = TransactionReader::new(csv.as_bytes(), config);
+        let transactions = reader.chunks(1000).next().unwrap().unwrap();
+        assert!((60..=140).contains(&transactions.len()));
+        for pair in transactions.chunks(2) {
+            assert!(
+                matches!(pair, [Transaction::Deposit(deposit), Transaction::Dispute(dispute)] if deposit.tx == dispute.tx)
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_after_tx_gap() {
+        // client 2 has every other tx id, but the input has no gap
+        let csv = (1..=10).fold("type,client,tx,amount\n".to_string(), |csv, tx| {
+            csv + &format!("deposit,{},{tx},1.0\n", tx % 2 + 1)
+        });
+        let config = ReaderConfig {
+            strict: true,
+            max_tx_gap: Some(1),
+            filter: Some("client=2".parse().unwrap()),
+            ..Default::default()
+        };
+        let reader = TransactionReader::new(csv.as_bytes(), config);
+        let transactions = reader.chunks(100).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(transactions.concat().len(), 5);
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let csv = "type,client,tx,amount,idempotency_key\ndeposit,1,1,1.0,abc\ndeposit,1,2,1.0,";
+        let mut reader = TransactionReader::new(csv.as_bytes(), ReaderConfig::default());
+
+        let (_, key) = reader.next_keyed_transaction().unwrap().unwrap();
+        assert_eq!(key.as_deref(), Some("abc"));
+        let (_, key) = reader.next_keyed_transaction().unwrap().unwrap();
+        assert_eq!(key, None);
+    }
+
+    #[test]
+    fn test_trim_none_keeps_reason_whitespace() {
+        let csv = "type,client,tx,amount,reason\ndeposit,1,1,1.0,\ndispute,1,1,, fraud \n";
+        let reason = |trim| {
+            let mut reader = TransactionReader::new(
+                csv.as_bytes(),
+                ReaderConfig {
+                    trim,
+                    ..Default::default()
+                },
+            );
+            reader.next_transaction().unwrap().unwrap();
+            match reader.next_transaction().unwrap().unwrap() {
+                Transaction::Dispute(dispute) => dispute.reason,
+                transaction => panic!("expected a dispute, got {transaction:?}"),
+            }
+        };
+
+        assert_eq!(reason(TrimMode::None).as_deref(), Some(" fraud "));
+        assert_eq!(reason(TrimMode::All).as_deref(), Some("fraud"));
+    }
+
+    #[test]
+    fn test_trim_crlf() {
+        // the line endings are never part of the last header or field, whatever is trimmed
+        let csv = "type,client,tx,amount\r\ndeposit,1,1,1.0\r\n\r\ndispute,1,1,\r\n";
+        let with_reason =
+            "type,client,tx,amount,reason\r\ndeposit,1,1,1.0,\r\ndispute,1,1,,fraud\r\n";
+        for trim in TrimMode::value_variants().iter().copied() {
+            let config = ReaderConfig {
+                strict_columns: true,
+                trim,
+                ..Default::default()
+            };
+            let reader = TransactionReader::new(csv.as_bytes(), config.clone());
+            let transactions = reader.chunks(10).collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(transactions.concat().len(), 2, "{trim:?}");
+
+            let mut reader = TransactionReader::new(with_reason.as_bytes(), config);
+            reader.next_transaction().unwrap().unwrap();
+            match reader.next_transaction().unwrap().unwrap() {
+                Transaction::Dispute(dispute) => {
+                    assert_eq!(dispute.reason.as_deref(), Some("fraud"), "{trim:?}")
+                }
+                transaction => panic!("expected a dispute, got {transaction:?}"),
+            }
+            assert_eq!(reader.skipped(), 0, "{trim:?}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo() {
+        use std::{fs::File, io::Write, process::Command, thread, time::Duration};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transactions");
+        let status = Command::new("mkfifo").arg(&path).status().unwrap();
+        assert!(status.success());
+
+        let writer_path = path.clone();
+        let writer = thread::spawn(move || {
+            let mut fifo = File::create(writer_path).unwrap();
+            // records are split across writes, with pauses in which the pipe is empty
+            for part in [
+                "type,client,tx,amount\ndeposit,1,",
+                "1,1.0\nwithd",
+                "rawal,1,2,0.5\n",
+            ] {
+                fifo.write_all(part.as_bytes()).unwrap();
+                fifo.flush().unwrap();
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let reader = TransactionReader::new(File::open(&path).unwrap(), ReaderConfig::default());
+        let transactions: Vec<_> = reader.chunks(10).map(Result::unwrap).collect();
+        writer.join().unwrap();
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].len(), 2);
+    }
+
+    #[test]
+    fn test_max_line_count() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ninvalid\ndeposit,1,2,1.0";
+        let config = ReaderConfig {
+            max_line_count: Some(3),
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+
+        assert!(reader.next_transaction().unwrap().is_some());
+        // the invalid row is counted as well
+        let err = reader.next_transaction().unwrap_err();
+        assert!(matches!(err, ReaderError::LineCount { max: 3 }));
+
+        let config = ReaderConfig {
+            max_line_count: Some(4),
+            ..Default::default()
+        };
+        let reader = TransactionReader::new(csv.as_bytes(), config);
+        assert_eq!(reader.chunks(10).map(Result::unwrap).count(), 1);
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let csv = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,1,1.0
+deposit,1,2,1.0
+deposit,1,3,1.0
+deposit,1,1,1.0";
+        let config = ReaderConfig {
+            dedup_window: NonZeroUsize::new(2),
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+
+        let mut txs = Vec::new();
+        while let Some(transaction) = reader.next_transaction().unwrap() {
+            txs.push(u32::from(transaction.tx()));
+        }
+        // the direct repeat is dropped, the one 3 records later is outside the window
+        assert_eq!(txs, vec![1, 2, 3, 1]);
+        assert_eq!(reader.skipped(), 1);
+    }
+
+    #[test]
+    fn test_mid_file_header_with_bom() {
+        let csv = "\u{feff}type,client,tx,amount\r\ndeposit,1,1,1.0\r\n\u{feff}\"type\",\"client\",\"tx\",\"amount\"\r\ndeposit,1,2,1.0\r\n";
+        let config = ReaderConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+
+        assert!(reader.next_transaction().unwrap().is_some());
+        let err = reader.next_transaction().unwrap_err();
+        assert!(matches!(err, ReaderError::MidFileHeader { line: 3 }));
+    }
+
+    #[test]
+    fn test_crlf_line_numbers() {
+        let csv =
+            "type,client,tx,amount\r\ndeposit,1,1,1.0\r\n\r\n\"type\",\"client\",\"tx\",\"amount\"\r\n";
+        let mut reader = TransactionReader::new(csv.as_bytes(), ReaderConfig::default());
+        assert!(reader.next_transaction().unwrap().is_some());
+        assert!(reader.next_transaction().unwrap().is_none());
+        // the blank line isn't an invalid row
+        assert_eq!(reader.skipped(), 1);
+
+        let config = ReaderConfig {
+            strict: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        assert!(reader.next_transaction().unwrap().is_some());
+        let err = reader.next_transaction().unwrap_err();
+        assert!(
+            matches!(err, ReaderError::MidFileHeader { line: 4 }),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        // blank lines aren't rows, also when rows may have fewer fields than the header
+        let csv = "type,client,tx,amount\r\n\r\ndeposit,1,1,1.0\r\n\n\r\ntype,client,tx,amount\n";
+        for ignore_trailing_columns in [false, true] {
+            let config = ReaderConfig {
+                strict: true,
+                ignore_trailing_columns,
+                ..Default::default()
+            };
+            let mut reader = TransactionReader::new(csv.as_bytes(), config);
+            assert!(reader.next_transaction().unwrap().is_some());
+            let err = reader.next_transaction().unwrap_err();
+            assert!(
+                matches!(err, ReaderError::MidFileHeader { line: 6 }),
+                "{err:?}"
+            );
+
+            let config = ReaderConfig {
+                ignore_trailing_columns,
+                ..Default::default()
+            };
+            let mut reader = TransactionReader::new(csv.as_bytes(), config);
+            while reader.next_transaction().unwrap().is_some() {}
+            assert_eq!(
+                reader.skip_reasons().keys().copied().collect::<Vec<_>>(),
+                ["mid_file_header"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_allow_zero_withdrawals() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,0.0\ndeposit,1,3,0\n";
+        let config = ReaderConfig {
+            allow_zero_withdrawals: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(csv.as_bytes(), config);
+        while reader.next_transaction().unwrap().is_some() {}
+        // only the zero deposit is invalid
+        assert_eq!(reader.skipped(), 1);
+        assert_eq!(reader.skip_reasons().get("invalid_amount"), Some(&1));
+
+        // without the option the zero withdrawal is invalid as well
+        let mut reader = TransactionReader::new(csv.as_bytes(), ReaderConfig::default());
+        while reader.next_transaction().unwrap().is_some() {}
+        assert_eq!(reader.skipped(), 2);
     }
 }
@@ -0,0 +1,126 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead},
+};
+
+/// Prepares the lines of the input for the csv reader: CRLF line endings become `\n` and blank
+/// lines are dropped, both only outside of quoted fields.
+///
+/// The csv reader uses a `\n` terminator to count the lines of CRLF input right, which would leave
+/// the `\r` at the end of the last field unless the fields are trimmed. Blank lines are skipped by
+/// the csv reader as well, but it then reports the next record on the line of the first blank one,
+/// so they are dropped here and counted by `dropped_before` instead.
+///
+/// Quotes are tracked by their parity, which works for the doubled quotes csv uses as escapes.
+#[derive(Debug)]
+pub struct NormalizeLines<R> {
+    inner: R,
+    /// The current line, with its line ending already converted.
+    line: Vec<u8>,
+    /// Number of bytes of `line` already consumed.
+    consumed: usize,
+    /// Whether the lines read so far end inside a quoted field.
+    in_quotes: bool,
+    /// Number of bytes consumed so far.
+    position: u64,
+    /// Positions of the dropped blank lines not yet counted by `dropped_before`.
+    dropped: VecDeque<u64>,
+}
+
+impl<R> NormalizeLines<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            line: Vec::new(),
+            consumed: 0,
+            in_quotes: false,
+            position: 0,
+            dropped: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of blank lines dropped before the byte offset `position` of the output,
+    /// every blank line is only counted by the first call that covers it.
+    pub fn dropped_before(&mut self, position: u64) -> u64 {
+        let mut count = 0;
+        while self
+            .dropped
+            .front()
+            .is_some_and(|dropped| *dropped <= position)
+        {
+            self.dropped.pop_front();
+            count += 1;
+        }
+        count
+    }
+}
+
+impl<R: BufRead> io::Read for NormalizeLines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for NormalizeLines<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.consumed == self.line.len() {
+            self.line.clear();
+            self.consumed = 0;
+            if self.inner.read_until(b'\n', &mut self.line)? == 0 {
+                break;
+            }
+            let quotes = self.line.iter().filter(|byte| **byte == b'"').count();
+            self.in_quotes ^= quotes % 2 == 1;
+            if self.in_quotes {
+                break;
+            }
+
+            // the last line may end with a `\r` alone
+            if self.line.ends_with(b"\r\n") {
+                self.line.truncate(self.line.len() - 2);
+                self.line.push(b'\n');
+            } else if self.line.ends_with(b"\r") {
+                self.line.pop();
+            }
+            if self.line.iter().all(|byte| *byte == b'\n') {
+                self.dropped.push_back(self.position);
+                self.line.clear();
+            }
+        }
+        Ok(&self.line[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        let amount = amount.min(self.line.len() - self.consumed);
+        self.consumed += amount;
+        self.position += amount as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_normalize_lines() {
+        // a tiny buffer splits the `\r\n` of the first line between two reads of the input
+        let input = "a,b\r\n\r\n\nc\rd\r\n\"e\r\n\r\nf\"\r\n\ng\r";
+        let mut lines = NormalizeLines::new(io::BufReader::with_capacity(4, input.as_bytes()));
+        let mut out = String::new();
+        lines.read_to_string(&mut out).unwrap();
+        // quoted line breaks are kept as they are
+        assert_eq!(out, "a,b\nc\rd\n\"e\r\n\r\nf\"\ng");
+
+        // two blank lines before `c`, one before `g`
+        assert_eq!(lines.dropped_before(3), 0);
+        assert_eq!(lines.dropped_before(4), 2);
+        assert_eq!(lines.dropped_before(4), 0);
+        assert_eq!(lines.dropped_before(u64::MAX), 1);
+    }
+}
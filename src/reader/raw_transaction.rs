@@ -1,25 +1,44 @@
 use std::fmt::Display;
 
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_variant::to_variant_name;
 use thiserror::Error;
 
 use crate::{
     ids::{ClientID, TransactionID},
-    positive_decimal::PositiveDecimal,
-    transaction::{Chargeback, Deposit, Dispute, Resolve, Transaction, Withdrawal},
+    positive_decimal::{PositiveDecimal, PositiveDecimalError},
+    transaction::{Chargeback, Deposit, Dispute, Open, Release, Resolve, Transaction, Withdrawal},
 };
 
 // Introduced RawTransaction to workaround a rust-csv issue
 // Tagged enums are not supported
 // https://github.com/BurntSushi/rust-csv/issues/211
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+/// Names of the columns `RawTransaction` is deserialized from.
+pub const COLUMNS: [&str; 6] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "reason",
+    "idempotency_key",
+];
+
+/// Serializing writes the columns of `COLUMNS`, so re-emitted rows can be read again.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct RawTransaction {
     #[serde(rename = "type")]
     pub transaction_type: RawTransactionType,
     pub client: ClientID,
     pub tx: TransactionID,
-    pub amount: Option<PositiveDecimal>,
+    /// Checked when converting to a `Transaction`, which amounts are valid depends on the type.
+    pub amount: Option<Decimal>,
+    /// Optional reason code of a dispute, the column may be missing from the input.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Optional key identifying redelivered messages, the column may be missing from the input.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -30,6 +49,32 @@ pub enum RawTransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Release,
+    Open,
+}
+
+impl RawTransactionType {
+    /// Maps the numeric type codes used by legacy feeds to a transaction type.
+    ///
+    /// | code | type       |
+    /// |------|------------|
+    /// | 1    | deposit    |
+    /// | 2    | withdrawal |
+    /// | 3    | dispute    |
+    /// | 4    | resolve    |
+    /// | 5    | chargeback |
+    /// | 6    | release    |
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.parse::<u8>().ok()? {
+            1 => Some(Self::Deposit),
+            2 => Some(Self::Withdrawal),
+            3 => Some(Self::Dispute),
+            4 => Some(Self::Resolve),
+            5 => Some(Self::Chargeback),
+            6 => Some(Self::Release),
+            _ => None,
+        }
+    }
 }
 
 impl Display for RawTransactionType {
@@ -45,6 +90,29 @@ pub enum RawTransactionConvertError {
     MissingAmount {
         transaction_type: RawTransactionType,
     },
+    #[error("invalid amount for transaction type '{transaction_type}': {source}")]
+    InvalidAmount {
+        transaction_type: RawTransactionType,
+        #[source]
+        source: PositiveDecimalError,
+    },
+}
+
+impl RawTransaction {
+    /// Returns whether this is a withdrawal of exactly zero, which some feeds send as a heartbeat.
+    ///
+    /// Such a withdrawal can't be converted to a `Transaction`, it's up to the caller to treat it
+    /// as a no-op instead of an invalid row.
+    pub fn is_zero_withdrawal(&self) -> bool {
+        self.transaction_type == RawTransactionType::Withdrawal
+            && self.amount.is_some_and(|amount| amount.is_zero())
+    }
+
+    /// Returns the amount if it's valid for any transaction type, i.e. present and positive.
+    pub fn positive_amount(&self) -> Option<PositiveDecimal> {
+        self.amount
+            .and_then(|amount| PositiveDecimal::new(amount).ok())
+    }
 }
 
 impl TryFrom<RawTransaction> for Transaction {
@@ -63,15 +131,33 @@ impl TryFrom<RawTransaction> for Transaction {
                 client: value.client,
                 tx: value.tx,
             }),
-            RawTransactionType::Dispute => Transaction::Dispute(Dispute {
+            RawTransactionType::Dispute => {
+                Transaction::Dispute(Dispute {
+                    client: value.client,
+                    tx: value.tx,
+                    amount: value.amount.map(PositiveDecimal::new).transpose().map_err(
+                        |source| RawTransactionConvertError::InvalidAmount {
+                            transaction_type: value.transaction_type,
+                            source,
+                        },
+                    )?,
+                    reason: value.reason,
+                })
+            }
+            RawTransactionType::Resolve => Transaction::Resolve(Resolve {
                 client: value.client,
                 tx: value.tx,
             }),
-            RawTransactionType::Resolve => Transaction::Resolve(Resolve {
+            RawTransactionType::Chargeback => Transaction::Chargeback(Chargeback {
                 client: value.client,
                 tx: value.tx,
             }),
-            RawTransactionType::Chargeback => Transaction::Chargeback(Chargeback {
+            RawTransactionType::Release => Transaction::Release(Release {
+                amount: get_transaction_amount(&value)?,
+                client: value.client,
+                tx: value.tx,
+            }),
+            RawTransactionType::Open => Transaction::Open(Open {
                 client: value.client,
                 tx: value.tx,
             }),
@@ -79,6 +165,54 @@ impl TryFrom<RawTransaction> for Transaction {
     }
 }
 
+impl From<Transaction> for RawTransaction {
+    /// Maps a transaction back to its row. Types without an amount get an empty amount column,
+    /// neither a missing column nor a zero would be read back as the same transaction.
+    fn from(transaction: Transaction) -> Self {
+        let (transaction_type, amount, reason) = match &transaction {
+            Transaction::Deposit(deposit) => {
+                (RawTransactionType::Deposit, Some(deposit.amount), None)
+            }
+            Transaction::Withdrawal(withdrawal) => (
+                RawTransactionType::Withdrawal,
+                Some(withdrawal.amount),
+                None,
+            ),
+            Transaction::Dispute(dispute) => (
+                RawTransactionType::Dispute,
+                dispute.amount,
+                dispute.reason.clone(),
+            ),
+            Transaction::Resolve(_) => (RawTransactionType::Resolve, None, None),
+            Transaction::Chargeback(_) => (RawTransactionType::Chargeback, None, None),
+            Transaction::Release(release) => {
+                (RawTransactionType::Release, Some(release.amount), None)
+            }
+            Transaction::Open(_) => (RawTransactionType::Open, None, None),
+        };
+
+        RawTransaction {
+            transaction_type,
+            client: transaction.client(),
+            tx: transaction.tx(),
+            amount: amount.map(Decimal::from),
+            reason,
+            idempotency_key: None,
+        }
+    }
+}
+
+/// Returns the number of digits after the decimal point of a raw amount field.
+///
+/// Only looks at the text, so it can be used to reject over-scaled amounts before paying for
+/// parsing them into a `Decimal`.
+pub fn amount_scale(amount: &str) -> usize {
+    amount
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len())
+}
+
+#[allow(clippy::unnecessary_lazy_evaluations)]
 fn get_transaction_amount(
     raw_transaction: &RawTransaction,
 ) -> Result<PositiveDecimal, RawTransactionConvertError> {
@@ -89,7 +223,11 @@ fn get_transaction_amount(
                 transaction_type: raw_transaction.transaction_type,
             })?;
 
-    Ok(amount)
+    // zero and negative amounts are rejected for every type, see `is_zero_withdrawal`
+    PositiveDecimal::new(amount).map_err(|source| RawTransactionConvertError::InvalidAmount {
+        transaction_type: raw_transaction.transaction_type,
+        source,
+    })
 }
 
 #[cfg(test)]
@@ -97,6 +235,59 @@ mod tests {
     use super::*;
     use rust_decimal_macros::dec;
 
+    #[test]
+    fn test_amount_scale() {
+        assert_eq!(amount_scale("1"), 0);
+        assert_eq!(amount_scale("1."), 0);
+        assert_eq!(amount_scale("1.1234"), 4);
+        assert_eq!(amount_scale("1.0000000000000000000000000001"), 28);
+        assert_eq!(amount_scale(&format!("1.{}", "0".repeat(5000))), 5000);
+    }
+
+    #[test]
+    fn test_type_from_code() {
+        assert_eq!(
+            RawTransactionType::from_code("1"),
+            Some(RawTransactionType::Deposit)
+        );
+        assert_eq!(
+            RawTransactionType::from_code("6"),
+            Some(RawTransactionType::Release)
+        );
+        assert_eq!(RawTransactionType::from_code("0"), None);
+        assert_eq!(RawTransactionType::from_code("7"), None);
+        assert_eq!(RawTransactionType::from_code("deposit"), None);
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let input = "type,client,tx,amount\ndispute,3,7,\ndeposit,3,8,1.5\n";
+        let mut reader = ::csv::Reader::from_reader(input.as_bytes());
+        let transactions: Vec<Transaction> = reader
+            .deserialize::<RawTransaction>()
+            .map(|raw| raw.unwrap().try_into().unwrap())
+            .collect();
+
+        let mut writer = ::csv::Writer::from_writer(vec![]);
+        for transaction in transactions.clone() {
+            writer.serialize(RawTransaction::from(transaction)).unwrap();
+        }
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "type,client,tx,amount,reason,idempotency_key\n\
+             dispute,3,7,,,\n\
+             deposit,3,8,1.5,,\n"
+        );
+
+        let mut reader = ::csv::Reader::from_reader(output.as_bytes());
+        let reparsed: Vec<Transaction> = reader
+            .deserialize::<RawTransaction>()
+            .map(|raw| raw.unwrap().try_into().unwrap())
+            .collect();
+        assert_eq!(reparsed, transactions);
+    }
+
     macro_rules! deserialize_test {
         ($test_name:ident, $csv_str:literal, $expected_raw_transaction:expr) => {
             #[test]
@@ -126,7 +317,9 @@ mod tests {
             transaction_type: RawTransactionType::Deposit,
             client: ClientID::new(1),
             tx: TransactionID::new(1),
-            amount: Some(PositiveDecimal::new(dec!(1.1)).unwrap()),
+            amount: Some(dec!(1.1)),
+            reason: None,
+            idempotency_key: None,
         }
     );
 
@@ -137,7 +330,9 @@ mod tests {
             transaction_type: RawTransactionType::Withdrawal,
             client: ClientID::new(2),
             tx: TransactionID::new(2),
-            amount: Some(PositiveDecimal::new(dec!(2.22)).unwrap()),
+            amount: Some(dec!(2.22)),
+            reason: None,
+            idempotency_key: None,
         }
     );
 
@@ -149,6 +344,8 @@ mod tests {
             client: ClientID::new(3),
             tx: TransactionID::new(3),
             amount: None,
+            reason: None,
+            idempotency_key: None,
         }
     );
 
@@ -160,6 +357,8 @@ mod tests {
             client: ClientID::new(4),
             tx: TransactionID::new(4),
             amount: None,
+            reason: None,
+            idempotency_key: None,
         }
     );
 
@@ -171,6 +370,107 @@ mod tests {
             client: ClientID::new(5),
             tx: TransactionID::new(5),
             amount: None,
+            reason: None,
+            idempotency_key: None,
+        }
+    );
+
+    deserialize_test!(
+        test_deserialize_leading_zeros,
+        "deposit, 007, 00000001, 1.1",
+        RawTransaction {
+            transaction_type: RawTransactionType::Deposit,
+            client: ClientID::new(7),
+            tx: TransactionID::new(1),
+            amount: Some(dec!(1.1)),
+            reason: None,
+            idempotency_key: None,
+        }
+    );
+
+    deserialize_test!(
+        test_deserialize_zero_ids,
+        "deposit, 0, 000, 1.1",
+        RawTransaction {
+            transaction_type: RawTransactionType::Deposit,
+            client: ClientID::new(0),
+            tx: TransactionID::new(0),
+            amount: Some(dec!(1.1)),
+            reason: None,
+            idempotency_key: None,
+        }
+    );
+
+    deserialize_test!(
+        test_deserialize_plus_sign_ids,
+        "deposit, +007, +1, 1.1",
+        RawTransaction {
+            transaction_type: RawTransactionType::Deposit,
+            client: ClientID::new(7),
+            tx: TransactionID::new(1),
+            amount: Some(dec!(1.1)),
+            reason: None,
+            idempotency_key: None,
+        }
+    );
+
+    deserialize_test!(
+        test_deserialize_release,
+        "release, 6, 6, 6.6",
+        RawTransaction {
+            transaction_type: RawTransactionType::Release,
+            client: ClientID::new(6),
+            tx: TransactionID::new(6),
+            amount: Some(dec!(6.6)),
+            reason: None,
+            idempotency_key: None,
         }
     );
+
+    #[test]
+    fn test_deserialize_dispute_reason() {
+        let csv = "type, client, tx, amount, reason\ndispute, 3, 3, , fraud\ndispute, 3, 4, ,";
+        let mut reader = ::csv::ReaderBuilder::new()
+            .trim(::csv::Trim::All)
+            .from_reader(csv.as_bytes());
+
+        let transactions = reader
+            .deserialize()
+            .collect::<Result<Vec<RawTransaction>, _>>()
+            .expect("valid raw transactions");
+
+        assert_eq!(transactions[0].reason.as_deref(), Some("fraud"));
+        assert_eq!(transactions[1].reason, None);
+        assert!(matches!(
+            Transaction::try_from(transactions[0].clone()).unwrap(),
+            Transaction::Dispute(Dispute { reason: Some(reason), .. }) if reason == "fraud"
+        ));
+    }
+
+    #[test]
+    fn test_zero_amounts() {
+        let raw = |transaction_type, amount| RawTransaction {
+            transaction_type,
+            client: ClientID::new(1),
+            tx: TransactionID::new(1),
+            amount: Some(amount),
+            reason: None,
+            idempotency_key: None,
+        };
+
+        let deposit = raw(RawTransactionType::Deposit, dec!(0.0));
+        assert!(!deposit.is_zero_withdrawal());
+        assert!(matches!(
+            Transaction::try_from(deposit),
+            Err(RawTransactionConvertError::InvalidAmount {
+                source: PositiveDecimalError::ZeroAmount,
+                ..
+            })
+        ));
+
+        let withdrawal = raw(RawTransactionType::Withdrawal, dec!(0.0));
+        assert!(withdrawal.is_zero_withdrawal());
+        assert!(Transaction::try_from(withdrawal).is_err());
+        assert!(!raw(RawTransactionType::Withdrawal, dec!(-1)).is_zero_withdrawal());
+    }
 }
@@ -0,0 +1,146 @@
+use std::io::{self, BufRead, Write};
+
+use thiserror::Error;
+
+use crate::transaction::Transaction;
+
+#[derive(Debug, Error)]
+pub enum ReplayLogError {
+    #[error("failed to read replay log: {0}")]
+    Io(#[source] io::Error),
+    #[error("line {line}: invalid replay log entry: {source}")]
+    Parse {
+        line: u64,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Writes applied transactions as JSON lines, one `Transaction` per line in the order they were
+/// applied, e.g. `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`.
+///
+/// Replaying the log with the same processor options reconstructs the final state, see
+/// `ReplayLogReader`.
+pub struct ReplayLogWriter<W: io::Write> {
+    writer: io::BufWriter<W>,
+}
+
+impl<W> ReplayLogWriter<W>
+where
+    W: io::Write,
+{
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: io::BufWriter::new(writer),
+        }
+    }
+
+    pub fn write(&mut self, transaction: &Transaction) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, transaction)?;
+        writeln!(self.writer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads the transactions of a replay log written by `ReplayLogWriter`.
+///
+/// Blank lines are ignored. The log only holds transactions that were valid when written, so any
+/// other line is an error instead of being skipped like an invalid CSV row.
+pub struct ReplayLogReader<R> {
+    reader: io::BufReader<R>,
+    line: String,
+    lines: u64,
+}
+
+impl<R> ReplayLogReader<R>
+where
+    R: io::Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: io::BufReader::new(reader),
+            line: String::new(),
+            lines: 0,
+        }
+    }
+
+    pub fn next_transaction(&mut self) -> Result<Option<Transaction>, ReplayLogError> {
+        loop {
+            self.line.clear();
+            if self
+                .reader
+                .read_line(&mut self.line)
+                .map_err(ReplayLogError::Io)?
+                == 0
+            {
+                return Ok(None);
+            }
+            self.lines += 1;
+
+            if self.line.trim().is_empty() {
+                continue;
+            }
+            return serde_json::from_str(&self.line)
+                .map(Some)
+                .map_err(|source| ReplayLogError::Parse {
+                    line: self.lines,
+                    source,
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        ids::{ClientID, TransactionID},
+        transaction::{Deposit, Dispute, Resolve},
+    };
+
+    #[test]
+    fn test_replay_log_round_trip() {
+        let (client, tx) = (ClientID::new(1), TransactionID::new(1));
+        let transactions = vec![
+            Transaction::Deposit(Deposit {
+                client,
+                tx,
+                amount: dec!(1.50).try_into().unwrap(),
+            }),
+            Transaction::Dispute(Dispute {
+                client,
+                tx,
+                amount: None,
+                reason: Some("fraud".to_string()),
+            }),
+            Transaction::Resolve(Resolve { client, tx }),
+        ];
+
+        let mut output = Vec::new();
+        let mut writer = ReplayLogWriter::new(&mut output);
+        for transaction in &transactions {
+            writer.write(transaction).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        output.extend_from_slice(b"\n");
+
+        let mut reader = ReplayLogReader::new(output.as_slice());
+        let mut read = Vec::new();
+        while let Some(transaction) = reader.next_transaction().unwrap() {
+            read.push(transaction);
+        }
+        assert_eq!(read, transactions);
+
+        let mut reader = ReplayLogReader::new("\n{\"type\":\"deposit\"}\n".as_bytes());
+        assert!(matches!(
+            reader.next_transaction(),
+            Err(ReplayLogError::Parse { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+
+/// Retries reads interrupted by a signal instead of reporting them as an error.
+///
+/// Reads from pipes and FIFOs block until data arrives and can be interrupted while waiting. The
+/// csv reader aborts the current record on any io error, so the read has to be retried before it
+/// gets there. A read of 0 bytes is only returned once the writer has closed the pipe, so it
+/// still marks the end of the input.
+#[derive(Debug)]
+pub struct RetryInterrupted<R>(pub R);
+
+impl<R: io::Read> io::Read for RetryInterrupted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    /// Fails every other read with `Interrupted`.
+    struct Flaky<R> {
+        inner: R,
+        interrupt: bool,
+    }
+
+    impl<R: io::Read> io::Read for Flaky<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            match self.interrupt {
+                true => Err(io::ErrorKind::Interrupted.into()),
+                false => self.inner.read(buf),
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_interrupted() {
+        let mut reader = RetryInterrupted(Flaky {
+            inner: "abc".as_bytes(),
+            interrupt: false,
+        });
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "abc");
+    }
+}
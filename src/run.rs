@@ -0,0 +1,2310 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use clap::Parser;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{trace, warn};
+
+use crate::{
+    certificate::Certificate,
+    client::{ClientConfig, ProcessingError},
+    ids::{ClientID, TransactionID},
+    ledger::{LedgerEntry, LedgerWriter},
+    metadata::RunMetadata,
+    output::{self, OrderBy, OutputConfig, OutputError, OutputFormat, OutputLocale, TrailingZeros},
+    processor::{EligibleDisputes, MergeError, Processor, ProcessorConfig, ProcessorStatusEntry},
+    reader::{
+        Filter, InputFormat, MatchBy, NegativeAmount, ReaderConfig, ReaderError, ReplayLogError,
+        ReplayLogReader, ReplayLogWriter, Sample, TransactionReader, TransactionWriter, TrimMode,
+    },
+    snapshot::{Snapshot, SnapshotIssue},
+    transaction::Transaction,
+};
+
+#[derive(Debug, Parser, Serialize)]
+#[command(group(clap::ArgGroup::new("anon_key_source").multiple(true)))]
+/// Transaction processor
+pub struct Args {
+    /// Path to the file containing the transactions
+    #[arg(required_unless_present_any = [
+        "interactive",
+        "inspect",
+        "validate",
+        "eligible_disputes",
+        "parallel_files",
+        "input_dir",
+    ])]
+    pub file: Option<PathBuf>,
+
+    /// Process all `*.csv` files of this directory into one processor, in lexical order of their
+    /// names, as if they were a single file. Other files and subdirectories are ignored
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["file", "interactive", "parallel_files"]
+    )]
+    pub input_dir: Option<PathBuf>,
+
+    /// Read transactions from stdin, one per line without a header, and print the state of the
+    /// affected client after each one
+    #[arg(long, conflicts_with = "file")]
+    pub interactive: bool,
+
+    /// Read the client states from a `--bincode-out` file and write them in the output format
+    /// instead of processing transactions
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file", "interactive"])]
+    pub inspect: Option<PathBuf>,
+
+    /// Check the internal consistency of a `--snapshot-out` file instead of processing
+    /// transactions. Prints every issue and exits with code 4 if there are any
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file", "interactive", "inspect"])]
+    pub validate: Option<PathBuf>,
+
+    /// List the deposits that could currently be disputed per client, from a `--snapshot-out`
+    /// file, instead of processing transactions
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["file", "interactive", "inspect", "validate"]
+    )]
+    pub eligible_disputes: Option<PathBuf>,
+
+    /// Write the valid transactions of the input file to stdout as canonical CSV instead of
+    /// processing them, e.g. to normalize a feed with numeric type codes before archiving it
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "interactive",
+            "inspect",
+            "validate",
+            "eligible_disputes",
+            "parallel_files",
+            "input_dir",
+        ]
+    )]
+    pub transform_only: bool,
+
+    /// Start the output of `--transform-only` with a `# schema_version: N` comment, which is
+    /// checked when the output is read again
+    #[arg(long, requires = "transform_only")]
+    pub schema_comment: bool,
+
+    /// Process these files in parallel, one thread per file, and merge the results. Every file
+    /// must hold a disjoint set of clients, a client in several files is an error
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 1..,
+        conflicts_with_all = [
+            "file",
+            "interactive",
+            "snapshot_in",
+            "ledger_out",
+            "replay_log_out",
+        ]
+    )]
+    pub parallel_files: Vec<PathBuf>,
+
+    /// Abort on a header row repeated mid-file, which means several files were concatenated,
+    /// instead of skipping it
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Reject rows when the input has columns other than type, client, tx, amount, reason and
+    /// idempotency_key
+    #[arg(long)]
+    pub strict_columns: bool,
+
+    /// Ignore fields and columns after the known ones, e.g. a comment some feeds append to rows
+    #[arg(long)]
+    pub ignore_trailing_columns: bool,
+
+    /// Abort if a deposit or withdrawal has no amount
+    #[arg(long)]
+    pub fail_on_missing_amount: bool,
+
+    /// What to do with rows that have a negative amount: skip them, abort, or treat negative
+    /// deposits as withdrawals
+    #[arg(long, value_enum, default_value_t)]
+    pub negative_amount: NegativeAmount,
+
+    /// Warn (or fail in strict mode) when the tx ids of consecutive deposits and withdrawals
+    /// differ by more than N, for feeds that are expected to be gapless
+    #[arg(long, value_name = "N")]
+    pub max_tx_gap: Option<u32>,
+
+    /// Remove internal and non-breaking whitespace from the type column before matching it
+    #[arg(long)]
+    pub normalize_type_whitespace: bool,
+
+    /// Read the type column as numeric codes: 1 = deposit, 2 = withdrawal, 3 = dispute,
+    /// 4 = resolve, 5 = chargeback, 6 = release
+    #[arg(long)]
+    pub type_codes: bool,
+
+    /// Round amounts to SCALE decimal places, with halves rounded away from zero.
+    /// Amounts that round to zero are invalid
+    #[arg(long, value_name = "SCALE", value_parser = clap::value_parser!(u32).range(0..=28))]
+    pub round_amounts: Option<u32>,
+
+    /// Warn about amounts with more decimal places than the output keeps (`--output-scale` or
+    /// `--output-minor-units`), still processing them at full precision
+    #[arg(long)]
+    pub warn_on_precision_loss: bool,
+
+    /// Reject amounts with more than SCALE decimal places, checked before parsing the amount
+    #[arg(long, value_name = "SCALE")]
+    pub max_amount_scale: Option<u32>,
+
+    /// Reject amounts with fewer than SCALE decimal places, e.g. `5` instead of `5.00` for a feed
+    /// that always writes 2 decimal places
+    #[arg(long, value_name = "SCALE")]
+    pub require_fractional_scale: Option<u32>,
+
+    /// Drop records identical to one of the last COUNT records, e.g. redeliveries. Duplicates
+    /// further apart aren't detected, which keeps the memory bounded
+    #[arg(long, value_name = "COUNT")]
+    pub dedup_window: Option<NonZeroUsize>,
+
+    /// Abort once the input has more than COUNT lines, including the header
+    #[arg(long, value_name = "COUNT")]
+    pub max_line_count: Option<u64>,
+
+    /// Reject transactions for client id 0, for systems that reserve it as a sentinel
+    #[arg(long)]
+    pub reject_client_zero: bool,
+
+    /// Drop withdrawals of zero as no-ops (heartbeats) instead of treating them as invalid rows,
+    /// zero deposits are still invalid
+    #[arg(long)]
+    pub allow_zero_withdrawals: bool,
+
+    /// How disputes, resolves and chargebacks refer to deposits
+    #[arg(long, value_enum, default_value_t)]
+    pub match_by: MatchBy,
+
+    /// Which leading and trailing whitespace to remove from the header and fields
+    #[arg(long, value_enum, default_value_t)]
+    pub trim: TrimMode,
+
+    /// Only process a reproducible sample of the transactions, e.g. 0.01 for 1%, selected by tx id.
+    /// Meant for quick statistical checks, the balances and disputes won't match the full input
+    #[arg(long, value_name = "RATE", value_parser = parse_fraction)]
+    pub sample_rate: Option<f64>,
+
+    /// Seed for `--sample-rate`, different seeds select different samples
+    #[arg(
+        long,
+        value_name = "SEED",
+        default_value_t = 0,
+        requires = "sample_rate"
+    )]
+    pub sample_seed: u64,
+
+    /// Only process transactions matching an expression like `type=deposit && amount>100`, see
+    /// the README for the grammar. Meant for ad-hoc analysis like `--sample-rate`
+    #[arg(long, value_name = "EXPR")]
+    pub filter: Option<Filter>,
+
+    /// Allow withdrawals to use held funds (administrative mode for privileged corrections)
+    #[arg(long)]
+    pub allow_withdraw_held: bool,
+
+    /// Size of the input read buffer in bytes (default 8 KiB).
+    ///
+    /// Larger buffers (e.g. 64 KiB - 1 MiB) can improve throughput for very large files on fast disks.
+    #[arg(long, value_name = "BYTES")]
+    pub input_buffer_size: Option<NonZeroUsize>,
+
+    /// Format of the input: CSV, or a replay log written by `--replay-log-out` to reconstruct the
+    /// state of an earlier run. The CSV options don't apply to replay logs
+    #[arg(long, value_enum, default_value_t)]
+    pub input_format: InputFormat,
+
+    /// Format of the output
+    #[arg(long, value_enum, default_value_t)]
+    pub output_format: OutputFormat,
+
+    /// Write the CSV output to a file instead of stdout, can be combined with `--json-out`
+    #[arg(long, value_name = "PATH")]
+    pub csv_out: Option<PathBuf>,
+
+    /// Write the JSON output to a file instead of stdout, can be combined with `--csv-out`
+    #[arg(long, value_name = "PATH")]
+    pub json_out: Option<PathBuf>,
+
+    /// Write the client states in the compact bincode format to a file, read it with `--inspect`
+    #[arg(long, value_name = "PATH")]
+    pub bincode_out: Option<PathBuf>,
+
+    /// Write a JSON certificate with the row counts, the summed balances and a hash of all client
+    /// states to a file after a successful run
+    #[arg(long, value_name = "PATH")]
+    pub certificate: Option<PathBuf>,
+
+    /// Write JSON metadata about the run to a file: the input files and their sizes, the row
+    /// counts, the start and end time, the tool version and all options
+    #[arg(long, value_name = "PATH", conflicts_with = "interactive")]
+    pub metadata_out: Option<PathBuf>,
+
+    /// Field delimiter of the CSV output, a single ASCII character or `\t` for tabs
+    #[arg(long, value_name = "CHAR", value_parser = parse_delimiter)]
+    pub output_delimiter: Option<u8>,
+
+    /// Locale used to format the balances, `de` writes a decimal comma and needs a
+    /// `--output-delimiter` other than a comma
+    #[arg(long, value_enum, default_value_t)]
+    pub output_locale: OutputLocale,
+
+    /// Don't write the header row to the CSV output
+    #[arg(long)]
+    pub no_output_header: bool,
+
+    /// Start the CSV output with a UTF-8 BOM, for Excel to detect the encoding
+    #[arg(long)]
+    pub output_bom: bool,
+
+    /// Add a `state_hash` column per client, to detect which clients changed between runs
+    #[arg(long)]
+    pub state_hash: bool,
+
+    /// Round the balances in the output to SCALE decimal places, halves away from zero, they are
+    /// kept at full precision until then
+    #[arg(
+        long,
+        value_name = "SCALE",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u32).range(0..=28)
+    )]
+    pub output_scale: u32,
+
+    /// Trim the insignificant trailing zeros of the amounts (5.1000 becomes 5.1) or pad them to
+    /// the output scale (5.1 becomes 5.1000), by default they keep the scale of the balance
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        conflicts_with = "output_minor_units"
+    )]
+    pub trailing_zeros: Option<TrailingZeros>,
+
+    /// Write balances as integer minor units: multiplied by 10^SCALE and rounded to the nearest
+    /// integer, with halves rounded away from zero (e.g. 1.505 at scale 2 becomes 151)
+    #[arg(long, value_name = "SCALE", value_parser = clap::value_parser!(u32).range(0..=18))]
+    pub output_minor_units: Option<u32>,
+
+    /// Zero-pad the client ids in the output to WIDTH digits (e.g. 00042 for width 5)
+    #[arg(long, value_name = "WIDTH")]
+    pub client_id_width: Option<usize>,
+
+    /// Replace the client ids in the output by deterministic pseudonyms (16 hex digits) derived
+    /// from `--anon-key` or `--anon-key-file`, for sharing outputs externally. Not supported for
+    /// bincode output
+    #[arg(
+        long,
+        requires = "anon_key_source",
+        conflicts_with_all = ["client_id_width", "group_by_range"]
+    )]
+    pub anonymize_clients: bool,
+
+    /// Secret key of `--anonymize-clients`, the same key always gives the same pseudonyms.
+    /// Prefer the environment variable or `--anon-key-file`, arguments are visible to other users
+    #[arg(
+        long,
+        value_name = "KEY",
+        env = "ANON_KEY",
+        hide_env_values = true,
+        group = "anon_key_source"
+    )]
+    #[serde(skip)]
+    pub anon_key: Option<String>,
+
+    /// Read the secret key of `--anonymize-clients` from the first line of a file, takes
+    /// precedence over `--anon-key`
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = read_key_file,
+        group = "anon_key_source",
+        requires = "anonymize_clients"
+    )]
+    #[serde(skip)]
+    pub anon_key_file: Option<String>,
+
+    /// Write one row per client id range of this size (e.g. 1000: 0-999, 1000-1999, ...) with summed balances
+    #[arg(long, value_name = "SIZE")]
+    pub group_by_range: Option<NonZeroU32>,
+
+    /// Write the locked and the active clients as two separate groups, each sorted by client id
+    #[arg(long, conflicts_with = "group_by_range")]
+    pub group_by_locked: bool,
+
+    /// Order of the client rows, by default CSV rows are in the order the clients were first seen
+    /// and JSON rows sorted by client id
+    #[arg(long, value_enum)]
+    pub order_by: Option<OrderBy>,
+
+    /// Write only the first N clients in the `--order-by` order, followed by an `others` row with
+    /// the summed balances of the remaining clients
+    #[arg(long, value_name = "N", conflicts_with_all = ["group_by_range", "group_by_locked"])]
+    pub top: Option<NonZeroUsize>,
+
+    /// Write every applied deposit (positive) and withdrawal (negative) to a ledger CSV file
+    #[arg(long, value_name = "PATH")]
+    pub ledger_out: Option<PathBuf>,
+
+    /// Write every applied transaction to a replay log, as JSON lines that `--input-format
+    /// replay-log` reads back. Run with the same processor options to get the same state
+    #[arg(long, value_name = "PATH")]
+    pub replay_log_out: Option<PathBuf>,
+
+    /// Resume from a snapshot written by an earlier run using `--snapshot-out`
+    #[arg(long, value_name = "PATH")]
+    pub snapshot_in: Option<PathBuf>,
+
+    /// Only output the clients whose state differs from this snapshot, including new clients
+    #[arg(long, value_name = "SNAPSHOT")]
+    pub delta_against: Option<PathBuf>,
+
+    /// Write a snapshot of the final state, to be able to resume processing later
+    #[arg(long, value_name = "PATH")]
+    pub snapshot_out: Option<PathBuf>,
+
+    /// Drop transactions whose `idempotency_key` column was already seen, regardless of the tx id
+    #[arg(long)]
+    pub use_idempotency_keys: bool,
+
+    /// Allow administrative operations (`release` rows moving held funds back to available)
+    #[arg(long)]
+    pub allow_admin_ops: bool,
+
+    /// File with the client ids allowed to transact, one per line. Transactions for other clients
+    /// are rejected
+    #[arg(long, value_name = "PATH")]
+    pub client_roster: Option<PathBuf>,
+
+    /// Accounts must be opened by an `open` row before the client's first deposit, transactions
+    /// for clients without an account are rejected
+    #[arg(long)]
+    pub deposit_requires_open_account: bool,
+
+    /// Disputes with an amount only hold that part of the deposit, several partial disputes may
+    /// hold at most the deposit amount
+    #[arg(long)]
+    pub partial_disputes: bool,
+
+    /// Treat a dispute of an already disputed deposit as a no-op instead of rejecting it, the
+    /// tolerated disputes are counted and reported at the end
+    #[arg(long)]
+    pub tolerate_redundant_disputes: bool,
+
+    /// Read the whole input before applying it, so disputes, resolves and chargebacks that
+    /// precede their deposit are applied right after it. For unordered streams, the input is held
+    /// in memory
+    #[arg(long, conflicts_with = "interactive")]
+    pub two_pass: bool,
+
+    /// Verify at the end that every client's held balance matches its disputed deposits
+    #[arg(long)]
+    pub reconcile: bool,
+
+    /// Add diagnostic columns to the output: `deposits_retained` with the number of deposits kept
+    /// per client, `locked_by_tx` with the chargeback that locked the account, and `max_deposit`
+    /// and `max_withdrawal` with the largest single deposit and withdrawal
+    #[arg(long)]
+    pub verbose: bool,
+
+    /// Add a `negative_available` column marking clients whose available balance went negative,
+    /// e.g. by a dispute of a deposit that was already partly withdrawn
+    #[arg(long)]
+    pub flag_negative_available: bool,
+
+    /// Recompute every client's total from its deposits and withdrawals at the end and report
+    /// any difference to the running total
+    #[arg(long)]
+    pub audit_balances: bool,
+
+    /// Warn about clients that disputed more than RATIO of their deposits (e.g. 0.5), a fraud
+    /// indicator, and add a `dispute_ratio_exceeded` column to the output
+    #[arg(long, value_name = "RATIO", value_parser = parse_fraction)]
+    pub max_dispute_ratio: Option<f64>,
+
+    /// Print the number of skipped and rejected rows per reason to stderr at the end
+    #[arg(long)]
+    pub warning_histogram: bool,
+
+    /// Approximate number of clients in the input, to allocate room for them up front instead of
+    /// growing the client map while processing
+    #[arg(long, value_name = "COUNT")]
+    pub expected_clients: Option<usize>,
+
+    /// Print the total time spent applying transactions per transaction type to stderr at the end
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Reject and undo transactions that took longer than MS milliseconds to apply, as a watchdog
+    /// against pathologically slow operations. Slow operations still run to completion
+    #[arg(long, value_name = "MS")]
+    pub tx_timeout_ms: Option<u64>,
+
+    /// Narrate the transactions of this client with their balances before and after to stderr,
+    /// all other clients are processed as usual but not narrated
+    #[arg(long, value_name = "ID")]
+    pub explain_client: Option<u16>,
+
+    /// Exit with code 3 when the output has no clients, e.g. because every row was invalid
+    #[arg(long)]
+    pub fail_on_empty_output: bool,
+
+    /// Stop processing as soon as a chargeback locks an account, for review before the rest of
+    /// the batch. The state up to the chargeback is written as usual, then the exit code is 5
+    #[arg(long, conflicts_with = "parallel_files")]
+    pub halt_on_lock: bool,
+}
+
+impl Args {
+    /// Returns the output options selected by the arguments.
+    pub fn output_config(&self) -> OutputConfig {
+        OutputConfig {
+            format: self.output_format,
+            omit_header: self.no_output_header,
+            bom: self.output_bom,
+            state_hash: self.state_hash,
+            scale: Some(self.output_scale),
+            trailing_zeros: self.trailing_zeros,
+            minor_units_scale: self.output_minor_units,
+            group_by_range: self.group_by_range,
+            group_by_locked: self.group_by_locked,
+            verbose: self.verbose,
+            flag_negative_available: self.flag_negative_available,
+            client_id_width: self.client_id_width,
+            anonymize_key: self
+                .anonymize_clients
+                .then(|| self.anon_key_file.clone().or_else(|| self.anon_key.clone()))
+                .flatten(),
+            delimiter: self.output_delimiter,
+            locale: self.output_locale,
+            order_by: self.order_by,
+            top: self.top,
+        }
+    }
+
+    /// Returns the client states to write, with the diagnostic columns in verbose mode and the
+    /// `dispute_ratio_exceeded` column with `--max-dispute-ratio`.
+    pub fn status_entries(&self, processor: &Processor) -> Vec<ProcessorStatusEntry> {
+        let mut entries: Vec<_> = match self.verbose {
+            true => processor.verbose_status_entries().collect(),
+            false => processor.status_entries().collect(),
+        };
+
+        if let Some(max_ratio) = self.max_dispute_ratio {
+            let flagged: HashSet<_> = processor
+                .dispute_ratio_alerts(max_ratio)
+                .into_iter()
+                .map(|alert| alert.client)
+                .collect();
+            for entry in &mut entries {
+                entry.dispute_ratio_exceeded = Some(flagged.contains(&entry.client));
+            }
+        }
+
+        entries
+    }
+
+    /// Returns whether the output goes to `--csv-out` and/or `--json-out` instead of stdout.
+    pub fn writes_output_files(&self) -> bool {
+        self.csv_out.is_some() || self.json_out.is_some() || self.bincode_out.is_some()
+    }
+}
+
+/// Parses a CSV delimiter argument, `\t` is accepted since a literal tab is awkward to pass.
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        [delimiter] if delimiter.is_ascii() => Ok(*delimiter),
+        _ => Err(format!("'{value}' is not a single ASCII character")),
+    }
+}
+
+/// Parses a fraction argument like a sample rate, which must be between 0 and 1.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("'{value}' is not a number between 0 and 1")),
+    }
+}
+
+/// Reads a secret key from the first line of a file, without its line ending.
+fn read_key_file(path: &str) -> Result<String, String> {
+    let content = std::fs::read_to_string(path).map_err(|err| format!("'{path}': {err}"))?;
+    match content.lines().next() {
+        Some(key) if !key.is_empty() => Ok(key.to_string()),
+        _ => Err(format!("'{path}' doesn't contain a key")),
+    }
+}
+
+/// Exit code used with `--fail-on-empty-output` when the output has no clients.
+pub const EMPTY_OUTPUT_EXIT_CODE: u8 = 3;
+
+/// Exit code used with `--validate` when the snapshot is inconsistent.
+pub const INVALID_SNAPSHOT_EXIT_CODE: u8 = 4;
+
+/// Exit code used with `--halt-on-lock` when a chargeback locked an account.
+pub const HALTED_ON_LOCK_EXIT_CODE: u8 = 5;
+
+/// The outcome of a successful `run`.
+#[derive(Debug)]
+pub struct RunSummary {
+    /// Number of transactions applied to the client accounts.
+    pub processed: usize,
+    /// Number of invalid rows skipped by the reader.
+    pub skipped: usize,
+    /// Number of valid transactions rejected by the processor, e.g. because of insufficient funds.
+    pub rejected: usize,
+    /// Number of skipped and rejected rows per reason, e.g. `missing_amount` or
+    /// `insufficient_funds`, see `ReaderError::kind` and `ProcessingError::kind`.
+    pub reasons: BTreeMap<&'static str, usize>,
+    /// The final state of all client accounts.
+    pub processor: Processor,
+    /// One line per transaction of the `--explain-client` client, in the order they were processed.
+    pub explanation: Vec<String>,
+    /// The locked client and the chargeback that locked it, when `--halt-on-lock` stopped
+    /// processing there.
+    pub halted_on_lock: Option<(ClientID, TransactionID)>,
+    /// The snapshot given by `--delta-against`, only clients that changed relative to it are output.
+    pub delta_base: Option<Snapshot>,
+}
+
+impl RunSummary {
+    /// Returns whether no client was seen, i.e. the output has no clients.
+    pub fn is_empty(&self) -> bool {
+        self.processor.status_entries().next().is_none()
+    }
+
+    /// Formats the skip and reject reasons as a histogram, the most frequent reason first,
+    /// e.g. `missing_amount: 12, insufficient_funds: 3`.
+    pub fn warning_histogram(&self) -> String {
+        let mut reasons: Vec<_> = self.reasons.iter().collect();
+        // the map is sorted by name, the stable sort keeps that order for equal counts
+        reasons.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        reasons
+            .into_iter()
+            .map(|(reason, count)| format!("{reason}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns the client states to write, see `Args::status_entries`. With `--delta-against`
+    /// only the clients that changed relative to that snapshot are returned.
+    pub fn status_entries(&self, args: &Args) -> Vec<ProcessorStatusEntry> {
+        let entries = args.status_entries(&self.processor);
+        match &self.delta_base {
+            Some(snapshot) => snapshot.changed_entries(entries),
+            None => entries,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RunError {
+    #[error("no input file given")]
+    NoInputFile,
+    #[error("open csv file")]
+    OpenInput(#[source] io::Error),
+    #[error("read input directory")]
+    ReadInputDir(#[source] io::Error),
+    #[error("read transaction")]
+    Read(#[source] ReaderError),
+    #[error("open snapshot file")]
+    OpenSnapshot(#[source] io::Error),
+    #[error("read snapshot file")]
+    ReadSnapshot(#[source] serde_json::Error),
+    #[error("write snapshot file")]
+    WriteSnapshot(#[source] io::Error),
+    #[error("write transaction")]
+    WriteTransaction(#[source] csv::Error),
+    #[error("create ledger file")]
+    CreateLedger(#[source] io::Error),
+    #[error("write ledger entry")]
+    WriteLedger(#[source] csv::Error),
+    #[error("read replay log")]
+    ReadReplayLog(#[source] ReplayLogError),
+    #[error("create replay log file")]
+    CreateReplayLog(#[source] io::Error),
+    #[error("write replay log entry")]
+    WriteReplayLog(#[source] io::Error),
+    #[error("create output file")]
+    CreateOutput(#[source] io::Error),
+    #[error("write status entries")]
+    WriteOutput(#[source] OutputError),
+    #[error("read roster file")]
+    ReadRoster(#[source] io::Error),
+    #[error("roster line {line}: invalid client id '{value}'")]
+    InvalidRoster { line: usize, value: String },
+    #[error("open bincode file")]
+    OpenInspect(#[source] io::Error),
+    #[error("read bincode file")]
+    ReadInspect(#[source] OutputError),
+    #[error("merge parallel files")]
+    Merge(#[source] MergeError),
+    #[error("write certificate file")]
+    WriteCertificate(#[source] io::Error),
+    #[error("write metadata file")]
+    WriteMetadata(#[source] io::Error),
+}
+
+/// Processes the transactions file described by `args`, the files of `--input-dir`, or the
+/// `--parallel-files` shards.
+///
+/// Writes the optional ledger, snapshot, metadata and `--csv-out`/`--json-out` files, but nothing
+/// to stdout. Printing the final state when no output file is requested is left to the caller,
+/// using `RunSummary::processor` and `Args::output_config`.
+pub fn run(args: &Args) -> Result<RunSummary, RunError> {
+    let started_at = SystemTime::now();
+    let summary = match (&args.input_dir, args.parallel_files.is_empty()) {
+        (Some(dir), _) => {
+            // Open the CSV files one after the other
+            let files = input_dir_files(dir)?.into_iter().map(|path| {
+                let file = File::open(&path).map_err(RunError::OpenInput)?;
+                trace!(?path, "opened csv file");
+                Ok(file)
+            });
+
+            process(files, args, |_, _| Ok(()))?
+        }
+        (None, true) => {
+            // Open the CSV file
+            let path = args.file.as_ref().ok_or(RunError::NoInputFile)?;
+            let file = File::open(path).map_err(RunError::OpenInput)?;
+            trace!(?path, "opened csv file");
+
+            process([Ok(file)], args, |_, _| Ok(()))?
+        }
+        (None, false) => run_parallel(args)?,
+    };
+
+    if let Some(path) = &args.metadata_out {
+        write_metadata(path, &RunMetadata::new(args, &summary, started_at))?;
+    }
+
+    Ok(summary)
+}
+
+/// Reads the client states from the bincode file given by `--inspect`.
+pub fn inspect(args: &Args) -> Result<Vec<ProcessorStatusEntry>, RunError> {
+    let path = args.inspect.as_ref().ok_or(RunError::NoInputFile)?;
+    let file = File::open(path).map_err(RunError::OpenInspect)?;
+    output::read_bincode(BufReader::new(file)).map_err(RunError::ReadInspect)
+}
+
+/// Checks the snapshot given by `--validate`, see `Snapshot::validate`.
+pub fn validate(args: &Args) -> Result<Vec<SnapshotIssue>, RunError> {
+    let path = args.validate.as_ref().ok_or(RunError::NoInputFile)?;
+    Ok(read_snapshot(path)?.validate())
+}
+
+/// Lists the deposits eligible for a dispute in the snapshot given by `--eligible-disputes`, see
+/// `Processor::eligible_disputes`.
+pub fn eligible_disputes(args: &Args) -> Result<Vec<EligibleDisputes>, RunError> {
+    let path = args
+        .eligible_disputes
+        .as_ref()
+        .ok_or(RunError::NoInputFile)?;
+    let processor = Processor::restore(&read_snapshot(path)?, ProcessorConfig::default());
+    Ok(processor.eligible_disputes())
+}
+
+/// Writes the valid transactions of the input file as canonical CSV for `--transform-only`, see
+/// `TransactionWriter`.
+pub fn transform<W: io::Write>(args: &Args, output: W) -> Result<(), RunError> {
+    let path = args.file.as_ref().ok_or(RunError::NoInputFile)?;
+    let file = File::open(path).map_err(RunError::OpenInput)?;
+    let mut reader = match args.input_format {
+        InputFormat::Csv => Input::Csv(Box::new(TransactionReader::new(file, reader_config(args)))),
+        InputFormat::ReplayLog => Input::ReplayLog(ReplayLogReader::new(file)),
+    };
+
+    let mut writer =
+        TransactionWriter::new(output, args.schema_comment).map_err(RunError::WriteTransaction)?;
+    while let Some((transaction, _)) = reader.next_keyed_transaction()? {
+        writer
+            .write(transaction)
+            .map_err(RunError::WriteTransaction)?;
+    }
+    writer.flush().map_err(RunError::WriteTransaction)
+}
+
+/// Processes transactions typed one per line (without a header row) for interactive use.
+///
+/// After every transaction the state of the affected client is written to `output`, which is
+/// flushed right away. Rejected transactions are logged and their client's state written as well.
+/// The ledger, snapshot and output files are written the same way as by `run`.
+pub fn run_interactive<R, W>(input: R, mut output: W, args: &Args) -> Result<RunSummary, RunError>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    // the reader expects a header row, which nobody wants to type
+    let input = io::Cursor::new("type,client,tx,amount\n").chain(input);
+
+    let mut config = args.output_config();
+    process([Ok(input)], args, |processor, client| {
+        output::write(&mut output, processor.status_entry(client), &config)
+            .map_err(RunError::WriteOutput)?;
+        output
+            .flush()
+            .map_err(|err| RunError::WriteOutput(err.into()))?;
+
+        // only write the BOM and the header before the first row
+        config.bom = false;
+        config.omit_header = true;
+        Ok(())
+    })
+}
+
+/// Processes all transactions of the inputs one after the other, calling `after_transaction` with
+/// the affected client after each one.
+///
+/// Every input starts with its own header row and has its own line numbers, while the reader state
+/// spans all of them, i.e. the duplicate window, the composite tx ids and the tx gap check, see
+/// `ChainedInput`. All of them go into the same processor.
+fn process<I, R, F>(
+    inputs: I,
+    args: &Args,
+    mut after_transaction: F,
+) -> Result<RunSummary, RunError>
+where
+    I: IntoIterator<Item = Result<R, RunError>>,
+    R: io::Read,
+    F: FnMut(&Processor, ClientID) -> Result<(), RunError>,
+{
+    // Create a processor to process the transactions
+    let processor_config = processor_config(args)?;
+    let mut processor = match &args.snapshot_in {
+        Some(path) => Processor::restore(&read_snapshot(path)?, processor_config),
+        None => Processor::with_capacity(processor_config, args.expected_clients.unwrap_or(0)),
+    };
+
+    // Optionally write the applied deposits and withdrawals to a ledger file
+    let mut ledger_writer = match &args.ledger_out {
+        Some(path) => Some(LedgerWriter::new(
+            File::create(path).map_err(RunError::CreateLedger)?,
+        )),
+        None => None,
+    };
+
+    // Optionally write all applied transactions to a replay log
+    let mut replay_log_writer = match &args.replay_log_out {
+        Some(path) => Some(ReplayLogWriter::new(
+            File::create(path).map_err(RunError::CreateReplayLog)?,
+        )),
+        None => None,
+    };
+
+    let counts = process_input(
+        inputs,
+        args,
+        &mut processor,
+        ledger_writer.as_mut(),
+        replay_log_writer.as_mut(),
+        &mut after_transaction,
+    )?;
+
+    if let Some(writer) = &mut ledger_writer {
+        writer.flush().map_err(RunError::WriteLedger)?;
+    }
+    if let Some(writer) = &mut replay_log_writer {
+        writer.flush().map_err(RunError::WriteReplayLog)?;
+    }
+
+    finish(args, processor, counts)
+}
+
+/// Processes every file of `--parallel-files` on its own thread with its own processor, then
+/// merges the processors in the order of the files, see `Processor::merge`.
+fn run_parallel(args: &Args) -> Result<RunSummary, RunError> {
+    let processor_config = processor_config(args)?;
+    // the clients of the files are disjoint, so every shard gets its share of them
+    let shard_clients = args.expected_clients.unwrap_or(0) / args.parallel_files.len();
+    let shards = std::thread::scope(|scope| {
+        let handles: Vec<_> = args
+            .parallel_files
+            .iter()
+            .map(|path| {
+                let config = processor_config.clone();
+                scope.spawn(move || {
+                    let file = File::open(path).map_err(RunError::OpenInput)?;
+                    trace!(?path, "opened csv file");
+                    let mut processor = Processor::with_capacity(config, shard_clients);
+                    let counts =
+                        process_input([Ok(file)], args, &mut processor, None, None, |_, _| Ok(()))?;
+                    Ok::<_, RunError>((processor, counts))
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("shard thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut shards = shards.into_iter();
+    let (mut processor, mut counts) = shards.next().ok_or(RunError::NoInputFile)??;
+    for shard in shards {
+        let (shard_processor, shard_counts) = shard?;
+        processor.merge(shard_processor).map_err(RunError::Merge)?;
+        counts.add(shard_counts);
+    }
+
+    finish(args, processor, counts)
+}
+
+/// Lists the `*.csv` files of `dir` sorted by name.
+pub(crate) fn input_dir_files(dir: &Path) -> Result<Vec<PathBuf>, RunError> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(RunError::ReadInputDir)? {
+        let path = entry.map_err(RunError::ReadInputDir)?.path();
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "csv") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Outcome counts of processing one input, along with the `--explain-client` narration.
+#[derive(Default)]
+struct InputCounts {
+    processed: usize,
+    skipped: usize,
+    rejected: usize,
+    reasons: BTreeMap<&'static str, usize>,
+    explanation: Vec<String>,
+    halted_on_lock: Option<(ClientID, TransactionID)>,
+    precision_warnings: usize,
+}
+
+impl InputCounts {
+    fn add(&mut self, other: InputCounts) {
+        self.processed += other.processed;
+        self.skipped += other.skipped;
+        self.rejected += other.rejected;
+        for (reason, count) in other.reasons {
+            *self.reasons.entry(reason).or_default() += count;
+        }
+        self.explanation.extend(other.explanation);
+        self.halted_on_lock = self.halted_on_lock.or(other.halted_on_lock);
+        self.precision_warnings += other.precision_warnings;
+    }
+}
+
+fn processor_config(args: &Args) -> Result<ProcessorConfig, RunError> {
+    Ok(ProcessorConfig {
+        client: ClientConfig {
+            allow_withdraw_held: args.allow_withdraw_held,
+            allow_admin_ops: args.allow_admin_ops,
+            partial_disputes: args.partial_disputes,
+        },
+        use_idempotency_keys: args.use_idempotency_keys,
+        roster: args.client_roster.as_deref().map(read_roster).transpose()?,
+        require_open_account: args.deposit_requires_open_account,
+        profile: args.profile,
+        tolerate_redundant_disputes: args.tolerate_redundant_disputes,
+        tx_timeout: args.tx_timeout_ms.map(Duration::from_millis),
+    })
+}
+
+/// Returns the reader options given by the arguments.
+fn reader_config(args: &Args) -> ReaderConfig {
+    ReaderConfig {
+        strict: args.strict,
+        strict_columns: args.strict_columns,
+        ignore_trailing_columns: args.ignore_trailing_columns,
+        fail_on_missing_amount: args.fail_on_missing_amount,
+        max_tx_gap: args.max_tx_gap,
+        max_line_count: args.max_line_count,
+        dedup_window: args.dedup_window,
+        max_amount_scale: args.max_amount_scale,
+        require_fractional_scale: args.require_fractional_scale,
+        type_codes: args.type_codes,
+        normalize_type_whitespace: args.normalize_type_whitespace,
+        round_amounts: args.round_amounts,
+        warn_amount_scale: args
+            .warn_on_precision_loss
+            .then(|| args.output_minor_units.unwrap_or(args.output_scale)),
+        match_by: args.match_by,
+        last_used_tx: None,
+        trim: args.trim,
+        negative_amount: args.negative_amount,
+        reject_client_zero: args.reject_client_zero,
+        allow_zero_withdrawals: args.allow_zero_withdrawals,
+        sample: args.sample_rate.map(|rate| Sample {
+            rate,
+            seed: args.sample_seed,
+        }),
+        filter: args.filter.clone(),
+        buffer_capacity: args.input_buffer_size,
+    }
+}
+
+/// Applies all transactions of the inputs to the processor, writing the applied ones to the
+/// ledger and the replay log if given.
+///
+/// The inputs are read one after the other as if they were one, see `ChainedInput`.
+fn process_input<I, R, F>(
+    inputs: I,
+    args: &Args,
+    processor: &mut Processor,
+    mut ledger_writer: Option<&mut LedgerWriter<File>>,
+    mut replay_log_writer: Option<&mut ReplayLogWriter<File>>,
+    mut after_transaction: F,
+) -> Result<InputCounts, RunError>
+where
+    I: IntoIterator<Item = Result<R, RunError>>,
+    R: io::Read,
+    F: FnMut(&Processor, ClientID) -> Result<(), RunError>,
+{
+    let mut inputs = inputs.into_iter();
+    let Some(input) = inputs.next().transpose()? else {
+        return Ok(InputCounts::default());
+    };
+
+    // Read the CSV file using the TransactionReader
+    // This reader only returns valid transactions, in strict mode it fails on repeated headers
+    let reader_config = ReaderConfig {
+        last_used_tx: (args.match_by == MatchBy::Composite)
+            .then(|| processor.last_deposit_tx())
+            .flatten(),
+        ..reader_config(args)
+    };
+    if let Some(rate) = args.sample_rate {
+        warn!(
+            rate,
+            "processing a sample of the transactions, balances and disputes won't match the full input"
+        );
+    }
+    if args.filter.is_some() {
+        warn!(
+            "processing the transactions matching the filter, balances won't match the full input"
+        );
+    }
+    let mut reader = ChainedInput {
+        input: match args.input_format {
+            InputFormat::Csv => Input::Csv(Box::new(TransactionReader::new(input, reader_config))),
+            InputFormat::ReplayLog => Input::ReplayLog(ReplayLogReader::new(input)),
+        },
+        rest: inputs,
+    };
+
+    let mut counts = InputCounts::default();
+
+    // In two-pass mode the first pass reads everything, the loop below is the second pass
+    let mut reordered = match args.two_pass {
+        true => Some(two_pass_order(&mut reader)?.into_iter()),
+        false => None,
+    };
+
+    // Loop through all the the transactions and process them one by one
+    loop {
+        let next = match &mut reordered {
+            Some(transactions) => transactions.next(),
+            None => reader.next_keyed_transaction()?,
+        };
+        let Some((transaction, key)) = next else {
+            break;
+        };
+        trace!(?transaction, "processing transaction");
+
+        // The ledger only holds applied transactions, so keep the entry until we know the outcome
+        let ledger_entry = LedgerEntry::from_transaction(&transaction);
+        let replay_log_entry = replay_log_writer.is_some().then(|| transaction.clone());
+        let (client, tx) = (transaction.client(), transaction.tx());
+        let explained = (args.explain_client.map(ClientID::new) == Some(client))
+            .then(|| (transaction.type_name(), processor.status_entry(client)));
+
+        // In case the transaction processing fails, print a warning, but don't stop processing
+        let is_chargeback = matches!(transaction, Transaction::Chargeback(_));
+        let result = processor.handle_keyed_transaction(transaction, key);
+        if let Some((type_name, before)) = explained {
+            let after = processor.status_entry(client);
+            let line = explain(type_name, tx, &result, before, after);
+            counts.explanation.push(line);
+        }
+
+        // Applied chargebacks always lock the account
+        let locked = is_chargeback && result.is_ok();
+        match result {
+            Ok(()) => {
+                counts.processed += 1;
+                if let (Some(writer), Some(entry)) = (&mut ledger_writer, &ledger_entry) {
+                    writer.write(entry).map_err(RunError::WriteLedger)?;
+                }
+                if let (Some(writer), Some(entry)) = (&mut replay_log_writer, &replay_log_entry) {
+                    writer.write(entry).map_err(RunError::WriteReplayLog)?;
+                }
+            }
+            Err(err) => {
+                counts.rejected += 1;
+                *counts.reasons.entry(err.kind()).or_default() += 1;
+                warn!(%err, %tx, "transaction processing failed");
+            }
+        }
+
+        after_transaction(processor, client)?;
+
+        if args.halt_on_lock && locked {
+            counts.halted_on_lock = Some((client, tx));
+            break;
+        }
+    }
+
+    // replay logs only hold valid transactions, invalid entries are errors
+    if let Input::Csv(reader) = &reader.input {
+        counts.skipped = reader.skipped();
+        counts.precision_warnings = reader.precision_warnings();
+        for (reason, count) in reader.skip_reasons() {
+            *counts.reasons.entry(*reason).or_default() += count;
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Reads all transactions of the input and moves every dispute, resolve and chargeback that
+/// precedes its deposit to right after the deposit, keeping their order otherwise.
+///
+/// Transactions referring to a deposit that doesn't exist at all stay where they are.
+fn two_pass_order<I, R>(
+    input: &mut ChainedInput<I, R>,
+) -> Result<Vec<(Transaction, Option<String>)>, RunError>
+where
+    I: Iterator<Item = Result<R, RunError>>,
+    R: io::Read,
+{
+    let mut transactions = Vec::new();
+    while let Some(transaction) = input.next_keyed_transaction()? {
+        transactions.push(transaction);
+    }
+
+    // the first deposit of a tx id is the one a dispute refers to, later ones are rejected
+    let mut deposits = HashMap::new();
+    for (index, (transaction, _)) in transactions.iter().enumerate() {
+        if let Transaction::Deposit(deposit) = transaction {
+            deposits
+                .entry((deposit.client, deposit.tx))
+                .or_insert(index);
+        }
+    }
+
+    let mut deferred: HashMap<usize, Vec<_>> = HashMap::new();
+    let mut ordered = Vec::with_capacity(transactions.len());
+    for (index, (transaction, key)) in transactions.into_iter().enumerate() {
+        let refers_to_deposit = matches!(
+            transaction,
+            Transaction::Dispute(_) | Transaction::Resolve(_) | Transaction::Chargeback(_)
+        );
+        match deposits.get(&(transaction.client(), transaction.tx())) {
+            Some(deposit) if refers_to_deposit && *deposit > index => {
+                deferred
+                    .entry(*deposit)
+                    .or_default()
+                    .push((transaction, key));
+            }
+            _ => {
+                ordered.push((transaction, key));
+                ordered.extend(deferred.remove(&index).unwrap_or_default());
+            }
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// Source of the transactions of one input, depending on `--input-format`.
+enum Input<R> {
+    Csv(Box<TransactionReader<R>>),
+    ReplayLog(ReplayLogReader<R>),
+}
+
+impl<R> Input<R>
+where
+    R: io::Read,
+{
+    /// Returns the next transaction with its idempotency key, replay logs don't have keys.
+    fn next_keyed_transaction(
+        &mut self,
+    ) -> Result<Option<(Transaction, Option<String>)>, RunError> {
+        match self {
+            Input::Csv(reader) => reader.next_keyed_transaction().map_err(RunError::Read),
+            Input::ReplayLog(reader) => Ok(reader
+                .next_transaction()
+                .map_err(RunError::ReadReplayLog)?
+                .map(|transaction| (transaction, None))),
+        }
+    }
+}
+
+/// Reads several inputs one after the other, e.g. the files of `--input-dir`.
+///
+/// A CSV reader keeps its state from one input to the next, so the composite tx ids, the dedup
+/// window and the tx gap check span the inputs, see `TransactionReader::continue_with`.
+struct ChainedInput<I, R> {
+    input: Input<R>,
+    /// The inputs after the current one.
+    rest: I,
+}
+
+impl<I, R> ChainedInput<I, R>
+where
+    I: Iterator<Item = Result<R, RunError>>,
+    R: io::Read,
+{
+    /// Returns the next transaction with its idempotency key, continuing with the next input
+    /// once the current one is exhausted.
+    fn next_keyed_transaction(
+        &mut self,
+    ) -> Result<Option<(Transaction, Option<String>)>, RunError> {
+        loop {
+            if let Some(transaction) = self.input.next_keyed_transaction()? {
+                return Ok(Some(transaction));
+            }
+            let Some(next) = self.rest.next().transpose()? else {
+                return Ok(None);
+            };
+            match &mut self.input {
+                Input::Csv(reader) => reader.continue_with(next),
+                Input::ReplayLog(reader) => *reader = ReplayLogReader::new(next),
+            }
+        }
+    }
+}
+
+/// Narrates a transaction of the `--explain-client` client, e.g.
+/// `tx 2 withdrawal: applied, available 5 -> 3, held 0 -> 0, total 5 -> 3, locked false -> false`.
+///
+/// A client seen for the first time has zero balances before the transaction.
+fn explain(
+    type_name: &str,
+    tx: TransactionID,
+    result: &Result<(), ProcessingError>,
+    before: Option<ProcessorStatusEntry>,
+    after: Option<ProcessorStatusEntry>,
+) -> String {
+    let outcome = match result {
+        Ok(()) => "applied".to_string(),
+        Err(err) => format!("rejected ({err})"),
+    };
+    let balances = |entry: Option<ProcessorStatusEntry>| {
+        entry.map_or(
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, false),
+            |entry| (entry.available, entry.held, entry.total, entry.locked),
+        )
+    };
+    let (before, after) = (balances(before), balances(after));
+
+    format!(
+        "tx {tx} {type_name}: {outcome}, available {} -> {}, held {} -> {}, total {} -> {}, locked {} -> {}",
+        before.0, after.0, before.1, after.1, before.2, after.2, before.3, after.3
+    )
+}
+
+/// Runs the end of run checks on the final state and writes the snapshot and output files.
+fn finish(args: &Args, processor: Processor, counts: InputCounts) -> Result<RunSummary, RunError> {
+    if processor.off_roster_rejections() > 0 {
+        warn!(
+            count = processor.off_roster_rejections(),
+            "rejected transactions for clients not on the roster"
+        );
+    }
+    if let Some((client, tx)) = counts.halted_on_lock {
+        warn!(%client, %tx, "chargeback locked an account, stopped processing for review");
+    }
+    if counts.precision_warnings > 0 {
+        warn!(
+            count = counts.precision_warnings,
+            "processed amounts with more decimal places than the output at full precision"
+        );
+    }
+    for (anomaly, count) in processor.ordering_anomalies() {
+        warn!(%anomaly, count, "transactions arrived before the dispute of their deposit");
+    }
+    if processor.redundant_disputes() > 0 {
+        warn!(
+            count = processor.redundant_disputes(),
+            "ignored disputes of already disputed deposits"
+        );
+    }
+
+    // Report clients disputing suspiciously many of their deposits
+    if let Some(max_ratio) = args.max_dispute_ratio {
+        for alert in processor.dispute_ratio_alerts(max_ratio) {
+            warn!(
+                client = %alert.client,
+                disputed = alert.disputed,
+                deposits = alert.deposits,
+                "client disputed a high fraction of its deposits"
+            );
+        }
+    }
+
+    // Report any client whose held balance doesn't add up, this would be an accounting bug
+    if args.reconcile {
+        for issue in processor.reconcile() {
+            warn!(?issue, "held balance doesn't match the disputed deposits");
+        }
+    }
+
+    // Report any client whose total doesn't add up, this would be an accumulation bug
+    if args.audit_balances {
+        for discrepancy in processor.audit_balances() {
+            warn!(
+                ?discrepancy,
+                "total doesn't match the deposits and withdrawals"
+            );
+        }
+    }
+
+    // Save the complete state to be able to resume processing later
+    if let Some(path) = &args.snapshot_out {
+        write_snapshot(path, &processor)?;
+    }
+
+    let delta_base = args
+        .delta_against
+        .as_deref()
+        .map(read_snapshot)
+        .transpose()?;
+
+    let summary = RunSummary {
+        processed: counts.processed,
+        skipped: counts.skipped,
+        rejected: counts.rejected,
+        reasons: counts.reasons,
+        processor,
+        explanation: counts.explanation,
+        halted_on_lock: counts.halted_on_lock,
+        delta_base,
+    };
+    write_output_files(args, &summary)?;
+
+    // Certify the run last, once everything else succeeded
+    if let Some(path) = &args.certificate {
+        write_certificate(path, &summary)?;
+    }
+
+    Ok(summary)
+}
+
+/// Reads a roster file with one client id per line, empty lines are ignored.
+fn read_roster(path: &Path) -> Result<HashSet<ClientID>, RunError> {
+    let content = std::fs::read_to_string(path).map_err(RunError::ReadRoster)?;
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line, value)| {
+            value
+                .parse::<u16>()
+                .map(ClientID::new)
+                .map_err(|_| RunError::InvalidRoster {
+                    line,
+                    value: value.to_string(),
+                })
+        })
+        .collect()
+}
+
+fn read_snapshot(path: &Path) -> Result<Snapshot, RunError> {
+    let file = File::open(path).map_err(RunError::OpenSnapshot)?;
+    Snapshot::read_json(BufReader::new(file)).map_err(RunError::ReadSnapshot)
+}
+
+fn write_snapshot(path: &Path, processor: &Processor) -> Result<(), RunError> {
+    let mut file = BufWriter::new(File::create(path).map_err(RunError::WriteSnapshot)?);
+    processor
+        .snapshot()
+        .write_json(&mut file)
+        .map_err(|err| RunError::WriteSnapshot(err.into()))?;
+    file.flush().map_err(RunError::WriteSnapshot)
+}
+
+fn write_certificate(path: &Path, summary: &RunSummary) -> Result<(), RunError> {
+    let certificate = Certificate::new(
+        summary.processed,
+        summary.skipped,
+        summary.rejected,
+        &summary.processor,
+    );
+    let mut file = BufWriter::new(File::create(path).map_err(RunError::WriteCertificate)?);
+    certificate
+        .write_json(&mut file)
+        .map_err(|err| RunError::WriteCertificate(err.into()))?;
+    writeln!(file).map_err(RunError::WriteCertificate)?;
+    file.flush().map_err(RunError::WriteCertificate)
+}
+
+fn write_metadata(path: &Path, metadata: &RunMetadata) -> Result<(), RunError> {
+    let mut file = BufWriter::new(File::create(path).map_err(RunError::WriteMetadata)?);
+    metadata
+        .write_json(&mut file)
+        .map_err(|err| RunError::WriteMetadata(err.into()))?;
+    writeln!(file).map_err(RunError::WriteMetadata)?;
+    file.flush().map_err(RunError::WriteMetadata)
+}
+
+/// Writes the same sorted status entries to every requested output file.
+fn write_output_files(args: &Args, summary: &RunSummary) -> Result<(), RunError> {
+    if !args.writes_output_files() {
+        return Ok(());
+    }
+
+    let mut entries = summary.status_entries(args);
+    entries.sort_by_key(|entry| entry.client);
+
+    let output_config = args.output_config();
+    let outputs = [
+        (&args.csv_out, OutputFormat::Csv),
+        (&args.json_out, OutputFormat::Json),
+        (&args.bincode_out, OutputFormat::Bincode),
+    ];
+    for (path, format) in outputs {
+        if let Some(path) = path {
+            let config = OutputConfig {
+                format,
+                ..output_config.clone()
+            };
+            let file = File::create(path).map_err(RunError::CreateOutput)?;
+            output::write(BufWriter::new(file), entries.clone(), &config)
+                .map_err(RunError::WriteOutput)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::processor::DisputeRatioAlert;
+
+    use super::*;
+
+    #[test]
+    fn run_summary_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             withdrawal,1,2,10.0\n\
+             deposit,2,3,2.5\n\
+             invalid,2,4,1.0\n\
+             dispute,2,3,\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+        ]);
+        let summary = run(&args).unwrap();
+
+        assert_eq!(summary.processed, 3);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.rejected, 1);
+
+        let entries = summary
+            .processor
+            .status_entries()
+            .map(|entry| (entry.client, entry.available, entry.held))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (ClientID::new(1), dec!(5.0), dec!(0)),
+                (ClientID::new(2), dec!(0), dec!(2.5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn run_empty_output_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\ninvalid,1,1,1.0\ndeposit,-1,2,1.0\ndeposit,1,3,\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            std::ffi::OsStr::new("--fail-on-empty-output"),
+            input.as_os_str(),
+        ]);
+        let summary = run(&args).unwrap();
+
+        assert!(args.fail_on_empty_output);
+        assert!(summary.is_empty());
+        assert_eq!(summary.skipped, 3);
+        assert_eq!(summary.processed, 0);
+    }
+
+    #[test]
+    fn run_interactive_test() {
+        let input = "deposit,1,1,5.0\nwithdrawal,1,2,10.0\ndeposit,2,3,2.5\ndispute,1,1,\n";
+        let args = Args::parse_from(["toy_stream_processor", "--interactive"]);
+
+        let mut output = Vec::new();
+        let summary = run_interactive(input.as_bytes(), &mut output, &args).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,5,0,5,false\n\
+             1,5,0,5,false\n\
+             2,2.5,0,2.5,false\n\
+             1,0,5,5,false\n"
+        );
+        assert_eq!(summary.processed, 3);
+        assert_eq!(summary.rejected, 1);
+    }
+
+    #[test]
+    fn run_missing_file_test() {
+        let args = Args::parse_from(["toy_stream_processor", "does/not/exist.csv"]);
+        assert!(matches!(run(&args), Err(RunError::OpenInput(_))));
+    }
+
+    #[test]
+    fn read_roster_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roster.txt");
+
+        std::fs::write(&path, "1\n\n 3 \n").unwrap();
+        let roster = read_roster(&path).unwrap();
+        assert_eq!(roster, HashSet::from([ClientID::new(1), ClientID::new(3)]));
+
+        std::fs::write(&path, "1\nclient 2\n").unwrap();
+        assert!(matches!(
+            read_roster(&path),
+            Err(RunError::InvalidRoster { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn run_input_dir_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        };
+        // written out of order, only the lexical order of the names counts
+        write("c.csv", "type,client,tx,amount\nwithdrawal,1,3,7.0\n");
+        write("a.csv", "type,client,tx,amount\ndeposit,1,1,10.0\n");
+        write("b.csv", "type,client,tx,amount\nwithdrawal,1,2,4.0\n");
+        write("notes.txt", "type,client,tx,amount\ndeposit,1,4,100.0\n");
+        std::fs::create_dir(dir.path().join("archive.csv")).unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            "--input-dir".as_ref(),
+            dir.path().as_os_str(),
+        ]);
+        let summary = run(&args).unwrap();
+        // the withdrawal in c.csv comes after both others and exceeds the balance
+        assert_eq!(
+            (summary.processed, summary.skipped, summary.rejected),
+            (2, 0, 1)
+        );
+        let entry = summary.processor.status_entry(ClientID::new(1)).unwrap();
+        assert_eq!(entry.total, dec!(6.0));
+    }
+
+    #[test]
+    fn run_input_dir_state_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let run_dir = |files: &[(&str, &str)], extra: &[&str]| {
+            for entry in std::fs::read_dir(dir.path()).unwrap() {
+                std::fs::remove_file(entry.unwrap().path()).unwrap();
+            }
+            for (name, content) in files {
+                std::fs::write(dir.path().join(name), content).unwrap();
+            }
+            let mut args = vec![
+                std::ffi::OsStr::new("toy_stream_processor"),
+                "--input-dir".as_ref(),
+                dir.path().as_os_str(),
+                "--strict".as_ref(),
+            ];
+            args.extend(extra.iter().map(std::ffi::OsStr::new));
+            run(&Args::parse_from(args)).unwrap()
+        };
+
+        // the composite ids continue in the second file, whose dispute matches the deposit of
+        // the first one, and its header isn't a mid-file header in strict mode
+        let summary = run_dir(
+            &[
+                ("a.csv", "type,client,tx,amount\ndeposit,1,7,5.0\n"),
+                (
+                    "b.csv",
+                    "type,client,tx,amount\ndeposit,1,7,2.0\ndispute,1,0,5.0\n",
+                ),
+            ],
+            &["--match-by", "composite"],
+        );
+        assert_eq!((summary.processed, summary.rejected), (3, 0));
+        let entry = summary.processor.status_entry(ClientID::new(1)).unwrap();
+        assert_eq!((entry.available, entry.held), (dec!(2.0), dec!(5.0)));
+
+        // two passes reorder a dispute before its deposit in a later file
+        let summary = run_dir(
+            &[
+                ("a.csv", "type,client,tx,amount\ndispute,1,1,\n"),
+                ("b.csv", "type,client,tx,amount\ndeposit,1,1,5.0\n"),
+            ],
+            &["--two-pass"],
+        );
+        assert_eq!((summary.processed, summary.rejected), (2, 0));
+        let entry = summary.processor.status_entry(ClientID::new(1)).unwrap();
+        assert_eq!(entry.held, dec!(5.0));
+    }
+
+    #[test]
+    fn run_parallel_files_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+        let first = write(
+            "first.csv",
+            "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,10.0\n",
+        );
+        let second = write(
+            "second.csv",
+            "type,client,tx,amount\ndeposit,2,1,2.0\ndeposit,3,2,3.0\n",
+        );
+        let overlapping = write(
+            "overlapping.csv",
+            "type,client,tx,amount\ndeposit,1,1,1.0\n",
+        );
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            "--parallel-files".as_ref(),
+            first.as_os_str(),
+            second.as_os_str(),
+        ]);
+        let summary = run(&args).unwrap();
+        assert_eq!((summary.processed, summary.rejected), (3, 1));
+        let clients: Vec<_> = summary
+            .processor
+            .status_entries()
+            .map(|entry| (entry.client, entry.total))
+            .collect();
+        assert_eq!(
+            clients,
+            vec![
+                (ClientID::new(1), dec!(5.0)),
+                (ClientID::new(2), dec!(2.0)),
+                (ClientID::new(3), dec!(3.0)),
+            ]
+        );
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            "--parallel-files".as_ref(),
+            first.as_os_str(),
+            overlapping.as_os_str(),
+        ]);
+        assert!(matches!(
+            run(&args),
+            Err(RunError::Merge(MergeError::OverlappingClient(client))) if client == ClientID::new(1)
+        ));
+    }
+
+    #[test]
+    fn validate_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,2.0\n\
+             dispute,2,2,\n",
+        )
+        .unwrap();
+        let snapshot = dir.path().join("snapshot.json");
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--snapshot-out".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        run(&args).unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            "--validate".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        assert_eq!(validate(&args).unwrap(), vec![]);
+
+        // corrupt the balances of both clients
+        let json = std::fs::read_to_string(&snapshot).unwrap();
+        let corrupted = json
+            .replace(r#""total":"5""#, r#""total":"6""#)
+            .replace(r#""held":"2""#, r#""held":"1""#);
+        assert_ne!(corrupted, json);
+        std::fs::write(&snapshot, corrupted).unwrap();
+
+        let issues: Vec<_> = validate(&args)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                "client 1: total 6 doesn't match the deposits and withdrawals 5",
+                "client 2: held 1 doesn't match the disputed deposits 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn run_delta_against_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("snapshot.json");
+        let prior = dir.path().join("prior.csv");
+        std::fs::write(
+            &prior,
+            "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\n",
+        )
+        .unwrap();
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            prior.as_os_str(),
+            "--snapshot-out".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        run(&args).unwrap();
+
+        // client 1 is unchanged by a deposit and withdrawal of the same amount
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,3,1.0\n\
+             withdrawal,1,4,1.0\n\
+             deposit,2,5,1.0\n\
+             deposit,3,6,1.0\n",
+        )
+        .unwrap();
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--snapshot-in".as_ref(),
+            snapshot.as_os_str(),
+            "--delta-against".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        let summary = run(&args).unwrap();
+
+        let clients: Vec<_> = summary
+            .status_entries(&args)
+            .into_iter()
+            .map(|entry| entry.client)
+            .collect();
+        assert_eq!(clients, vec![ClientID::new(2), ClientID::new(3)]);
+    }
+
+    #[test]
+    fn run_composite_snapshot_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("snapshot.json");
+        let prior = dir.path().join("prior.csv");
+        std::fs::write(
+            &prior,
+            "type,client,tx,amount
+deposit,1,1,5.0
+",
+        )
+        .unwrap();
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            prior.as_os_str(),
+            "--match-by".as_ref(),
+            "composite".as_ref(),
+            "--snapshot-out".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        run(&args).unwrap();
+
+        // the deposit would be assigned the id of the restored deposit without the snapshot
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount
+             withdrawal,1,2,1.0
+             deposit,1,1,2.0
+             dispute,1,0,2.0
+",
+        )
+        .unwrap();
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--match-by".as_ref(),
+            "composite".as_ref(),
+            "--snapshot-in".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        let summary = run(&args).unwrap();
+        assert_eq!((summary.processed, summary.rejected), (3, 0));
+        let entry = summary.processor.status_entry(ClientID::new(1)).unwrap();
+        assert_eq!((entry.total, entry.held), (dec!(6.0), dec!(2.0)));
+        assert!(summary
+            .processor
+            .has_transaction(ClientID::new(1), TransactionID::new(3)));
+    }
+
+    #[test]
+    fn run_transform_only_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n1,1,1,5.0\n2,1,2,1.5\n3,1,1,\n9,1,3,1.0\n",
+        )
+        .unwrap();
+        let transform_file = |path: &Path, extra: &[&str]| {
+            let args = Args::try_parse_from(
+                ["toy_stream_processor".as_ref(), path.as_os_str()]
+                    .into_iter()
+                    .chain(extra.iter().map(std::ffi::OsStr::new)),
+            )
+            .unwrap();
+            let mut output = Vec::new();
+            transform(&args, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // the type codes are decoded and the invalid row is dropped
+        let canonical = transform_file(
+            &input,
+            &["--transform-only", "--schema-comment", "--type-codes"],
+        );
+        assert_eq!(
+            canonical,
+            "# schema_version: 1\n\
+             type,client,tx,amount,reason,idempotency_key\n\
+             deposit,1,1,5,,\n\
+             withdrawal,1,2,1.5,,\n\
+             dispute,1,1,,,\n"
+        );
+
+        // the canonical output reads back unchanged
+        let transformed = dir.path().join("canonical.csv");
+        std::fs::write(&transformed, &canonical).unwrap();
+        assert_eq!(
+            transform_file(&transformed, &["--transform-only", "--schema-comment"]),
+            canonical
+        );
+
+        // a mismatching version is an error in strict mode and a warning otherwise
+        std::fs::write(
+            &transformed,
+            canonical.replace("schema_version: 1", "schema_version: 2"),
+        )
+        .unwrap();
+        let args = |strict: bool| {
+            let mut args = Args::parse_from([
+                std::ffi::OsStr::new("toy_stream_processor"),
+                transformed.as_os_str(),
+            ]);
+            args.strict = strict;
+            args
+        };
+        assert!(matches!(
+            run(&args(true)),
+            Err(RunError::Read(ReaderError::SchemaVersion {
+                found: 2,
+                expected: 1
+            }))
+        ));
+        let summary = run(&args(false)).unwrap();
+        let entry = summary.processor.status_entry(ClientID::new(1)).unwrap();
+        assert_eq!((entry.total, entry.held), (dec!(3.5), dec!(5.0)));
+    }
+
+    #[test]
+    fn input_buffer_size_test() {
+        let parse = |size: &str| {
+            Args::try_parse_from([
+                "toy_stream_processor",
+                "in.csv",
+                "--input-buffer-size",
+                size,
+            ])
+        };
+        assert_eq!(
+            parse("65536").unwrap().input_buffer_size,
+            NonZeroUsize::new(65536)
+        );
+        // an empty buffer can't hold a single byte of input
+        assert!(parse("0").is_err());
+    }
+
+    #[test]
+    fn anon_key_file_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("key");
+        std::fs::write(&key, "secret\n").unwrap();
+        let parse = |extra: &[&str]| {
+            Args::try_parse_from(
+                ["toy_stream_processor", "in.csv", "--anonymize-clients"]
+                    .into_iter()
+                    .map(std::ffi::OsStr::new)
+                    .chain(extra.iter().map(std::ffi::OsStr::new))
+                    .chain(["--anon-key-file".as_ref(), key.as_os_str()]),
+            )
+        };
+
+        // the line ending isn't part of the key, which takes precedence over `--anon-key`
+        for extra in [&[][..], &["--anon-key", "other"]] {
+            assert_eq!(
+                parse(extra).unwrap().output_config().anonymize_key,
+                Some("secret".to_string())
+            );
+        }
+
+        std::fs::write(&key, "\n").unwrap();
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn run_excel_export_test() {
+        // BOM, CRLF line endings and quoted fields must parse the same as a clean file
+        let run_fixture = |name: &str| {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("fixture")
+                .join(name);
+            let args = Args::parse_from([
+                std::ffi::OsStr::new("toy_stream_processor"),
+                path.as_os_str(),
+                "--strict-columns".as_ref(),
+            ]);
+            let summary = run(&args).unwrap();
+            assert_eq!(summary.skipped, 0, "{name}");
+            let entries = summary.status_entries(&args);
+            (summary.processed, summary.rejected, entries)
+        };
+
+        let excel = run_fixture("01_excel_transactions_in.csv");
+        assert_eq!(excel, run_fixture("01_clean_transactions_in.csv"));
+        assert_eq!(excel.0, 8);
+    }
+
+    #[test]
+    fn run_warning_histogram_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,\n\
+             deposit,1,2,1.0\n\
+             withdrawal,1,3,\n\
+             withdrawal,1,4,5.0\n\
+             bad,1,5,1.0\n\
+             dispute,1,9,\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--warning-histogram".as_ref(),
+        ]);
+        let summary = run(&args).unwrap();
+
+        assert_eq!(
+            summary.warning_histogram(),
+            "missing_amount: 2, deposit_not_found: 1, insufficient_funds: 1, invalid_record: 1"
+        );
+    }
+
+    #[test]
+    fn run_explain_client_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,1.0\n\
+             withdrawal,2,3,2.0\n\
+             withdrawal,1,4,2.0\n\
+             withdrawal,1,5,7.0\n\
+             dispute,2,2,\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--explain-client".as_ref(),
+            "1".as_ref(),
+        ]);
+        let summary = run(&args).unwrap();
+
+        assert_eq!(
+            summary.explanation,
+            vec![
+                "tx 1 deposit: applied, available 0 -> 5, held 0 -> 0, total 0 -> 5, locked false -> false",
+                "tx 4 withdrawal: applied, available 5 -> 3, held 0 -> 0, total 5 -> 3, locked false -> false",
+                "tx 5 withdrawal: rejected (insufficient funds), available 3 -> 3, held 0 -> 0, total 3 -> 3, locked false -> false",
+            ]
+        );
+        // the other clients are still processed
+        assert_eq!(summary.processed, 4);
+    }
+
+    #[test]
+    fn run_certificate_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        let certificate = dir.path().join("certificate.json");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,1.5\n\
+             dispute,2,2,\n\
+             withdrawal,1,3,9.0\n\
+             bad,1,4,1.0\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--certificate".as_ref(),
+            certificate.as_os_str(),
+        ]);
+        let summary = run(&args).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&certificate).unwrap()).unwrap();
+        assert_eq!(json["processed"], 3);
+        assert_eq!(json["skipped"], 1);
+        assert_eq!(json["rejected"], 1);
+        assert_eq!(json["balances"]["clients"], 2);
+        assert_eq!(json["balances"]["available"], "5");
+        assert_eq!(json["balances"]["held"], "1.5");
+        assert_eq!(json["balances"]["total"], "6.5");
+        assert_eq!(json["balances"]["locked"], 0);
+        assert_eq!(json["state_hash"], summary.processor.state_hash());
+    }
+
+    #[test]
+    fn run_replay_log_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        let replay_log = dir.path().join("replay.jsonl");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,1.5\n\
+             withdrawal,1,3,2.25\n\
+             dispute,2,2,\n\
+             withdrawal,2,4,1.0\n\
+             deposit,3,5,7.0\n\
+             dispute,3,5,\n\
+             chargeback,3,5,\n\
+             bad,1,6,1.0\n",
+        )
+        .unwrap();
+
+        let summary = run(&Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--replay-log-out".as_ref(),
+            replay_log.as_os_str(),
+        ]))
+        .unwrap();
+        // only the applied transactions are logged
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(
+            std::fs::read_to_string(&replay_log)
+                .unwrap()
+                .lines()
+                .count(),
+            summary.processed
+        );
+
+        let replayed = run(&Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            replay_log.as_os_str(),
+            "--input-format".as_ref(),
+            "replay-log".as_ref(),
+        ]))
+        .unwrap();
+        assert_eq!(replayed.processed, summary.processed);
+        assert_eq!((replayed.skipped, replayed.rejected), (0, 0));
+        assert_eq!(replayed.processor.snapshot(), summary.processor.snapshot());
+    }
+
+    #[test]
+    fn run_two_pass_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             dispute,1,2,\n\
+             deposit,1,1,5.0\n\
+             chargeback,1,2,\n\
+             deposit,1,2,3.0\n\
+             deposit,2,3,1.0\n\
+             dispute,2,4,\n",
+        )
+        .unwrap();
+        let run_with = |extra: &[&str]| {
+            let mut args = vec![
+                std::ffi::OsStr::new("toy_stream_processor"),
+                input.as_os_str(),
+            ];
+            args.extend(extra.iter().map(std::ffi::OsStr::new));
+            run(&Args::parse_from(args)).unwrap()
+        };
+
+        // single pass, the dispute and the chargeback come before their deposit
+        let summary = run_with(&[]);
+        assert_eq!((summary.processed, summary.rejected), (3, 3));
+        let entry = summary.processor.status_entry(ClientID::new(1)).unwrap();
+        assert_eq!((entry.total, entry.locked), (dec!(8), false));
+
+        // two passes, only the dispute of the missing deposit 4 fails
+        let summary = run_with(&["--two-pass"]);
+        assert_eq!((summary.processed, summary.rejected), (5, 1));
+        let entry = summary.processor.status_entry(ClientID::new(1)).unwrap();
+        assert_eq!(
+            (entry.total, entry.held, entry.locked),
+            (dec!(5), dec!(0), true)
+        );
+    }
+
+    #[test]
+    fn run_metadata_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        let metadata = dir.path().join("metadata.json");
+        let content = "type,client,tx,amount\n\
+                       deposit,1,1,5.0\n\
+                       withdrawal,1,2,9.0\n\
+                       bad,1,3,1.0\n";
+        std::fs::write(&input, content).unwrap();
+
+        run(&Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--metadata-out".as_ref(),
+            metadata.as_os_str(),
+            "--filter".as_ref(),
+            "type=deposit || type=withdrawal".as_ref(),
+        ]))
+        .unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&metadata).unwrap()).unwrap();
+        let keys: Vec<_> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        for key in [
+            "tool_version",
+            "inputs",
+            "processed",
+            "skipped",
+            "rejected",
+            "reasons",
+            "clients",
+            "started_at_unix_ms",
+            "finished_at_unix_ms",
+            "config",
+        ] {
+            assert!(keys.contains(&key), "missing {key}");
+        }
+        assert_eq!(json["tool_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["inputs"][0]["path"], input.to_str().unwrap());
+        assert_eq!(json["inputs"][0]["size"], content.len());
+        assert_eq!(
+            (&json["processed"], &json["skipped"], &json["rejected"]),
+            (&1.into(), &1.into(), &1.into())
+        );
+        assert_eq!(json["reasons"]["insufficient_funds"], 1);
+        assert!(json["started_at_unix_ms"].as_u64() <= json["finished_at_unix_ms"].as_u64());
+        // the effective config includes the defaults
+        assert_eq!(json["config"]["output_format"], "csv");
+        assert_eq!(json["config"]["output_scale"], 4);
+        assert_eq!(json["config"]["filter"], "type=deposit || type=withdrawal");
+    }
+
+    #[test]
+    fn run_halt_on_lock_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        let output = dir.path().join("output.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,3.0\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,2,3,1.0\n\
+             withdrawal,2,4,2.0\n",
+        )
+        .unwrap();
+
+        let summary = run(&Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--halt-on-lock".as_ref(),
+            "--csv-out".as_ref(),
+            output.as_os_str(),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            summary.halted_on_lock,
+            Some((ClientID::new(1), TransactionID::new(1)))
+        );
+        assert_eq!(summary.processed, 4);
+        // the state up to the chargeback is written, the transactions after it aren't applied
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,0,0,0,true\n\
+             2,3,0,3,false\n"
+        );
+    }
+
+    #[test]
+    fn eligible_disputes_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,2.0\n\
+             deposit,1,3,1.0\n\
+             withdrawal,1,4,1.0\n\
+             dispute,1,1,\n\
+             deposit,3,5,1.0\n\
+             dispute,3,5,\n\
+             chargeback,3,5,\n",
+        )
+        .unwrap();
+        let snapshot = dir.path().join("snapshot.json");
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--snapshot-out".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        run(&args).unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            "--eligible-disputes".as_ref(),
+            snapshot.as_os_str(),
+        ]);
+        let eligible: Vec<_> = eligible_disputes(&args)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(eligible, vec!["client 1: 3", "client 2: 2"]);
+    }
+
+    #[test]
+    fn run_max_dispute_ratio_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("transactions.csv");
+        // client 1 disputed 1 of 4 deposits, twice, client 2 both of its deposits
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,1,2,1.0\n\
+             deposit,1,3,1.0\n\
+             deposit,1,4,1.0\n\
+             dispute,1,1,\n\
+             resolve,1,1,\n\
+             dispute,1,1,\n\
+             deposit,2,5,1.0\n\
+             deposit,2,6,1.0\n\
+             dispute,2,5,\n\
+             resolve,2,5,\n\
+             dispute,2,6,\n\
+             chargeback,2,6,\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            std::ffi::OsStr::new("toy_stream_processor"),
+            input.as_os_str(),
+            "--max-dispute-ratio".as_ref(),
+            "0.5".as_ref(),
+        ]);
+        let summary = run(&args).unwrap();
+
+        let alerts = summary.processor.dispute_ratio_alerts(0.5);
+        assert_eq!(
+            alerts,
+            vec![DisputeRatioAlert {
+                client: ClientID::new(2),
+                disputed: 2,
+                deposits: 2
+            }]
+        );
+        let flags: Vec<_> = summary
+            .status_entries(&args)
+            .into_iter()
+            .map(|entry| (entry.client, entry.dispute_ratio_exceeded))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                (ClientID::new(1), Some(false)),
+                (ClientID::new(2), Some(true))
+            ]
+        );
+
+        // a ratio of exactly the maximum is fine
+        assert_eq!(summary.processor.dispute_ratio_alerts(0.25).len(), 1);
+        assert_eq!(summary.processor.dispute_ratio_alerts(0.2).len(), 2);
+    }
+}
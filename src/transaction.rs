@@ -1,11 +1,11 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     ids::{ClientID, TransactionID},
     positive_decimal::PositiveDecimal,
 };
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Transaction {
     Deposit(Deposit),
@@ -13,9 +13,35 @@ pub enum Transaction {
     Dispute(Dispute),
     Resolve(Resolve),
     Chargeback(Chargeback),
+    Release(Release),
+    Open(Open),
 }
 
 impl Transaction {
+    /// Names of all transaction types, as used in the type column.
+    pub const TYPE_NAMES: [&'static str; 7] = [
+        "deposit",
+        "withdrawal",
+        "dispute",
+        "resolve",
+        "chargeback",
+        "release",
+        "open",
+    ];
+
+    /// Returns the name of the transaction type, as used in the type column.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Transaction::Deposit(_) => "deposit",
+            Transaction::Withdrawal(_) => "withdrawal",
+            Transaction::Dispute(_) => "dispute",
+            Transaction::Resolve(_) => "resolve",
+            Transaction::Chargeback(_) => "chargeback",
+            Transaction::Release(_) => "release",
+            Transaction::Open(_) => "open",
+        }
+    }
+
     pub fn client(&self) -> ClientID {
         match self {
             Transaction::Deposit(deposit) => deposit.client,
@@ -23,38 +49,74 @@ impl Transaction {
             Transaction::Dispute(dispute) => dispute.client,
             Transaction::Resolve(resolve) => resolve.client,
             Transaction::Chargeback(chargeback) => chargeback.client,
+            Transaction::Release(release) => release.client,
+            Transaction::Open(open) => open.client,
+        }
+    }
+
+    /// Returns the tx id of the transaction, for disputes, resolves and chargebacks the id of the
+    /// deposit they refer to.
+    pub fn tx(&self) -> TransactionID {
+        match self {
+            Transaction::Deposit(deposit) => deposit.tx,
+            Transaction::Withdrawal(withdrawal) => withdrawal.tx,
+            Transaction::Dispute(dispute) => dispute.tx,
+            Transaction::Resolve(resolve) => resolve.tx,
+            Transaction::Chargeback(chargeback) => chargeback.tx,
+            Transaction::Release(release) => release.tx,
+            Transaction::Open(open) => open.tx,
         }
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct Deposit {
     pub client: ClientID,
     pub tx: TransactionID,
     pub amount: PositiveDecimal,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct Withdrawal {
     pub client: ClientID,
     pub tx: TransactionID,
     pub amount: PositiveDecimal,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct Dispute {
     pub client: ClientID,
     pub tx: TransactionID,
+    /// Amount of a partial dispute, only used when partial disputes are enabled.
+    pub amount: Option<PositiveDecimal>,
+    /// Optional reason code given by the dispute notification, e.g. `fraud` or `duplicate`.
+    pub reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct Resolve {
     pub client: ClientID,
     pub tx: TransactionID,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct Chargeback {
     pub client: ClientID,
     pub tx: TransactionID,
 }
+
+/// Administrative operation moving held funds back to available without a matching resolve.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Release {
+    pub client: ClientID,
+    pub tx: TransactionID,
+    pub amount: PositiveDecimal,
+}
+
+/// Explicitly opens the account of a client, required before any other transaction when running
+/// with `--deposit-requires-open-account`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Open {
+    pub client: ClientID,
+    pub tx: TransactionID,
+}